@@ -0,0 +1,29 @@
+// Sentryのrelease (`rust-web-tools-test@0.1.0+<sha>`) に使うgitのコミットを埋め込む
+// .gitがないとき (Dockerのビルドなど) はGIT_SHAで指定する。どちらもなければunknown
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=GIT_SHA");
+    for path in [".git/HEAD", ".git/refs/heads"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={path}");
+        }
+    }
+
+    let sha = std::env::var("GIT_SHA")
+        .ok()
+        .filter(|x| !x.is_empty())
+        .or_else(|| {
+            let output = Command::new("git")
+                .args(["rev-parse", "--short=12", "HEAD"])
+                .output()
+                .ok()?;
+            output
+                .status
+                .success()
+                .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        })
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=GIT_SHA={sha}");
+}
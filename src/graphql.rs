@@ -1,11 +1,14 @@
 use anyhow::Result;
 use async_graphql::{Context, EmptySubscription, MergedObject, Object, SDLExportOptions, Schema};
 
+use super::tools::default_deny::public;
+
 #[derive(Default)]
 pub struct QueryRoot;
 
 #[Object]
 impl QueryRoot {
+    #[graphql(directive = public::apply())]
     async fn say_hello(&self, _ctx: &Context<'_>) -> Result<&str> {
         Ok("hello")
     }
@@ -16,6 +19,7 @@ pub struct MutationRoot;
 
 #[Object]
 impl MutationRoot {
+    #[graphql(directive = public::apply())]
     async fn set_hello(&self, _ctx: &Context<'_>) -> Result<&str> {
         Ok("hello")
     }
@@ -27,14 +31,14 @@ pub struct Query(QueryRoot);
 #[derive(MergedObject, Default)]
 pub struct Mutation(MutationRoot);
 
+#[cfg(all(feature = "with-axum", feature = "with-sea-orm"))]
 pub type AppSchema = Schema<Query, Mutation, EmptySubscription>;
 
 pub fn build() -> async_graphql::SchemaBuilder<Query, Mutation, EmptySubscription> {
     Schema::build(Query::default(), Mutation::default(), EmptySubscription)
 }
 
-#[allow(dead_code)]
 pub fn export_sdl() -> String {
     let schema = build().enable_federation().finish();
-    schema.sdl_with_options(SDLExportOptions::new())
+    schema.sdl_with_options(SDLExportOptions::new().federation())
 }
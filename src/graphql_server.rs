@@ -1,28 +1,120 @@
 use async_graphql::http::GraphiQLSource;
-use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
-use axum::{response, Extension};
-use axum::{response::IntoResponse, routing::get, Router};
+use axum::{
+    extract::Request,
+    handler::Handler,
+    http::{Method, Uri},
+    response, Extension,
+};
+use axum::{
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
 #[cfg(feature = "with-opentelemetry")]
-use opentelemetry::trace::FutureExt;
+use opentelemetry::trace::{FutureExt, TraceContextExt, Tracer};
 
 use super::graphql;
+#[cfg(feature = "with-sentry")]
+use super::tools::async_graphql_sentry_extension::SentryTraceHeaders;
+#[cfg(feature = "with-auth")]
+use super::tools::auth::{AccessToken, AuthError, JwtValidator};
+use super::tools::{
+    db::Database,
+    default_deny,
+    graphql_http::{GraphQLHttpConfig, GraphQLHttpRequest},
+    request_id::{self, RequestId},
+    server,
+};
 #[cfg(feature = "with-opentelemetry")]
-use super::tools::parent_trace_context::ParentTraceContext;
-#[cfg(all(feature = "with-opentelemetry", feature = "with-sentry"))]
-use super::tools::setup_tracing;
-use super::tools::{db::Database, server};
+use super::tools::{http_trace, metrics, parent_trace_context::ParentTraceContext};
+use super::tools::{log_filter, setup_tracing, tracing_config::TracingConfig};
 
+// graphqlのspanを作るtracer。なければグローバルのものを使う
+#[cfg(feature = "with-opentelemetry")]
+#[derive(Clone)]
+pub struct GraphQLTracer(pub std::sync::Arc<opentelemetry::global::BoxedTracer>);
+
+#[allow(clippy::too_many_arguments)]
 async fn graphql_handler(
     schema: Extension<graphql::AppSchema>,
     #[cfg(feature = "with-opentelemetry")] parent_trace_context: ParentTraceContext,
-    req: GraphQLRequest,
-) -> GraphQLResponse {
-    let schema = schema.execute(req.into_inner());
+    #[cfg(feature = "with-opentelemetry")] tracer: Option<Extension<GraphQLTracer>>,
+    #[cfg(feature = "with-auth")] token: Result<AccessToken, AuthError>,
+    #[cfg(feature = "with-sentry")] sentry_trace: SentryTraceHeaders,
+    request_id: RequestId,
+    Extension(config): Extension<GraphQLHttpConfig>,
+    method: Method,
+    #[allow(unused_mut)] mut req: GraphQLHttpRequest,
+) -> Response {
+    // サンプラーのルールでoperation名を見られるように、開始時に属性として渡す
+    // HttpTraceLayerの中ならそのspanの子に、なければヘッダーの親から始める
+    // (RuleSamplerはサンプルしなかったHTTPのspanの直下でもルールを評価する)
+    #[cfg(feature = "with-opentelemetry")]
+    let cx = {
+        let current_cx = opentelemetry::Context::current();
+        let (parent_cx, kind) = if current_cx.has_active_span() {
+            (current_cx, opentelemetry::trace::SpanKind::Internal)
+        } else {
+            (
+                parent_trace_context.get(),
+                opentelemetry::trace::SpanKind::Server,
+            )
+        };
+        let tracer = match tracer {
+            Some(Extension(GraphQLTracer(tracer))) => tracer,
+            None => std::sync::Arc::new(opentelemetry::global::tracer("graphql")),
+        };
+        let span = tracer
+            .span_builder("graphql")
+            .with_kind(kind)
+            .with_attributes([opentelemetry::KeyValue::new(
+                "graphql.operation.name",
+                opentelemetry::Value::Array(
+                    req.operation_names()
+                        .into_iter()
+                        .map(opentelemetry::StringValue::from)
+                        .collect::<Vec<_>>()
+                        .into(),
+                ),
+            )])
+            .start_with_context(tracer.as_ref(), &parent_cx);
+        parent_cx.with_span(span)
+    };
+
+    let req = req.into_inner().data(request_id);
+
+    #[cfg(feature = "with-opentelemetry")]
+    let req = req.data(parent_trace_context.baggage().clone());
+
+    #[cfg(feature = "with-auth")]
+    let req = req.data(token);
+
+    #[cfg(feature = "with-sentry")]
+    let req = req.data(sentry_trace);
+
+    let schema = schema.execute_batch(req);
 
     #[cfg(feature = "with-opentelemetry")]
-    let schema = schema.with_context(parent_trace_context.get());
+    let schema = schema.with_context(cx.clone());
 
-    schema.await.into()
+    let resp = schema.await;
+
+    #[cfg(feature = "with-opentelemetry")]
+    if !resp.is_ok() {
+        cx.span()
+            .set_status(opentelemetry::trace::Status::error("graphql error"));
+    }
+
+    config.respond(&method, resp)
+}
+
+// クエリ文字列なしのGETはGraphiQL、ありならクエリとして実行する
+async fn graphql_get_handler(uri: Uri, req: Request) -> Response {
+    if uri.query().is_none() {
+        graphiql().await.into_response()
+    } else {
+        Handler::call(graphql_handler, req, ()).await
+    }
 }
 
 #[cfg(debug_assertions)]
@@ -36,32 +128,150 @@ async fn graphiql() -> impl IntoResponse {
     StatusCode::BAD_REQUEST
 }
 
+// GraphQLのエンドポイント。認証やトレースなどのlayerはmainで足す
+fn graphql_router(schema: graphql::AppSchema, config: GraphQLHttpConfig) -> Router {
+    Router::new()
+        .route("/", get(graphql_get_handler).post(graphql_handler))
+        .layer(Extension(schema))
+        .layer(Extension(config))
+}
+
 pub async fn main() -> anyhow::Result<()> {
-    #[cfg(all(feature = "with-opentelemetry", feature = "with-sentry"))]
-    let guard = setup_tracing::setup()?;
+    let guard = setup_tracing::setup(TracingConfig::load()?)?;
+
+    let database = Database::new_from_env().await?;
+    #[cfg(feature = "with-opentelemetry")]
+    database.loader().register_pool_metrics();
 
     let schema_builder = graphql::build()
-        .data(async_graphql::dataloader::DataLoader::new(
-            Database::new_from_env().await?,
-            tokio::task::spawn,
-        ))
+        .data(database)
         .enable_federation()
         .extension(async_graphql::extensions::Logger);
 
-    #[cfg(all(feature = "with-opentelemetry", feature = "with-sentry"))]
     let schema_builder = guard.add_extension(schema_builder);
-    let schema = schema_builder.finish();
+    let schema = default_deny::finish_default_deny(schema_builder)?;
 
     let cors = tower_http::cors::CorsLayer::new()
         .allow_credentials(false)
         .allow_headers(tower_http::cors::Any)
         .allow_origin(tower_http::cors::AllowOrigin::mirror_request());
-    let router = Router::new()
-        .route("/", get(graphiql).post(graphql_handler))
-        .layer(Extension(schema))
+    let router = graphql_router(schema, GraphQLHttpConfig::new_from_env()?);
+
+    #[cfg(feature = "with-auth")]
+    let router = if let Some(validator) = JwtValidator::new_from_env().await? {
+        router.layer(Extension(std::sync::Arc::new(validator)))
+    } else {
+        router
+    };
+
+    #[cfg(feature = "with-opentelemetry")]
+    let router = router.layer(axum::middleware::from_fn_with_state(
+        metrics::HttpMetrics::new(),
+        metrics::track_http,
+    ));
+
+    // ADMIN_TOKENが設定されていれば管理用のエンドポイント (/admin/log-filter, /metrics) を有効にする
+    let router = match std::env::var("ADMIN_TOKEN").ok().filter(|x| !x.is_empty()) {
+        Some(token) => {
+            let admin = log_filter::router(guard.log_filter(), &token);
+            #[cfg(feature = "with-opentelemetry")]
+            let admin = match guard.prometheus_exporter() {
+                Some(exporter) => admin.merge(log_filter::require_token(
+                    Router::new().route(
+                        "/metrics",
+                        get(metrics::prometheus_handler).layer(Extension(exporter)),
+                    ),
+                    &token,
+                )),
+                None => admin,
+            };
+            router.merge(admin)
+        }
+        None => {
+            #[cfg(feature = "with-opentelemetry")]
+            if guard.prometheus_exporter().is_some() {
+                tracing::warn!("ADMIN_TOKEN is not set, /metrics is disabled");
+            }
+            router
+        }
+    };
+
+    #[cfg(feature = "with-opentelemetry")]
+    let router = router.layer(http_trace::HttpTraceLayer::new());
+
+    let router = router
+        .layer(axum::middleware::from_fn(request_id::middleware))
         .layer(cors);
 
     server::run(router, Some(8000)).await?;
 
     Ok(())
 }
+
+#[cfg(all(test, feature = "with-opentelemetry"))]
+mod tests {
+    use super::*;
+    use crate::tools::{
+        otel_setup::build_tracer_provider,
+        otlp_collector::OtlpCollector,
+        sampler::SamplingRule,
+        tracing_config::{ExporterConfig, Protocol, SamplerConfig},
+    };
+    use axum::{body::Body, http::header};
+    use opentelemetry::{global::BoxedTracer, trace::TracerProvider as _};
+    use tower::ServiceExt;
+
+    fn request(operation_name: &str) -> anyhow::Result<axum::http::Request<Body>> {
+        let body = serde_json::json!({ "query": format!("query {operation_name} {{ sayHello }}") });
+        Ok(axum::http::Request::post("/")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))?)
+    }
+
+    // HttpTraceLayerのspanがルートでも、operation名のルールでサンプルされる
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_operation_name_sampling() -> anyhow::Result<()> {
+        let collector = OtlpCollector::start().await?;
+        let config = TracingConfig {
+            exporter: Some(ExporterConfig {
+                protocol: Protocol::HttpJson,
+                ..ExporterConfig::new(collector.endpoint())
+            }),
+            sampler: SamplerConfig::RuleBased {
+                rules: SamplingRule::parse_rules("graphql.operation.name=CreateOrder:1;*:0")?,
+                record_errors: false,
+            },
+            ..Default::default()
+        };
+        let provider = build_tracer_provider(&config)?.unwrap();
+        let tracer = |name| BoxedTracer::new(Box::new(provider.tracer(name)));
+
+        let router = graphql_router(graphql::build().finish(), GraphQLHttpConfig::default())
+            .layer(Extension(GraphQLTracer(std::sync::Arc::new(tracer(
+                "graphql",
+            )))))
+            .layer(http_trace::HttpTraceLayer::from_tracer(tracer("http")));
+        for operation_name in ["ListOrders", "CreateOrder"] {
+            let resp = router.clone().oneshot(request(operation_name)?).await?;
+            assert!(resp.status().is_success());
+        }
+
+        let provider = tokio::task::spawn_blocking(move || {
+            assert!(provider.force_flush().iter().all(|x| x.is_ok()));
+            provider
+        })
+        .await?;
+
+        let spans = collector.spans();
+        let mut names: Vec<_> = spans.iter().map(|x| x["name"].as_str().unwrap()).collect();
+        names.sort();
+        assert_eq!(names, vec!["POST /", "graphql"]);
+        let http = spans.iter().find(|x| x["name"] == "POST /").unwrap();
+        let graphql = spans.iter().find(|x| x["name"] == "graphql").unwrap();
+        assert_eq!(graphql["traceId"], http["traceId"]);
+        assert_eq!(graphql["parentSpanId"], http["spanId"]);
+
+        tokio::task::spawn_blocking(move || provider.shutdown()).await??;
+        Ok(())
+    }
+}
@@ -1,5 +1,13 @@
+#[cfg(feature = "with-graphql")]
 mod graphql;
+#[cfg(all(
+    feature = "with-graphql",
+    feature = "with-axum",
+    feature = "with-sea-orm"
+))]
 pub mod graphql_server;
+#[cfg(feature = "with-graphql")]
+pub mod schema_command;
 
 #[allow(dead_code)]
 mod tools;
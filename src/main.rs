@@ -1,6 +1,31 @@
+#[cfg(all(
+    feature = "with-graphql",
+    feature = "with-axum",
+    feature = "with-sea-orm"
+))]
 use rust_web_tools_test::graphql_server;
+#[cfg(feature = "with-graphql")]
+use rust_web_tools_test::schema_command;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    graphql_server::main().await
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(|x| x.as_str()) {
+        #[cfg(feature = "with-graphql")]
+        Some("schema") => schema_command::main(&args[1..]),
+        #[cfg(all(
+            feature = "with-graphql",
+            feature = "with-axum",
+            feature = "with-sea-orm"
+        ))]
+        _ => graphql_server::main().await,
+        #[cfg(not(all(
+            feature = "with-graphql",
+            feature = "with-axum",
+            feature = "with-sea-orm"
+        )))]
+        _ => anyhow::bail!(
+            "the server requires the with-graphql, with-axum and with-sea-orm features"
+        ),
+    }
 }
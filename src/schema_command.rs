@@ -0,0 +1,36 @@
+use super::graphql;
+use super::tools::schema_diff::{self, Criticality};
+
+const USAGE: &str = "usage: schema print [output.graphql] | schema check <old.graphql>";
+
+pub fn main(args: &[String]) -> anyhow::Result<()> {
+    match args
+        .iter()
+        .map(|x| x.as_str())
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["print"] => {
+            print!("{}", graphql::export_sdl());
+            Ok(())
+        }
+        ["print", path] => Ok(std::fs::write(path, graphql::export_sdl())?),
+        ["check", path] => {
+            let changes =
+                schema_diff::diff(&std::fs::read_to_string(path)?, &graphql::export_sdl())?;
+            for change in &changes {
+                println!("{}", change);
+            }
+            let breaking = changes
+                .iter()
+                .filter(|x| x.criticality == Criticality::Breaking)
+                .count();
+            if breaking > 0 {
+                Err(anyhow::anyhow!("{breaking} breaking change(s) detected"))
+            } else {
+                Ok(())
+            }
+        }
+        _ => Err(anyhow::anyhow!(USAGE)),
+    }
+}
@@ -0,0 +1,262 @@
+// GraphQLのoperation/fieldごとの実行時間とエラー数を記録する
+// fieldはintrospectionを除いて `親の型.フィールド名` で集計する (パスだとリストの添字で系列が増えるので)
+// operation名はクライアントが自由に付けられるので、with_operation_namesで指定したもの以外は "other" にする
+//
+// ```ignore
+// schema_builder.extension(Metrics::new().with_operation_names(["GetUser", "CreateOrder"]))
+// ```
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use async_graphql::{
+    extensions::{
+        Extension, ExtensionContext, ExtensionFactory, NextExecute, NextParseQuery, NextResolve,
+        ResolveInfo,
+    },
+    parser::types::{DocumentOperations, ExecutableDocument, OperationType},
+    Response, ServerError, ServerResult, Value, Variables,
+};
+use opentelemetry::{
+    metrics::{Counter, Histogram, Meter},
+    KeyValue,
+};
+
+use super::metrics::METER_NAME;
+
+struct Instruments {
+    operation_duration: Histogram<f64>,
+    operation_errors: Counter<u64>,
+    field_duration: Histogram<f64>,
+    field_errors: Counter<u64>,
+}
+
+const OTHER_OPERATION: &str = "other";
+
+#[derive(Clone)]
+pub struct Metrics {
+    instruments: Arc<Instruments>,
+    operation_names: Arc<HashSet<String>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::from_meter(&opentelemetry::global::meter(METER_NAME))
+    }
+
+    pub fn from_meter(meter: &Meter) -> Self {
+        Self {
+            instruments: Arc::new(Instruments {
+                operation_duration: meter
+                    .f64_histogram("graphql.operation.duration")
+                    .with_unit("s")
+                    .init(),
+                operation_errors: meter.u64_counter("graphql.operation.errors").init(),
+                field_duration: meter
+                    .f64_histogram("graphql.field.duration")
+                    .with_unit("s")
+                    .init(),
+                field_errors: meter.u64_counter("graphql.field.errors").init(),
+            }),
+            operation_names: Arc::default(),
+        }
+    }
+
+    pub fn with_operation_names(
+        mut self,
+        names: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.operation_names = Arc::new(names.into_iter().map(Into::into).collect());
+        self
+    }
+}
+
+impl ExtensionFactory for Metrics {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(MetricsExtension {
+            instruments: self.instruments.clone(),
+            operation_names: self.operation_names.clone(),
+            operations: Mutex::default(),
+        })
+    }
+}
+
+struct MetricsExtension {
+    instruments: Arc<Instruments>,
+    operation_names: Arc<HashSet<String>>,
+    // ドキュメント中のoperationの名前と種類。どれを実行するかはexecuteのoperation_nameで決まる
+    operations: Mutex<Vec<(Option<String>, OperationType)>>,
+}
+
+impl MetricsExtension {
+    fn operation_type(&self, operation_name: Option<&str>) -> Option<OperationType> {
+        let operations = self.operations.lock().unwrap();
+        match (operation_name, operations.as_slice()) {
+            (Some(name), operations) => operations
+                .iter()
+                .find(|(x, _)| x.as_deref() == Some(name))
+                .map(|(_, ty)| *ty),
+            (None, [(_, ty)]) => Some(*ty),
+            // 複数あってoperationNameがなければ実行時にエラーになる
+            (None, _) => None,
+        }
+    }
+}
+
+fn operation_type_name(ty: Option<OperationType>) -> &'static str {
+    match ty {
+        Some(OperationType::Query) => "query",
+        Some(OperationType::Mutation) => "mutation",
+        Some(OperationType::Subscription) => "subscription",
+        None => "unknown",
+    }
+}
+
+#[async_graphql::async_trait::async_trait]
+impl Extension for MetricsExtension {
+    async fn parse_query(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        query: &str,
+        variables: &Variables,
+        next: NextParseQuery<'_>,
+    ) -> ServerResult<ExecutableDocument> {
+        let document = next.run(ctx, query, variables).await?;
+        *self.operations.lock().unwrap() = match &document.operations {
+            DocumentOperations::Single(operation) => vec![(None, operation.node.ty)],
+            DocumentOperations::Multiple(operations) => operations
+                .iter()
+                .map(|(name, operation)| (Some(name.to_string()), operation.node.ty))
+                .collect(),
+        };
+        Ok(document)
+    }
+
+    async fn execute(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        operation_name: Option<&str>,
+        next: NextExecute<'_>,
+    ) -> Response {
+        let start = Instant::now();
+        let resp = next.run(ctx, operation_name).await;
+
+        let attributes = [
+            KeyValue::new(
+                "graphql.operation.name",
+                match operation_name {
+                    Some(name) if self.operation_names.contains(name) => name.to_string(),
+                    _ => OTHER_OPERATION.to_string(),
+                },
+            ),
+            KeyValue::new(
+                "graphql.operation.type",
+                operation_type_name(self.operation_type(operation_name)),
+            ),
+        ];
+        self.instruments
+            .operation_duration
+            .record(start.elapsed().as_secs_f64(), &attributes);
+        if resp.is_err() {
+            self.instruments
+                .operation_errors
+                .add(resp.errors.len() as u64, &attributes);
+        }
+        resp
+    }
+
+    async fn resolve(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        info: ResolveInfo<'_>,
+        next: NextResolve<'_>,
+    ) -> Result<Option<Value>, ServerError> {
+        if info.is_for_introspection {
+            return next.run(ctx, info).await;
+        }
+        let attributes = [KeyValue::new(
+            "graphql.field.name",
+            format!("{}.{}", info.parent_type, info.name),
+        )];
+        let start = Instant::now();
+        let resp = next.run(ctx, info).await;
+        self.instruments
+            .field_duration
+            .record(start.elapsed().as_secs_f64(), &attributes);
+        if resp.is_err() {
+            self.instruments.field_errors.add(1, &attributes);
+        }
+        resp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::metrics::PrometheusExporter;
+    use async_graphql::{EmptyMutation, EmptySubscription, Object, Schema};
+    use opentelemetry::metrics::MeterProvider;
+    use opentelemetry_sdk::metrics::SdkMeterProvider;
+
+    struct Query;
+
+    #[Object]
+    impl Query {
+        async fn ok(&self) -> i32 {
+            1
+        }
+
+        async fn fail(&self) -> async_graphql::Result<i32> {
+            Err("boom".into())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_metrics() -> anyhow::Result<()> {
+        let exporter = PrometheusExporter::new();
+        let provider = SdkMeterProvider::builder()
+            .with_reader(exporter.clone())
+            .build();
+        let schema = Schema::build(Query, EmptyMutation, EmptySubscription)
+            .extension(Metrics::from_meter(&provider.meter("test")).with_operation_names(["A"]))
+            .finish();
+
+        let resp = schema.execute("query A { ok fail }").await;
+        assert_eq!(resp.errors.len(), 1);
+        for query in ["query B { ok }", "query C { ok }", "{ ok }"] {
+            assert!(schema.execute(query).await.is_ok());
+        }
+        // 複数のoperationがあってもoperationNameで選んだものの種類になる (unknownにならない)
+        let resp = schema
+            .execute(
+                async_graphql::Request::new("query A { ok } query Z { ok }").operation_name("A"),
+            )
+            .await;
+        assert!(resp.is_ok(), "{:?}", resp.errors);
+
+        let body = exporter.render()?;
+        for line in [
+            r#"graphql_operation_duration_seconds_count{graphql_operation_name="A",graphql_operation_type="query"} 2"#,
+            r#"graphql_operation_errors_total{graphql_operation_name="A",graphql_operation_type="query"} 1"#,
+            r#"graphql_operation_duration_seconds_count{graphql_operation_name="other",graphql_operation_type="query"} 3"#,
+            r#"graphql_field_duration_seconds_count{graphql_field_name="Query.ok"} 5"#,
+            r#"graphql_field_errors_total{graphql_field_name="Query.fail"} 1"#,
+        ] {
+            assert!(body.contains(line), "{line} not found in\n{body}");
+        }
+        assert!(!body.contains(r#"graphql_operation_name="B""#), "{body}");
+        assert!(
+            !body.contains(r#"graphql_operation_type="unknown""#),
+            "{body}"
+        );
+        Ok(())
+    }
+}
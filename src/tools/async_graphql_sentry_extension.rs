@@ -1,30 +1,443 @@
-use std::{fmt::Write, sync::Arc};
+// GraphQLのエラーとtransactionをSentryに送る
+// エラーはサーバー側のもの (ErrorCodeがないかINTERNAL_SERVER_ERROR) を1つずつイベントにする
+// resolverがanyhow::Errorを返していれば原因の連鎖とbacktraceも付ける
+// 同時に処理しているリクエストのscopeが混ざらないように、リクエストごとにHubを分けてその中で実行する
+//
+// transaction (traces_sample_rateが0より大きいとき)
+// - 名前は `query GetUser` のようにoperationの種類と名前
+// - sentry-trace/baggageヘッダー (SentryTraceHeadersをdataに入れる) があればその続きにする
+// - parse、validation、slow_resolverより遅いresolver、DBのクエリ (db::TracedConnection) をspanにする
+use std::{
+    any::Any,
+    borrow::Cow,
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use async_graphql::{
-    extensions::{Extension, ExtensionContext, ExtensionFactory, NextExecute},
-    PathSegment, Response,
+    extensions::{
+        Extension, ExtensionContext, ExtensionFactory, NextExecute, NextParseQuery,
+        NextPrepareRequest, NextRequest, NextResolve, NextValidation, ResolveInfo,
+    },
+    parser::types::{DocumentOperations, ExecutableDocument, OperationType},
+    PathSegment, Request, Response, ServerError, ServerResult, ValidationResult, Variables,
 };
+#[cfg(feature = "with-opentelemetry")]
 use opentelemetry::trace::TraceContextExt;
+use sentry::{
+    protocol::{Event, SpanStatus},
+    Hub, SentryFutureExt, Transaction, TransactionContext,
+};
+use serde_json::Value;
+
+#[cfg(all(feature = "with-auth", feature = "with-axum"))]
+use super::auth::{AccessToken, AuthError};
+use super::error_code::ErrorCode;
+#[cfg(feature = "with-axum")]
+use super::request_id::RequestId;
+use super::sentry_breadcrumb::{self, SensitiveKeys};
+
+fn request_data<T: Any>(request: &Request) -> Option<&T> {
+    request
+        .data
+        .get(&std::any::TypeId::of::<T>())
+        .and_then(|x| x.downcast_ref::<T>())
+}
+
+// 受け取ったsentry-trace/baggageヘッダー
+// `schema.execute(req.data(sentry_trace))` のようにリクエストのdataに入れる
+#[derive(Debug, Clone, Default)]
+pub struct SentryTraceHeaders(pub Vec<(String, String)>);
+
+impl SentryTraceHeaders {
+    fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn transaction_context(&self, name: &str, op: &str) -> TransactionContext {
+        let mut ctx = TransactionContext::continue_from_headers(
+            name,
+            op,
+            self.0
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str())),
+        );
+        // sentry-traceにsampledがなければbaggageのsentry-sampledに従う
+        if ctx.sampled().is_none() {
+            let sampled = self.get("baggage").and_then(|baggage| {
+                baggage
+                    .split(',')
+                    .filter_map(|x| x.split_once('='))
+                    .find(|(key, _)| key.trim() == "sentry-sampled")
+                    .and_then(|(_, value)| value.trim().parse::<bool>().ok())
+            });
+            ctx.set_sampled(sampled);
+        }
+        ctx
+    }
+}
+
+#[cfg(feature = "with-axum")]
+#[axum::async_trait]
+impl<S> axum::extract::FromRequestParts<S> for SentryTraceHeaders
+where
+    S: Send + Sync,
+{
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Self(
+            ["sentry-trace", "baggage"]
+                .into_iter()
+                .filter_map(|name| {
+                    let value = parts.headers.get(name)?.to_str().ok()?;
+                    Some((name.to_string(), value.to_string()))
+                })
+                .collect(),
+        ))
+    }
+}
 
-pub struct Sentry;
+fn operation_type_name(ty: OperationType) -> &'static str {
+    match ty {
+        OperationType::Query => "query",
+        OperationType::Mutation => "mutation",
+        OperationType::Subscription => "subscription",
+    }
+}
+
+// transactionの名前は開始時に決める必要があるので、parseの前に一度パースしてoperationを探す
+fn find_operation(query: &str, operation_name: Option<&str>) -> Option<(OperationType, String)> {
+    let document = async_graphql::parser::parse_query(query).ok()?;
+    match (document.operations, operation_name) {
+        (DocumentOperations::Single(operation), _) => Some((operation.node.ty, String::new())),
+        (DocumentOperations::Multiple(operations), Some(name)) => operations
+            .get(name)
+            .map(|operation| (operation.node.ty, name.to_string())),
+        (DocumentOperations::Multiple(operations), None) if operations.len() == 1 => operations
+            .into_iter()
+            .next()
+            .map(|(name, operation)| (operation.node.ty, name.to_string())),
+        _ => None,
+    }
+}
+
+fn error_code(err: &ServerError) -> Option<&str> {
+    match err.extensions.as_ref()?.get("code")? {
+        async_graphql::Value::String(code) => Some(code),
+        _ => None,
+    }
+}
+
+fn should_capture(err: &ServerError) -> bool {
+    !error_code(err)
+        .and_then(ErrorCode::parse)
+        .is_some_and(|code| code.is_client_error())
+}
+
+// with_index: falseのときは `users.name` のように配列の添字を除く (fingerprint用)
+fn path_string(path: &[PathSegment], with_index: bool) -> String {
+    let mut s = String::new();
+    for segment in path {
+        match segment {
+            PathSegment::Index(idx) if with_index => {
+                let _ = write!(&mut s, ".{idx}");
+            }
+            PathSegment::Index(_) => {}
+            PathSegment::Field(name) => {
+                let _ = write!(&mut s, ".{name}");
+            }
+        }
+    }
+    s.trim_start_matches('.').to_string()
+}
+
+// sentry-anyhowと同じように、原因の連鎖をexceptionにして一番外側にbacktraceを付ける
+fn event_from_anyhow(err: &anyhow::Error) -> Event<'static> {
+    let mut event = sentry::event_from_error::<dyn std::error::Error + Send + Sync>(err.as_ref());
+    let backtrace = err.backtrace();
+    if backtrace.status() == std::backtrace::BacktraceStatus::Captured {
+        if let Some(exception) = event.exception.last_mut() {
+            exception.stacktrace =
+                sentry::integrations::backtrace::parse_stacktrace(&format!("{backtrace:#}"));
+        }
+    }
+    event
+}
+
+// 同じoperation、path、エラーコードのものを1つのissueにまとめる
+fn error_event(err: &ServerError, operation_name: Option<&str>) -> Event<'static> {
+    let mut event = match err.source::<anyhow::Error>() {
+        Some(source) => event_from_anyhow(source),
+        None => Event {
+            message: Some(err.message.clone()),
+            ..Default::default()
+        },
+    };
+    let code = error_code(err).unwrap_or("none");
+    let path = path_string(&err.path, true);
+
+    event.level = sentry::Level::Error;
+    event.fingerprint = Cow::Owned(
+        [
+            "graphql",
+            operation_name.unwrap_or("anonymous"),
+            &path_string(&err.path, false),
+            code,
+        ]
+        .into_iter()
+        .map(|x| Cow::Owned(x.to_string()))
+        .collect(),
+    );
+    event
+        .tags
+        .insert("graphql.error_code".to_string(), code.to_string());
+    let mut map = std::collections::BTreeMap::new();
+    map.insert(String::from("path"), Value::from(path));
+    map.insert(String::from("message"), Value::from(err.message.clone()));
+    event
+        .contexts
+        .insert("graphql".to_string(), sentry::protocol::Context::Other(map));
+    event
+}
+
+pub const DEFAULT_SLOW_RESOLVER: Duration = Duration::from_millis(100);
+
+pub struct Sentry {
+    slow_resolver: Duration,
+    sensitive_keys: Arc<SensitiveKeys>,
+}
+
+impl Sentry {
+    pub fn new(slow_resolver: Duration) -> Self {
+        Self {
+            slow_resolver,
+            sensitive_keys: Default::default(),
+        }
+    }
+
+    // 変数の値を隠すキーを追加する
+    pub fn with_sensitive_keys<S: AsRef<str>>(mut self, keys: impl IntoIterator<Item = S>) -> Self {
+        self.sensitive_keys = Arc::new(SensitiveKeys::new(keys));
+        self
+    }
+}
+
+impl Default for Sentry {
+    fn default() -> Self {
+        Self::new(DEFAULT_SLOW_RESOLVER)
+    }
+}
 
 impl ExtensionFactory for Sentry {
+    // リクエストごとに呼ばれるので、ここで今のHubから分ける
     fn create(&self) -> Arc<dyn Extension> {
-        Arc::new(SentryExtension)
+        Arc::new(SentryExtension {
+            hub: Arc::new(Hub::new_from_top(Hub::current())),
+            slow_resolver: self.slow_resolver,
+            sensitive_keys: self.sensitive_keys.clone(),
+            transaction: Mutex::new(None),
+        })
+    }
+}
+
+struct SentryExtension {
+    hub: Arc<Hub>,
+    slow_resolver: Duration,
+    sensitive_keys: Arc<SensitiveKeys>,
+    transaction: Mutex<Option<Transaction>>,
+}
+
+impl SentryExtension {
+    fn tracing_enabled(&self) -> bool {
+        self.hub.client().is_some_and(|client| {
+            let options = client.options();
+            options.traces_sample_rate > 0.0 || options.traces_sampler.is_some()
+        })
+    }
+
+    fn start_transaction(&self, request: &Request) {
+        let Some((ty, name)) = find_operation(&request.query, request.operation_name.as_deref())
+        else {
+            return;
+        };
+        // subscriptionはrequestが終わらないのでtransactionにしない
+        if ty == OperationType::Subscription {
+            return;
+        }
+        let ty = operation_type_name(ty);
+        let name = if name.is_empty() {
+            ty.to_string()
+        } else {
+            format!("{ty} {name}")
+        };
+        let op = format!("graphql.{ty}");
+        let ctx = match request_data::<SentryTraceHeaders>(request) {
+            Some(headers) => headers.transaction_context(&name, &op),
+            None => TransactionContext::new(&name, &op),
+        };
+        let transaction = self.hub.start_transaction(ctx);
+        self.hub
+            .configure_scope(|scope| scope.set_span(Some(transaction.clone().into())));
+        *self.transaction.lock().unwrap() = Some(transaction);
+    }
+
+    fn start_child(&self, op: &str, description: &str) -> Option<sentry::TransactionOrSpan> {
+        self.transaction
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|transaction| transaction.start_child(op, description).into())
     }
 }
 
-struct SentryExtension;
+fn finish<T, E>(span: Option<sentry::TransactionOrSpan>, result: &Result<T, E>) {
+    if let Some(span) = span {
+        span.set_status(if result.is_ok() {
+            SpanStatus::Ok
+        } else {
+            SpanStatus::InternalError
+        });
+        span.finish();
+    }
+}
 
 #[async_graphql::async_trait::async_trait]
 impl Extension for SentryExtension {
+    async fn request(&self, ctx: &ExtensionContext<'_>, next: NextRequest<'_>) -> Response {
+        let resp = next.run(ctx).bind_hub(self.hub.clone()).await;
+
+        let transaction = self.transaction.lock().unwrap().take();
+        if let Some(transaction) = transaction {
+            transaction.set_status(if resp.is_ok() {
+                SpanStatus::Ok
+            } else {
+                SpanStatus::InternalError
+            });
+            // scopeのtag、contextやuserをtransactionにも付ける
+            Hub::run(self.hub.clone(), || transaction.finish());
+        }
+        resp
+    }
+
+    async fn parse_query(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        query: &str,
+        variables: &Variables,
+        next: NextParseQuery<'_>,
+    ) -> ServerResult<ExecutableDocument> {
+        let span = self.start_child("graphql.parse", "");
+        let result = next.run(ctx, query, variables).await;
+        finish(span, &result);
+        result
+    }
+
+    async fn validation(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        next: NextValidation<'_>,
+    ) -> Result<ValidationResult, Vec<ServerError>> {
+        let span = self.start_child("graphql.validate", "");
+        let result = next.run(ctx).await;
+        finish(span, &result);
+        result
+    }
+
+    // 遅かったものだけfinishしてtransactionに入れ、breadcrumbにも残す
+    async fn resolve(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        info: ResolveInfo<'_>,
+        next: NextResolve<'_>,
+    ) -> ServerResult<Option<async_graphql::Value>> {
+        if info.is_for_introspection {
+            return next.run(ctx, info).await;
+        }
+        let field = format!("{}.{}", info.parent_type, info.name);
+        let path = info.path_node.to_string();
+        let span = self.start_child("graphql.resolve", &field);
+        if let Some(span) = span.as_ref() {
+            span.set_data("graphql.path", path.clone().into());
+        }
+        let start = Instant::now();
+        let result = next.run(ctx, info).await;
+        let elapsed = start.elapsed();
+        if elapsed >= self.slow_resolver {
+            finish(span, &result);
+            sentry_breadcrumb::add(
+                "graphql.resolve",
+                "default",
+                field,
+                elapsed,
+                [("path", Value::from(path))],
+                result.is_err(),
+            );
+        }
+        result
+    }
+
+    async fn prepare_request(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        request: Request,
+        next: NextPrepareRequest<'_>,
+    ) -> ServerResult<Request> {
+        if self.tracing_enabled() {
+            self.start_transaction(&request);
+        }
+
+        let variables = serde_json::to_value(&request.variables).unwrap_or_default();
+        let variables = self.sensitive_keys.scrub(variables);
+        self.hub.add_breadcrumb(sentry::Breadcrumb {
+            category: Some("graphql.request".to_string()),
+            message: request.operation_name.clone(),
+            data: [("variables".to_string(), variables.clone())].into(),
+            ..Default::default()
+        });
+
+        self.hub.configure_scope(|scope| {
+            let mut map = std::collections::BTreeMap::new();
+            map.insert(String::from("variables"), variables);
+            scope.set_context("graphql_request", sentry::protocol::Context::Other(map));
+
+            #[cfg(feature = "with-axum")]
+            if let Some(request_id) = request_data::<RequestId>(&request) {
+                scope.set_tag("request_id", request_id);
+            }
+
+            #[cfg(all(feature = "with-auth", feature = "with-axum"))]
+            if let Some(Ok(token)) = request_data::<Result<AccessToken, AuthError>>(&request) {
+                scope.set_user(Some(sentry::User {
+                    id: Some(token.claims.sub.clone()),
+                    ..Default::default()
+                }));
+            }
+        });
+        next.run(ctx, request).await
+    }
+
     async fn execute(
         &self,
         ctx: &ExtensionContext<'_>,
         operation_name: Option<&str>,
         next: NextExecute<'_>,
     ) -> Response {
-        sentry::configure_scope(|scope| {
+        if let Some(operation_name) = operation_name {
+            self.hub
+                .configure_scope(|scope| scope.set_tag("graphql.operation_name", operation_name));
+        }
+
+        // OpenTelemetryのtrace_idからSentryのイベントを探せるようにする
+        #[cfg(feature = "with-opentelemetry")]
+        self.hub.configure_scope(|scope| {
             let otel_context = opentelemetry::Context::current();
             let span = otel_context.span();
 
@@ -45,45 +458,220 @@ impl Extension for SentryExtension {
 
         let resp = next.run(ctx, operation_name).await;
 
-        if resp.is_err() {
-            let mut error_message = None;
-            let mut paths = vec![];
-            let mut errors = vec![];
-            for err in &resp.errors {
-                if !err.path.is_empty() {
-                    let mut path = String::new();
-                    for (idx, s) in err.path.iter().enumerate() {
-                        if idx > 0 {
-                            path.push('.');
-                        }
-                        match s {
-                            PathSegment::Index(idx) => {
-                                let _ = write!(&mut path, "{}", idx);
-                            }
-                            PathSegment::Field(name) => {
-                                let _ = write!(&mut path, "{}", name);
-                            }
-                        }
-                    }
-                    paths.push(path);
-                    errors.push(err.message.clone());
-                }
-                if error_message.is_none() {
-                    error_message = Some(err.message.clone());
-                }
+        // クライアントのエラー (BAD_USER_INPUTやFORBIDDENなど) は送らない
+        for err in resp.errors.iter().filter(|err| should_capture(err)) {
+            self.hub.capture_event(error_event(err, operation_name));
+        }
+        resp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::sentry_options;
+    use async_graphql::{EmptyMutation, EmptySubscription, Object, Schema};
+    use sentry::{
+        protocol::{Context, EnvelopeItem},
+        test::TestTransport,
+    };
+
+    struct Query;
+
+    #[Object]
+    impl Query {
+        async fn fail(&self, name: String, _password: String) -> async_graphql::Result<bool> {
+            // もう一方のリクエストと交互に進むようにする
+            tokio::task::yield_now().await;
+            Err(format!("{name} failed").into())
+        }
+
+        async fn hello(&self, _ctx: &async_graphql::Context<'_>) -> async_graphql::Result<&str> {
+            #[cfg(feature = "with-sea-orm")]
+            if let Some(db) = _ctx.data_opt::<sea_orm::DatabaseConnection>() {
+                use sea_orm::ConnectionTrait;
+
+                super::super::db::TracedConnection::new(db)
+                    .query_all(sea_orm::Statement::from_string(
+                        sea_orm::DbBackend::Postgres,
+                        "SELECT 1",
+                    ))
+                    .await?;
             }
-            sentry::configure_scope(|scope| {
-                let mut map = std::collections::BTreeMap::new();
-                map.insert(String::from("path"), serde_json::json!(paths));
-                map.insert(String::from("errors"), serde_json::json!(errors));
+            Ok("hello")
+        }
 
-                scope.set_context("graphql", sentry::protocol::Context::Other(map));
-            });
+        async fn user(&self, id: i32) -> async_graphql::Result<Option<String>> {
+            match id {
+                1 => Err(ErrorCode::NotFound.error("user not found")),
+                _ => Err(anyhow::anyhow!("connection refused")
+                    .context("failed to load user")
+                    .into()),
+            }
+        }
+    }
+
+    fn test_hub(traces_sample_rate: f32) -> anyhow::Result<(Arc<TestTransport>, Arc<Hub>)> {
+        Ok(sentry_options::test_hub(sentry::ClientOptions {
+            dsn: Some("https://public@sentry.invalid/1".parse()?),
+            traces_sample_rate,
+            ..Default::default()
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_hub_per_request() -> anyhow::Result<()> {
+        let (transport, hub) = test_hub(0.0)?;
 
-            let error_message = error_message.unwrap_or("GraphqlError".to_string());
-            // sentry::capture_message(&error_message, sentry::Level::Error);
-            tracing::error!("{}", error_message);
+        let schema = Schema::build(Query, EmptyMutation, EmptySubscription)
+            .extension(Sentry::default())
+            .finish();
+        let request = |name: &str| {
+            Request::new(
+                "query Fail($name: String!, $password: String!) { fail(name: $name, password: $password) }",
+            )
+            .variables(Variables::from_json(
+                serde_json::json!({"name": name, "password": "hunter2"}),
+            ))
+        };
+        async { tokio::join!(schema.execute(request("a")), schema.execute(request("b"))) }
+            .bind_hub(hub)
+            .await;
+
+        let events = transport.fetch_and_clear_events();
+        assert_eq!(events.len(), 2);
+        for event in events {
+            let Some(Context::Other(request)) = event.contexts.get("graphql_request") else {
+                panic!("no graphql_request context");
+            };
+            let name = request["variables"]["name"].as_str().unwrap();
+            assert_eq!(event.message, Some(format!("{name} failed")));
+            assert_eq!(request["variables"]["password"], "[Filtered]");
+            assert_eq!(event.tags["graphql.operation_name"], "Fail");
         }
-        resp
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_transaction() -> anyhow::Result<()> {
+        let (transport, hub) = test_hub(1.0)?;
+        let schema = Schema::build(Query, EmptyMutation, EmptySubscription)
+            .extension(Sentry::new(Duration::ZERO));
+        #[cfg(feature = "with-sea-orm")]
+        let schema = schema.data(
+            sea_orm::MockDatabase::new(sea_orm::DbBackend::Postgres)
+                .append_query_results([[std::collections::BTreeMap::from([(
+                    "x",
+                    sea_orm::Value::from(1),
+                )])]])
+                .into_connection(),
+        );
+        let schema = schema.finish();
+
+        let trace_id = "771a43a4192642f0b136d5159a501700";
+        let request = Request::new("query Hello { hello }").data(SentryTraceHeaders(vec![(
+            "sentry-trace".to_string(),
+            format!("{trace_id}-b7ad6b7169203331-1"),
+        )]));
+        let resp = schema.execute(request).bind_hub(hub).await;
+        assert!(resp.is_ok(), "{:?}", resp.errors);
+
+        let transactions: Vec<_> = transport
+            .fetch_and_clear_envelopes()
+            .iter()
+            .flat_map(|envelope| envelope.items().cloned().collect::<Vec<_>>())
+            .filter_map(|item| match item {
+                EnvelopeItem::Transaction(transaction) => Some(transaction),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(transactions.len(), 1);
+        let transaction = &transactions[0];
+        assert_eq!(transaction.name.as_deref(), Some("query Hello"));
+        let Some(Context::Trace(trace)) = transaction.contexts.get("trace") else {
+            panic!("no trace context");
+        };
+        assert_eq!(trace.trace_id.to_string(), trace_id);
+        assert_eq!(
+            trace.parent_span_id.map(|x| x.to_string()).as_deref(),
+            Some("b7ad6b7169203331")
+        );
+        assert_eq!(trace.op.as_deref(), Some("graphql.query"));
+
+        let mut ops: Vec<_> = transaction
+            .spans
+            .iter()
+            .filter_map(|span| span.op.as_deref())
+            .collect();
+        ops.sort();
+        #[cfg(feature = "with-sea-orm")]
+        assert_eq!(
+            ops,
+            [
+                "db.sql.query",
+                "graphql.parse",
+                "graphql.resolve",
+                "graphql.validate"
+            ]
+        );
+        #[cfg(not(feature = "with-sea-orm"))]
+        assert_eq!(
+            ops,
+            ["graphql.parse", "graphql.resolve", "graphql.validate"]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_capture_errors() -> anyhow::Result<()> {
+        let (transport, hub) = test_hub(0.0)?;
+        let schema = Schema::build(Query, EmptyMutation, EmptySubscription)
+            .extension(Sentry::new(Duration::ZERO))
+            .finish();
+        for id in [1, 2] {
+            let resp = schema
+                .execute(format!("query User {{ user(id: {id}) }}"))
+                .bind_hub(hub.clone())
+                .await;
+            assert!(resp.is_err());
+        }
+
+        // NOT_FOUNDは送らない
+        let events = transport.fetch_and_clear_events();
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert_eq!(
+            event
+                .fingerprint
+                .iter()
+                .map(|x| x.as_ref())
+                .collect::<Vec<_>>(),
+            ["graphql", "User", "user", "none"]
+        );
+        let exceptions: Vec<_> = event
+            .exception
+            .iter()
+            .map(|x| x.value.as_deref().unwrap_or_default())
+            .collect();
+        assert_eq!(exceptions, ["connection refused", "failed to load user"]);
+        let Some(Context::Other(graphql)) = event.contexts.get("graphql") else {
+            panic!("no graphql context");
+        };
+        assert_eq!(graphql["path"], "user");
+
+        // このリクエストのものだけが入る
+        let breadcrumbs: Vec<_> = event
+            .breadcrumbs
+            .iter()
+            .map(|x| (x.category.as_deref().unwrap(), x.message.as_deref()))
+            .collect();
+        assert_eq!(
+            breadcrumbs,
+            [
+                ("graphql.request", None),
+                ("graphql.resolve", Some("Query.user"))
+            ]
+        );
+        Ok(())
     }
 }
@@ -0,0 +1,401 @@
+use std::{collections::HashMap, fmt, path::PathBuf, sync::Arc, time::Duration};
+
+use async_graphql::DataContext;
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header, request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+};
+use jsonwebtoken::{jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use super::error_code::ErrorCode;
+
+const SUPPORTED_ALGORITHMS: [Algorithm; 3] = [Algorithm::HS256, Algorithm::RS256, Algorithm::ES256];
+// kidが見つからなかったときにJWKSを取り直す最小間隔
+const JWKS_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Claims {
+    pub sub: String,
+    pub exp: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<String>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+impl Claims {
+    pub fn scopes(&self) -> impl Iterator<Item = &str> {
+        self.scope.as_deref().unwrap_or("").split_whitespace()
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes().any(|x| x == scope)
+    }
+
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|x| x == role)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthError {
+    MissingToken,
+    InvalidToken(String),
+    // JWKSが読めない、validatorが登録されていないなどサーバー側の問題
+    Unavailable(String),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingToken => write!(f, "missing bearer token"),
+            Self::InvalidToken(reason) => write!(f, "invalid token: {reason}"),
+            Self::Unavailable(reason) => write!(f, "authentication unavailable: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+impl AuthError {
+    pub fn to_graphql_error(&self) -> async_graphql::Error {
+        match self {
+            Self::Unavailable(_) => ErrorCode::Internal.error(self.to_string()),
+            _ => ErrorCode::Unauthenticated.error(self.to_string()),
+        }
+    }
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Unavailable(_) => {
+                tracing::error!("{}", self);
+                (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()).into_response()
+            }
+            _ => (
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, "Bearer error=\"invalid_token\"")],
+                self.to_string(),
+            )
+                .into_response(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum JwksSource {
+    File(PathBuf),
+    Url(String),
+}
+
+impl JwksSource {
+    async fn load(&self) -> anyhow::Result<JwkSet> {
+        Ok(match self {
+            Self::File(path) => serde_json::from_slice(&tokio::fs::read(path).await?)?,
+            Self::Url(url) => reqwest::get(url).await?.error_for_status()?.json().await?,
+        })
+    }
+}
+
+struct KeyCache {
+    jwks: JwkSet,
+    loaded_at: tokio::time::Instant,
+}
+
+pub struct JwtValidator {
+    source: JwksSource,
+    issuer: Option<Vec<String>>,
+    audience: Option<Vec<String>>,
+    leeway: u64,
+    keys: RwLock<KeyCache>,
+}
+
+impl JwtValidator {
+    pub async fn new(
+        source: JwksSource,
+        issuer: Option<Vec<String>>,
+        audience: Option<Vec<String>>,
+    ) -> anyhow::Result<Self> {
+        let jwks = source.load().await?;
+        Ok(Self {
+            source,
+            issuer,
+            audience,
+            leeway: 60,
+            keys: RwLock::new(KeyCache {
+                jwks,
+                loaded_at: tokio::time::Instant::now(),
+            }),
+        })
+    }
+
+    // JWT_JWKS_PATH / JWT_JWKS_URL がどちらも未設定なら認証なしとしてNoneを返す
+    pub async fn new_from_env() -> anyhow::Result<Option<Self>> {
+        let source = if let Ok(path) = std::env::var("JWT_JWKS_PATH") {
+            JwksSource::File(path.into())
+        } else if let Ok(url) = std::env::var("JWT_JWKS_URL") {
+            JwksSource::Url(url)
+        } else {
+            return Ok(None);
+        };
+        let split = |name: &str| {
+            std::env::var(name)
+                .ok()
+                .map(|x| x.split(',').map(|x| x.trim().to_string()).collect())
+        };
+        Ok(Some(
+            Self::new(source, split("JWT_ISSUER"), split("JWT_AUDIENCE")).await?,
+        ))
+    }
+
+    pub fn with_leeway(mut self, leeway: u64) -> Self {
+        self.leeway = leeway;
+        self
+    }
+
+    async fn decoding_key(&self, kid: Option<&str>) -> Result<DecodingKey, AuthError> {
+        if let Some(key) = self.find_key(kid).await? {
+            return Ok(key);
+        }
+        // 鍵のローテーション対策で、URLの場合は一定間隔ごとに取り直す
+        if let JwksSource::Url(_) = self.source {
+            let mut keys = self.keys.write().await;
+            if keys.loaded_at.elapsed() >= JWKS_REFRESH_INTERVAL {
+                keys.jwks = self
+                    .source
+                    .load()
+                    .await
+                    .map_err(|e| AuthError::Unavailable(format!("failed to load jwks: {e}")))?;
+                keys.loaded_at = tokio::time::Instant::now();
+            }
+        }
+        self.find_key(kid)
+            .await?
+            .ok_or_else(|| AuthError::InvalidToken("unknown signing key".to_string()))
+    }
+
+    async fn find_key(&self, kid: Option<&str>) -> Result<Option<DecodingKey>, AuthError> {
+        let keys = self.keys.read().await;
+        let jwk = match kid {
+            Some(kid) => keys.jwks.find(kid),
+            None if keys.jwks.keys.len() == 1 => keys.jwks.keys.first(),
+            None => None,
+        };
+        jwk.map(|jwk| {
+            DecodingKey::from_jwk(jwk)
+                .map_err(|e| AuthError::Unavailable(format!("invalid jwk: {e}")))
+        })
+        .transpose()
+    }
+
+    pub async fn validate<C: DeserializeOwned>(&self, token: &str) -> Result<C, AuthError> {
+        let header = jsonwebtoken::decode_header(token)
+            .map_err(|e| AuthError::InvalidToken(e.to_string()))?;
+        if !SUPPORTED_ALGORITHMS.contains(&header.alg) {
+            return Err(AuthError::InvalidToken(format!(
+                "unsupported algorithm {:?}",
+                header.alg
+            )));
+        }
+        let key = self.decoding_key(header.kid.as_deref()).await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.leeway = self.leeway;
+        if let Some(issuer) = self.issuer.as_ref() {
+            validation.set_issuer(issuer);
+        }
+        if let Some(audience) = self.audience.as_ref() {
+            validation.set_audience(audience);
+        } else {
+            validation.validate_aud = false;
+        }
+
+        jsonwebtoken::decode::<C>(token, &key, &validation)
+            .map(|x| x.claims)
+            .map_err(|e| AuthError::InvalidToken(e.to_string()))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AccessToken<C = Claims> {
+    pub claims: C,
+    pub token: String,
+}
+
+#[async_trait]
+impl<S, C> FromRequestParts<S> for AccessToken<C>
+where
+    S: Send + Sync,
+    C: DeserializeOwned,
+{
+    type Rejection = AuthError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| {
+                x.strip_prefix("Bearer ")
+                    .or_else(|| x.strip_prefix("bearer "))
+            })
+            .map(|x| x.trim().to_string())
+            .ok_or(AuthError::MissingToken)?;
+
+        let validator = parts
+            .extensions
+            .get::<Arc<JwtValidator>>()
+            .ok_or_else(|| AuthError::Unavailable("JwtValidator is not registered".to_string()))?;
+
+        Ok(AccessToken {
+            claims: validator.validate(&token).await?,
+            token,
+        })
+    }
+}
+
+// graphql_handlerで `Result<AccessToken, AuthError>` をそのまま `req.data(token)` しておく
+// ContextのほかDefaultDenyのPolicyからも使えるようにDataContextで受ける
+pub fn get_access_token_from_ctx<'a, C: Send + Sync + 'static>(
+    ctx: &impl DataContext<'a>,
+) -> async_graphql::Result<&'a AccessToken<C>> {
+    match ctx.data_opt::<Result<AccessToken<C>, AuthError>>() {
+        Some(Ok(token)) => Ok(token),
+        Some(Err(err)) => Err(err.to_graphql_error()),
+        None => Err(AuthError::MissingToken.to_graphql_error()),
+    }
+}
+
+pub fn get_claims_from_ctx<'a, C: Send + Sync + 'static>(
+    ctx: &impl DataContext<'a>,
+) -> async_graphql::Result<&'a C> {
+    get_access_token_from_ctx(ctx).map(|x| &x.claims)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{EncodingKey, Header};
+
+    const SECRET: &[u8] = b"secret-for-test";
+
+    async fn validator(name: &str, audience: Option<Vec<String>>) -> anyhow::Result<JwtValidator> {
+        let jwks = serde_json::json!({
+            "keys": [{
+                "kty": "oct",
+                "kid": "test",
+                "alg": "HS256",
+                "k": "c2VjcmV0LWZvci10ZXN0",
+            }]
+        });
+        let path = std::env::temp_dir().join(format!("jwks-{}-{name}.json", std::process::id()));
+        std::fs::write(&path, jwks.to_string())?;
+        let validator = JwtValidator::new(
+            JwksSource::File(path.clone()),
+            Some(vec!["https://issuer.example.com".to_string()]),
+            audience,
+        )
+        .await;
+        std::fs::remove_file(path)?;
+        validator
+    }
+
+    fn token(claims: serde_json::Value) -> String {
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some("test".to_string());
+        jsonwebtoken::encode(&header, &claims, &EncodingKey::from_secret(SECRET)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_validate() -> anyhow::Result<()> {
+        let validator = validator("validate", Some(vec!["api".to_string()])).await?;
+        let exp = jsonwebtoken::get_current_timestamp() + 600;
+
+        let claims: Claims = validator
+            .validate(&token(serde_json::json!({
+                "sub": "user-1",
+                "iss": "https://issuer.example.com",
+                "aud": "api",
+                "exp": exp,
+                "scope": "read write",
+                "roles": ["admin"],
+            })))
+            .await
+            .unwrap();
+        assert_eq!(claims.sub, "user-1");
+        assert!(claims.has_scope("write"));
+        assert!(claims.has_role("admin"));
+
+        let expired = validator
+            .validate::<Claims>(&token(serde_json::json!({
+                "sub": "user-1",
+                "iss": "https://issuer.example.com",
+                "aud": "api",
+                "exp": exp - 3600,
+            })))
+            .await;
+        assert!(matches!(expired, Err(AuthError::InvalidToken(_))));
+
+        let wrong_audience = validator
+            .validate::<Claims>(&token(serde_json::json!({
+                "sub": "user-1",
+                "iss": "https://issuer.example.com",
+                "aud": "other",
+                "exp": exp,
+            })))
+            .await;
+        assert!(matches!(wrong_audience, Err(AuthError::InvalidToken(_))));
+
+        let wrong_issuer = validator
+            .validate::<Claims>(&token(serde_json::json!({
+                "sub": "user-1",
+                "iss": "https://evil.example.com",
+                "aud": "api",
+                "exp": exp,
+            })))
+            .await;
+        assert!(matches!(wrong_issuer, Err(AuthError::InvalidToken(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_extractor() -> anyhow::Result<()> {
+        let validator = Arc::new(validator("extractor", None).await?);
+        let exp = jsonwebtoken::get_current_timestamp() + 600;
+
+        let (mut parts, _) = axum::http::Request::builder()
+            .header(
+                header::AUTHORIZATION,
+                format!(
+                    "Bearer {}",
+                    token(serde_json::json!({
+                        "sub": "user-1",
+                        "iss": "https://issuer.example.com",
+                        "exp": exp,
+                    }))
+                ),
+            )
+            .extension(validator.clone())
+            .body(())?
+            .into_parts();
+        let token = AccessToken::<Claims>::from_request_parts(&mut parts, &()).await;
+        assert_eq!(token.unwrap().claims.sub, "user-1");
+
+        let (mut parts, _) = axum::http::Request::builder()
+            .extension(validator)
+            .body(())?
+            .into_parts();
+        let token = AccessToken::<Claims>::from_request_parts(&mut parts, &()).await;
+        let err = token.unwrap_err();
+        assert_eq!(err, AuthError::MissingToken);
+        assert_eq!(err.into_response().status(), StatusCode::UNAUTHORIZED);
+        Ok(())
+    }
+}
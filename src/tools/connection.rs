@@ -3,7 +3,7 @@
 // をみて作成し直し
 
 use sea_orm::{
-    DatabaseConnection, DbErr, EntityTrait, FromQueryResult, PaginatorTrait, QuerySelect, Select,
+    ConnectionTrait, DbErr, EntityTrait, FromQueryResult, PaginatorTrait, QuerySelect, Select,
 };
 type Result<T> = std::result::Result<T, DbErr>;
 
@@ -58,9 +58,9 @@ impl Range {
         }
     }
 
-    async fn forward<E: EntityTrait>(
+    async fn forward<E: EntityTrait, C: ConnectionTrait>(
         &self,
-        db: &DatabaseConnection,
+        db: &C,
         qs: Select<E>,
         first: u64,
         after: u64,
@@ -86,9 +86,9 @@ impl Range {
         })
     }
 
-    async fn backward<'db, E, M>(
+    async fn backward<'db, E, M, C>(
         &self,
-        db: &'db DatabaseConnection,
+        db: &'db C,
         qs: Select<E>,
         last: u64,
         before: Option<u64>,
@@ -96,6 +96,7 @@ impl Range {
     where
         E: EntityTrait<Model = M>,
         M: FromQueryResult + Sized + Send + Sync + 'db,
+        C: ConnectionTrait,
     {
         let count = qs.clone().paginate(db, 1).num_items().await?;
         let before = before.map(|x| std::cmp::min(x, count)).unwrap_or(count);
@@ -115,14 +116,15 @@ impl Range {
         })
     }
 
-    pub async fn get_connection<'db, E, M>(
+    pub async fn get_connection<'db, E, M, C>(
         &self,
-        db: &'db DatabaseConnection,
+        db: &'db C,
         qs: Select<E>,
     ) -> Result<Connection<M>>
     where
         E: EntityTrait<Model = M>,
         M: FromQueryResult + Sized + Send + Sync + 'db,
+        C: ConnectionTrait,
     {
         match self {
             Self::Forward((first, after)) => self.forward(db, qs, *first, *after).await,
@@ -0,0 +1,394 @@
+// 境界を含むかどうかを持つ日付、日時の範囲 (Postgresのdaterange, tstzrange)
+// startやendがnullならその側は制限なし。入力は開始を含み終了を含まない [start, end) がデフォルト
+// 開始が終了より後のものと空の範囲はBAD_USER_INPUTにする
+//
+// ```ignore
+// async fn events(&self, period: DateRangeInput) -> Result<Vec<Event>> {
+//     let period = DateRange::try_from(period)?;
+// }
+//
+// #[sea_orm(column_type = "custom(\"daterange\")", select_as = "text", save_as = "daterange")]
+// pub period: DateRange,
+// ```
+// daterangeは [a, b] を [a, b+1) にそろえるので、読み込んだものは終了を含まない形になる
+// Postgresの空の範囲 (empty) は is_empty=true (start, endはnull) で表す。入力では空の範囲は作れない
+use async_graphql::{InputObject, SimpleObject};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::date::Date;
+use super::date_time_rfc3339::DateTimeRfc3339;
+use super::error_code::ErrorCode;
+
+const EMPTY: &str = "empty";
+
+trait RangeBound: Sized {
+    type Inner: PartialOrd;
+
+    fn inner(&self) -> &Self::Inner;
+    fn to_pg(&self) -> String;
+    fn from_pg(value: &str) -> Option<Self>;
+}
+
+impl RangeBound for Date {
+    type Inner = NaiveDate;
+
+    fn inner(&self) -> &NaiveDate {
+        &self.0
+    }
+
+    fn to_pg(&self) -> String {
+        self.0.to_string()
+    }
+
+    fn from_pg(value: &str) -> Option<Self> {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d").ok().map(Date)
+    }
+}
+
+impl RangeBound for DateTimeRfc3339 {
+    type Inner = DateTime<Utc>;
+
+    fn inner(&self) -> &DateTime<Utc> {
+        &self.0
+    }
+
+    fn to_pg(&self) -> String {
+        format!("\"{}\"", self.0.to_rfc3339())
+    }
+
+    // Postgresの出力は `2024-01-01 00:00:00+09` の形
+    fn from_pg(value: &str) -> Option<Self> {
+        DateTime::parse_from_rfc3339(value)
+            .or_else(|_| DateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%.f%#z"))
+            .ok()
+            .map(|x| DateTimeRfc3339(x.to_utc()))
+    }
+}
+
+// 制限なしの側は含まないものとして扱う
+fn validate<T: RangeBound>(
+    start: &Option<T>,
+    end: &Option<T>,
+    start_inclusive: bool,
+    end_inclusive: bool,
+) -> anyhow::Result<(bool, bool)> {
+    let start_inclusive = start_inclusive && start.is_some();
+    let end_inclusive = end_inclusive && end.is_some();
+    if let (Some(start), Some(end)) = (start, end) {
+        if start.inner() > end.inner() {
+            anyhow::bail!("range start must not be after its end");
+        }
+        if start.inner() == end.inner() && !(start_inclusive && end_inclusive) {
+            anyhow::bail!("range must not be empty");
+        }
+    }
+    Ok((start_inclusive, end_inclusive))
+}
+
+fn contains<T: RangeBound>(
+    start: &Option<T>,
+    end: &Option<T>,
+    start_inclusive: bool,
+    end_inclusive: bool,
+    value: &T::Inner,
+) -> bool {
+    start
+        .as_ref()
+        .is_none_or(|start| start.inner() < value || (start_inclusive && start.inner() == value))
+        && end
+            .as_ref()
+            .is_none_or(|end| value < end.inner() || (end_inclusive && end.inner() == value))
+}
+
+fn to_pg_text<T: RangeBound>(
+    start: &Option<T>,
+    end: &Option<T>,
+    start_inclusive: bool,
+    end_inclusive: bool,
+) -> String {
+    format!(
+        "{}{},{}{}",
+        if start_inclusive { '[' } else { '(' },
+        start.as_ref().map(T::to_pg).unwrap_or_default(),
+        end.as_ref().map(T::to_pg).unwrap_or_default(),
+        if end_inclusive { ']' } else { ')' },
+    )
+}
+
+#[allow(clippy::type_complexity)]
+fn from_pg_text<T: RangeBound>(value: &str) -> Option<(Option<T>, Option<T>, bool, bool)> {
+    let value = value.trim();
+    let start_inclusive = match value.chars().next()? {
+        '[' => true,
+        '(' => false,
+        _ => return None,
+    };
+    let end_inclusive = match value.chars().last()? {
+        ']' => true,
+        ')' => false,
+        _ => return None,
+    };
+    let (start, end) = value.get(1..value.len() - 1)?.split_once(',')?;
+    let bound = |x: &str| {
+        let x = x.trim().trim_matches('"');
+        if x.is_empty() || x.ends_with("infinity") {
+            Some(None)
+        } else {
+            T::from_pg(x).map(Some)
+        }
+    };
+    Some((bound(start)?, bound(end)?, start_inclusive, end_inclusive))
+}
+
+#[derive(SimpleObject, Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[graphql(shareable)]
+pub struct DateRange {
+    pub start: Option<Date>,
+    pub end: Option<Date>,
+    pub start_inclusive: bool,
+    pub end_inclusive: bool,
+    #[serde(default)]
+    pub is_empty: bool,
+}
+
+#[derive(InputObject, Debug, Clone)]
+pub struct DateRangeInput {
+    pub start: Option<Date>,
+    pub end: Option<Date>,
+    #[graphql(default = true)]
+    pub start_inclusive: bool,
+    #[graphql(default = false)]
+    pub end_inclusive: bool,
+}
+
+#[derive(SimpleObject, Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[graphql(shareable)]
+pub struct DateTimeRange {
+    pub start: Option<DateTimeRfc3339>,
+    pub end: Option<DateTimeRfc3339>,
+    pub start_inclusive: bool,
+    pub end_inclusive: bool,
+    #[serde(default)]
+    pub is_empty: bool,
+}
+
+#[derive(InputObject, Debug, Clone)]
+pub struct DateTimeRangeInput {
+    pub start: Option<DateTimeRfc3339>,
+    pub end: Option<DateTimeRfc3339>,
+    #[graphql(default = true)]
+    pub start_inclusive: bool,
+    #[graphql(default = false)]
+    pub end_inclusive: bool,
+}
+
+macro_rules! impl_range {
+    ($name:ident, $input:ident, $bound:ty, $inner:ty) => {
+        impl $name {
+            pub fn new(
+                start: Option<$bound>,
+                end: Option<$bound>,
+                start_inclusive: bool,
+                end_inclusive: bool,
+            ) -> anyhow::Result<Self> {
+                let (start_inclusive, end_inclusive) =
+                    validate(&start, &end, start_inclusive, end_inclusive)?;
+                Ok(Self {
+                    start,
+                    end,
+                    start_inclusive,
+                    end_inclusive,
+                    is_empty: false,
+                })
+            }
+
+            // Postgresのemptyにあたる、どの値も含まない範囲
+            pub fn empty() -> Self {
+                Self {
+                    start: None,
+                    end: None,
+                    start_inclusive: false,
+                    end_inclusive: false,
+                    is_empty: true,
+                }
+            }
+
+            pub fn contains(&self, value: &$inner) -> bool {
+                !self.is_empty
+                    && contains(
+                        &self.start,
+                        &self.end,
+                        self.start_inclusive,
+                        self.end_inclusive,
+                        value,
+                    )
+            }
+
+            pub fn to_pg_text(&self) -> String {
+                if self.is_empty {
+                    return EMPTY.to_string();
+                }
+                to_pg_text(
+                    &self.start,
+                    &self.end,
+                    self.start_inclusive,
+                    self.end_inclusive,
+                )
+            }
+
+            pub fn from_pg_text(value: &str) -> Option<Self> {
+                if value.trim().eq_ignore_ascii_case(EMPTY) {
+                    return Some(Self::empty());
+                }
+                let (start, end, start_inclusive, end_inclusive) = from_pg_text(value)?;
+                Self::new(start, end, start_inclusive, end_inclusive).ok()
+            }
+        }
+
+        impl TryFrom<$input> for $name {
+            type Error = async_graphql::Error;
+
+            fn try_from(value: $input) -> Result<Self, Self::Error> {
+                Self::new(
+                    value.start,
+                    value.end,
+                    value.start_inclusive,
+                    value.end_inclusive,
+                )
+                .map_err(|e| ErrorCode::BadUserInput.error(e.to_string()))
+            }
+        }
+    };
+}
+
+impl_range!(DateRange, DateRangeInput, Date, NaiveDate);
+impl_range!(
+    DateTimeRange,
+    DateTimeRangeInput,
+    DateTimeRfc3339,
+    DateTime<Utc>
+);
+
+#[cfg(feature = "with-sea-orm")]
+super::pg_value::impl_pg_text_value!(
+    DateRange,
+    sea_orm::sea_query::ColumnType::custom("daterange")
+);
+
+#[cfg(feature = "with-sea-orm")]
+super::pg_value::impl_pg_text_value!(
+    DateTimeRange,
+    sea_orm::sea_query::ColumnType::custom("tstzrange")
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_graphql::{EmptyMutation, EmptySubscription, Object, Schema};
+
+    fn date(y: i32, m: u32, d: u32) -> Date {
+        Date(NaiveDate::from_ymd_opt(y, m, d).unwrap())
+    }
+
+    #[test]
+    fn test_date_range() -> anyhow::Result<()> {
+        let range = DateRange::new(Some(date(2024, 1, 1)), Some(date(2024, 2, 1)), true, false)?;
+        assert!(range.contains(&date(2024, 1, 1).0));
+        assert!(range.contains(&date(2024, 1, 31).0));
+        assert!(!range.contains(&date(2024, 2, 1).0));
+        assert_eq!(range.to_pg_text(), "[2024-01-01,2024-02-01)");
+        assert_eq!(
+            DateRange::from_pg_text("[2024-01-01,2024-02-01)"),
+            Some(range)
+        );
+
+        // 制限なしの側は含まない
+        let range = DateRange::new(None, Some(date(2024, 2, 1)), true, true)?;
+        assert!(!range.start_inclusive && range.end_inclusive);
+        assert!(range.contains(&date(1900, 1, 1).0));
+        assert_eq!(range.to_pg_text(), "(,2024-02-01]");
+        assert_eq!(DateRange::from_pg_text("(,2024-02-01]"), Some(range));
+
+        assert!(
+            DateRange::new(Some(date(2024, 2, 1)), Some(date(2024, 1, 1)), true, false).is_err()
+        );
+        assert!(
+            DateRange::new(Some(date(2024, 1, 1)), Some(date(2024, 1, 1)), true, false).is_err()
+        );
+        assert!(DateRange::new(Some(date(2024, 1, 1)), Some(date(2024, 1, 1)), true, true).is_ok());
+
+        let empty = DateRange::from_pg_text("empty").unwrap();
+        assert_eq!(empty, DateRange::empty());
+        assert!(empty.is_empty && empty.start.is_none() && empty.end.is_none());
+        assert!(!empty.contains(&date(2024, 1, 1).0));
+        assert_eq!(empty.to_pg_text(), "empty");
+        // 制限なしとは区別する
+        assert!(DateRange::new(None, None, false, false)?.contains(&date(2024, 1, 1).0));
+        assert_eq!(DateRange::from_pg_text("[2024-01-01,"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_date_time_range() -> anyhow::Result<()> {
+        let start = DateTimeRfc3339("2024-01-01T00:00:00Z".parse()?);
+        let range = DateTimeRange::new(Some(start.clone()), None, true, false)?;
+        assert_eq!(range.to_pg_text(), r#"["2024-01-01T00:00:00+00:00",)"#);
+        assert_eq!(
+            DateTimeRange::from_pg_text(r#"["2024-01-01 09:00:00+09",infinity)"#),
+            Some(range.clone())
+        );
+        assert_eq!(
+            DateTimeRange::from_pg_text(r#"["2023-12-31 23:59:59.5+00","2024-01-01 00:00:00+00"]"#)
+                .map(|x| x.end),
+            Some(Some(start.clone()))
+        );
+        assert!(range.contains(&start.0));
+        assert!(!range.contains(&(start.0 - chrono::TimeDelta::seconds(1))));
+        assert_eq!(
+            DateTimeRange::from_pg_text("empty"),
+            Some(DateTimeRange::empty())
+        );
+        assert!(!DateTimeRange::empty().contains(&start.0));
+        Ok(())
+    }
+
+    struct Query;
+
+    #[Object]
+    impl Query {
+        async fn days(&self, period: DateRangeInput) -> async_graphql::Result<DateRange> {
+            DateRange::try_from(period)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_input() -> anyhow::Result<()> {
+        let schema = Schema::new(Query, EmptyMutation, EmptySubscription);
+        let resp = schema
+            .execute(r#"{ days(period: { start: "2024-01-01", end: "2024-01-31" }) { start end startInclusive endInclusive isEmpty } }"#)
+            .await;
+        assert!(resp.errors.is_empty(), "{:?}", resp.errors);
+        assert_eq!(
+            resp.data,
+            async_graphql::value!({
+                "days": {
+                    "start": "2024-01-01",
+                    "end": "2024-01-31",
+                    "startInclusive": true,
+                    "endInclusive": false,
+                    "isEmpty": false,
+                }
+            })
+        );
+
+        let resp = schema
+            .execute(r#"{ days(period: { start: "2024-02-01", end: "2024-01-01" }) { start } }"#)
+            .await;
+        assert_eq!(
+            ErrorCode::from_server_error(&resp.errors[0]),
+            Some(ErrorCode::BadUserInput)
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+// 入力されたオフセットをそのまま持つRFC 3339の日時
+// DateTimeRfc3339はUTCにしてしまうので、ユーザーの壁時計の時刻が要るときはこちらを使う
+//
+// ```ignore
+// async fn create_event(&self, start: DateTimeOffset, tz: TimeZone) -> Result<Event> {
+//     let local = start.with_time_zone(&tz); // tzでの同じ時刻
+//     let date = local.local_date();
+//     let utc: DateTimeRfc3339 = start.into();
+// }
+// ```
+// PartialEqはchronoと同じく時刻だけを比べる (オフセットが違っても同じ時刻なら等しい)
+use async_graphql::{InputValueError, InputValueResult, Scalar, ScalarType, Value};
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::date_time_rfc3339::DateTimeRfc3339;
+use super::time_zone::TimeZone;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct DateTimeOffset(#[serde(with = "datetime_serializer")] pub DateTime<FixedOffset>);
+
+impl DateTimeOffset {
+    pub fn new(t: DateTime<FixedOffset>) -> Self {
+        DateTimeOffset(t)
+    }
+
+    pub fn to_utc(&self) -> DateTime<Utc> {
+        self.0.to_utc()
+    }
+
+    pub fn offset(&self) -> FixedOffset {
+        *self.0.offset()
+    }
+
+    // 同じ時刻をtzのオフセットで表したもの
+    pub fn with_time_zone(&self, tz: &TimeZone) -> Self {
+        DateTimeOffset(self.0.with_timezone(&tz.0).fixed_offset())
+    }
+
+    // 持っているオフセットでの日付
+    pub fn local_date(&self) -> NaiveDate {
+        self.0.date_naive()
+    }
+}
+
+impl From<DateTimeRfc3339> for DateTimeOffset {
+    fn from(value: DateTimeRfc3339) -> Self {
+        DateTimeOffset(value.0.fixed_offset())
+    }
+}
+
+impl From<DateTimeOffset> for DateTimeRfc3339 {
+    fn from(value: DateTimeOffset) -> Self {
+        DateTimeRfc3339(value.to_utc())
+    }
+}
+
+#[Scalar]
+impl ScalarType for DateTimeOffset {
+    fn parse(value: Value) -> InputValueResult<Self> {
+        if let Value::String(value) = &value {
+            DateTime::parse_from_rfc3339(value)
+                .map(DateTimeOffset)
+                .or(Err(InputValueError::custom("invalid rfc3339 format")))
+        } else {
+            Err(InputValueError::expected_type(value))
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        Value::String(self.0.to_rfc3339())
+    }
+}
+
+mod datetime_serializer {
+    use chrono::{DateTime, FixedOffset};
+    use serde::{de::Error, Deserialize, Deserializer, Serialize as _, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        time: &DateTime<FixedOffset>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        time.to_rfc3339().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<DateTime<FixedOffset>, D::Error> {
+        let date_str: String = Deserialize::deserialize(deserializer)?;
+        DateTime::parse_from_rfc3339(&date_str).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize() -> anyhow::Result<()> {
+        let value = Value::String("2024-10-15T23:30:00+09:00".to_string());
+        let d = DateTimeOffset::parse(value.clone()).unwrap();
+        assert_eq!(d.to_value(), value);
+        assert_eq!(d.offset(), FixedOffset::east_opt(9 * 3600).unwrap());
+        assert_eq!(
+            d.local_date(),
+            NaiveDate::from_ymd_opt(2024, 10, 15).unwrap()
+        );
+
+        let json = serde_json::to_string(&d)?;
+        assert_eq!(json, r#""2024-10-15T23:30:00+09:00""#);
+        let parsed: DateTimeOffset = serde_json::from_str(&json)?;
+        assert_eq!(parsed.offset(), d.offset());
+
+        assert!(DateTimeOffset::parse(Value::String("2024-10-15 23:30".to_string())).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert() -> anyhow::Result<()> {
+        let d =
+            DateTimeOffset::parse(Value::String("2024-10-15T23:30:00+09:00".to_string())).unwrap();
+        let utc: DateTimeRfc3339 = d.clone().into();
+        assert_eq!(
+            utc.to_value(),
+            Value::String("2024-10-15T14:30:00+00:00".to_string())
+        );
+        assert_eq!(DateTimeOffset::from(utc), d);
+
+        let ny = d.with_time_zone(&TimeZone(chrono_tz::America::New_York));
+        assert_eq!(
+            ny.to_value(),
+            Value::String("2024-10-15T10:30:00-04:00".to_string())
+        );
+        assert_eq!(ny, d);
+        Ok(())
+    }
+}
@@ -1,36 +1,410 @@
-use sea_orm::DatabaseConnection;
-use std::sync::Arc;
+use sea_orm::{
+    prelude::async_trait::async_trait, ConnectionTrait, DatabaseConnection, DbBackend, DbErr,
+    ExecResult, QueryResult, Statement,
+};
+use std::future::Future;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
 
 use async_graphql::{dataloader::DataLoader, Context};
 
+use super::sql;
+
 type Result<T> = anyhow::Result<T>;
 
+// これより遅いクエリはwarnのログを出す (DB_SLOW_QUERY_MS)
+pub const DEFAULT_SLOW_QUERY: Duration = Duration::from_millis(500);
+
 pub struct Database {
     pub connection: Arc<DatabaseConnection>,
 }
 
+// DB_SLOW_QUERY_MSは最初に使うときに一度だけ読む
+fn slow_query() -> Result<Duration> {
+    static SLOW_QUERY: OnceLock<Duration> = OnceLock::new();
+    if let Some(slow_query) = SLOW_QUERY.get() {
+        return Ok(*slow_query);
+    }
+    let slow_query = match std::env::var("DB_SLOW_QUERY_MS") {
+        Ok(value) => Duration::from_millis(
+            value
+                .parse()
+                .map_err(|e| anyhow::anyhow!("invalid DB_SLOW_QUERY_MS={value}: {e}"))?,
+        ),
+        Err(_) => DEFAULT_SLOW_QUERY,
+    };
+    Ok(*SLOW_QUERY.get_or_init(|| slow_query))
+}
+
 impl Database {
-    pub async fn new_from_env() -> Result<DataLoader<Self>> {
-        Ok(DataLoader::new(
+    pub fn new(connection: DatabaseConnection) -> DataLoader<Self> {
+        DataLoader::new(
             Self {
-                connection: Arc::new(
-                    sea_orm::Database::connect(std::env::var("DATABASE_URL")?).await?,
-                ),
+                connection: Arc::new(connection),
             },
             tokio::task::spawn,
+        )
+    }
+
+    pub async fn new_from_env() -> Result<DataLoader<Self>> {
+        // 値が不正なら起動時にエラーにする
+        slow_query()?;
+        Ok(Self::new(
+            sea_orm::Database::connect(std::env::var("DATABASE_URL")?).await?,
         ))
     }
 
+    // spanやslow queryのログが要らないとき。普段はtraced()を使う
     #[inline]
     pub fn get_connection(&self) -> &DatabaseConnection {
         &self.connection
     }
+
+    pub fn traced(&self) -> TracedConnection<'_> {
+        TracedConnection::new(self.get_connection())
+            .with_slow_query(slow_query().unwrap_or(DEFAULT_SLOW_QUERY))
+    }
+
+    // コネクションプールの使用状況 (db.client.connections.usage) をobservable gaugeで記録する
+    #[cfg(feature = "with-opentelemetry")]
+    pub fn register_pool_metrics(&self) {
+        use opentelemetry::KeyValue;
+
+        let connection = self.connection.clone();
+        opentelemetry::global::meter(super::metrics::METER_NAME)
+            .u64_observable_gauge("db.client.connections.usage")
+            .with_description("Number of connections that are currently in the pool by state")
+            .with_callback(move |observer| {
+                if let DatabaseConnection::SqlxPostgresPoolConnection(_) = connection.as_ref() {
+                    let pool = connection.get_postgres_connection_pool();
+                    let idle = pool.num_idle() as u64;
+                    let size = u64::from(pool.size());
+                    observer.observe(idle, &[KeyValue::new("state", "idle")]);
+                    observer.observe(size.saturating_sub(idle), &[KeyValue::new("state", "used")]);
+                }
+            })
+            .init();
+    }
 }
 
 pub fn get_data_loader_from_ctx<'a>(ctx: &Context<'a>) -> &'a DataLoader<Database> {
     ctx.data_unchecked::<DataLoader<Database>>()
 }
 
+// begin()やstream()も使えるsea-ormのコネクション。クエリはspanにならない
 pub fn get_db_from_ctx<'a>(ctx: &Context<'a>) -> &'a DatabaseConnection {
     get_data_loader_from_ctx(ctx).loader().get_connection()
 }
+
+// クエリがspanやslow queryのログになるコネクション。`.all(&get_traced_db_from_ctx(ctx))` のように使う
+// トランザクションの中のクエリは `TracedConnection::new(&txn)` で包む
+pub fn get_traced_db_from_ctx<'a>(ctx: &Context<'a>) -> TracedConnection<'a> {
+    get_data_loader_from_ctx(ctx).loader().traced()
+}
+
+// クエリごとにspanとbreadcrumbを作るConnectionTrait
+// with-opentelemetry: 今のContextの子に `SELECT users` のようなクライアントのspanを作る
+//   (db.system, リテラルを隠したdb.statement, db.operation, db.sql.table, 行数)
+// with-sentry: 今のHubのscopeにtransaction (GraphQLのoperation) があればその子spanにする。
+//   breadcrumbにはリテラルを隠したSQL、時間、行数を残す
+// slow_queryより遅いものはSQLとtrace_idをwarnのログに出す
+// spanのtracerはwith_tracerで変えられる (なければグローバルの "sea-orm")
+pub struct TracedConnection<'a, C = DatabaseConnection> {
+    connection: &'a C,
+    slow_query: Duration,
+    #[cfg(feature = "with-opentelemetry")]
+    tracer: Option<Arc<opentelemetry::global::BoxedTracer>>,
+}
+
+#[cfg(feature = "with-opentelemetry")]
+fn db_system(backend: DbBackend) -> &'static str {
+    match backend {
+        DbBackend::Postgres => "postgresql",
+        DbBackend::MySql => "mysql",
+        DbBackend::Sqlite => "sqlite",
+    }
+}
+
+impl<'a, C: ConnectionTrait + Sync> TracedConnection<'a, C> {
+    pub fn new(connection: &'a C) -> Self {
+        Self {
+            connection,
+            slow_query: DEFAULT_SLOW_QUERY,
+            #[cfg(feature = "with-opentelemetry")]
+            tracer: None,
+        }
+    }
+
+    pub fn with_slow_query(self, slow_query: Duration) -> Self {
+        Self { slow_query, ..self }
+    }
+
+    // グローバルに登録していないTracerProviderを使うとき
+    #[cfg(feature = "with-opentelemetry")]
+    pub fn with_tracer(self, tracer: opentelemetry::global::BoxedTracer) -> Self {
+        Self {
+            tracer: Some(Arc::new(tracer)),
+            ..self
+        }
+    }
+
+    #[cfg(feature = "with-opentelemetry")]
+    fn start_span(&self, statement: &str) -> opentelemetry::Context {
+        use opentelemetry::trace::{SpanKind, TraceContextExt, Tracer};
+        use opentelemetry::KeyValue;
+
+        let system = db_system(self.get_database_backend());
+        let operation = sql::operation(statement);
+        let table = sql::table(statement);
+        let name = match (operation.as_deref(), table.as_deref()) {
+            (Some(operation), Some(table)) => format!("{operation} {table}"),
+            (Some(operation), None) => operation.to_string(),
+            _ => system.to_string(),
+        };
+        let mut attributes = vec![
+            KeyValue::new("db.system", system),
+            KeyValue::new("db.statement", statement.to_string()),
+        ];
+        if let Some(operation) = operation {
+            attributes.push(KeyValue::new("db.operation", operation));
+        }
+        if let Some(table) = table {
+            attributes.push(KeyValue::new("db.sql.table", table));
+        }
+        let tracer = match self.tracer.as_ref() {
+            Some(tracer) => tracer.clone(),
+            None => Arc::new(opentelemetry::global::tracer("sea-orm")),
+        };
+        let span = tracer
+            .span_builder(name)
+            .with_kind(SpanKind::Client)
+            .with_attributes(attributes)
+            .start_with_context(tracer.as_ref(), &opentelemetry::Context::current());
+        opentelemetry::Context::current_with_span(span)
+    }
+
+    async fn traced<T>(
+        &self,
+        sql: &str,
+        query: impl Future<Output = std::result::Result<T, DbErr>>,
+        rows: impl FnOnce(&T) -> u64,
+    ) -> std::result::Result<T, DbErr> {
+        let statement = sql::redact_sql(sql);
+        #[cfg(feature = "with-opentelemetry")]
+        let cx = self.start_span(&statement);
+        #[cfg(feature = "with-sentry")]
+        let span = sentry::configure_scope(|scope| scope.get_span())
+            .map(|parent| parent.start_child("db.sql.query", &statement));
+        let start = Instant::now();
+
+        let result = query.await;
+        let elapsed = start.elapsed();
+        let rows = result.as_ref().ok().map(rows);
+
+        #[cfg(feature = "with-opentelemetry")]
+        let trace_id = {
+            use opentelemetry::trace::{Status, TraceContextExt};
+            use opentelemetry::KeyValue;
+
+            let span = cx.span();
+            if let Some(rows) = rows {
+                span.set_attribute(KeyValue::new("db.response.rows", rows as i64));
+            }
+            if let Err(e) = result.as_ref() {
+                span.set_attribute(KeyValue::new("error.type", "DbErr"));
+                span.set_status(Status::error(e.to_string()));
+            }
+            span.end();
+            let span_context = span.span_context();
+            span_context
+                .is_valid()
+                .then(|| span_context.trace_id().to_string())
+        };
+        #[cfg(not(feature = "with-opentelemetry"))]
+        let trace_id: Option<String> = None;
+
+        if elapsed >= self.slow_query {
+            tracing::warn!(
+                statement,
+                elapsed_ms = elapsed.as_millis() as u64,
+                rows,
+                trace_id,
+                "slow query"
+            );
+        }
+
+        #[cfg(feature = "with-sentry")]
+        {
+            if let Some(span) = span {
+                span.set_status(if result.is_ok() {
+                    sentry::protocol::SpanStatus::Ok
+                } else {
+                    sentry::protocol::SpanStatus::InternalError
+                });
+                span.finish();
+            }
+            super::sentry_breadcrumb::add(
+                "db.sql",
+                "query",
+                statement,
+                elapsed,
+                rows.map(|x| ("rows", serde_json::Value::from(x))),
+                result.is_err(),
+            );
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl<C: ConnectionTrait + Sync> ConnectionTrait for TracedConnection<'_, C> {
+    fn get_database_backend(&self) -> DbBackend {
+        self.connection.get_database_backend()
+    }
+
+    async fn execute(&self, stmt: Statement) -> std::result::Result<ExecResult, DbErr> {
+        let sql = stmt.sql.clone();
+        self.traced(
+            &sql,
+            self.connection.execute(stmt),
+            ExecResult::rows_affected,
+        )
+        .await
+    }
+
+    async fn execute_unprepared(&self, sql: &str) -> std::result::Result<ExecResult, DbErr> {
+        self.traced(
+            sql,
+            self.connection.execute_unprepared(sql),
+            ExecResult::rows_affected,
+        )
+        .await
+    }
+
+    async fn query_one(&self, stmt: Statement) -> std::result::Result<Option<QueryResult>, DbErr> {
+        let sql = stmt.sql.clone();
+        self.traced(&sql, self.connection.query_one(stmt), |x| {
+            u64::from(x.is_some())
+        })
+        .await
+    }
+
+    async fn query_all(&self, stmt: Statement) -> std::result::Result<Vec<QueryResult>, DbErr> {
+        let sql = stmt.sql.clone();
+        self.traced(&sql, self.connection.query_all(stmt), |x| x.len() as u64)
+            .await
+    }
+
+    fn support_returning(&self) -> bool {
+        self.connection.support_returning()
+    }
+
+    fn is_mock_connection(&self) -> bool {
+        self.connection.is_mock_connection()
+    }
+}
+
+#[cfg(all(test, feature = "with-axum", feature = "with-opentelemetry"))]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_traced_spans() -> anyhow::Result<()> {
+        use crate::tools::{
+            otel_setup::build_tracer_provider,
+            otlp_collector::OtlpCollector,
+            tracing_config::{ExporterConfig, Protocol, TracingConfig},
+        };
+        use opentelemetry::global::BoxedTracer;
+        use opentelemetry::trace::{
+            FutureExt, Span as _, TraceContextExt, Tracer, TracerProvider as _,
+        };
+        use sea_orm::{MockDatabase, MockExecResult, Value};
+        use std::collections::BTreeMap;
+
+        let collector = OtlpCollector::start().await?;
+        let config = TracingConfig {
+            exporter: Some(ExporterConfig {
+                protocol: Protocol::HttpJson,
+                ..ExporterConfig::new(collector.endpoint())
+            }),
+            ..Default::default()
+        };
+        let provider = build_tracer_provider(&config)?.unwrap();
+
+        let connection = MockDatabase::new(DbBackend::Postgres)
+            .append_query_results([[
+                BTreeMap::from([("id", Value::from(1))]),
+                BTreeMap::from([("id", Value::from(2))]),
+            ]])
+            .append_exec_results([MockExecResult {
+                last_insert_id: 0,
+                rows_affected: 3,
+            }])
+            .into_connection();
+        // すべてslow queryとして扱う
+        let db = TracedConnection::new(&connection)
+            .with_slow_query(Duration::ZERO)
+            .with_tracer(BoxedTracer::new(Box::new(provider.tracer("sea-orm"))));
+
+        let tracer = provider.tracer("test");
+        let parent = tracer.start("parent");
+        let parent_span_id = parent.span_context().span_id().to_string();
+        let cx = opentelemetry::Context::current_with_span(parent);
+        async {
+            let rows = db
+                .query_all(Statement::from_string(
+                    DbBackend::Postgres,
+                    r#"SELECT "id" FROM "user" WHERE "name" = 'alice'"#,
+                ))
+                .await?;
+            assert_eq!(rows.len(), 2);
+            // トランザクションの中のクエリも包めばspanになる
+            let txn = sea_orm::TransactionTrait::begin(&connection).await?;
+            TracedConnection::new(&txn)
+                .with_tracer(BoxedTracer::new(Box::new(provider.tracer("sea-orm"))))
+                .execute_unprepared(r#"UPDATE "user" SET "weight" = 10"#)
+                .await?;
+            txn.commit().await?;
+            anyhow::Ok(())
+        }
+        .with_context(cx.clone())
+        .await?;
+        cx.span().end();
+
+        let provider = tokio::task::spawn_blocking(move || {
+            assert!(provider.force_flush().iter().all(|x| x.is_ok()));
+            provider
+        })
+        .await?;
+
+        let spans = collector.spans();
+        let attribute = |span: &serde_json::Value, key: &str| {
+            span["attributes"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|x| x["key"] == key)
+                .map(|x| x["value"].clone())
+                .unwrap_or_default()
+        };
+        let span = spans.iter().find(|x| x["name"] == "SELECT user").unwrap();
+        // SpanKind::Client
+        assert_eq!(span["kind"], 3);
+        assert_eq!(span["parentSpanId"], parent_span_id);
+        assert_eq!(attribute(span, "db.system")["stringValue"], "postgresql");
+        assert_eq!(attribute(span, "db.operation")["stringValue"], "SELECT");
+        assert_eq!(attribute(span, "db.sql.table")["stringValue"], "user");
+        assert_eq!(
+            attribute(span, "db.statement")["stringValue"],
+            r#"SELECT "id" FROM "user" WHERE "name" = ?"#
+        );
+        assert_eq!(attribute(span, "db.response.rows")["intValue"], "2");
+
+        let span = spans.iter().find(|x| x["name"] == "UPDATE user").unwrap();
+        assert_eq!(attribute(span, "db.response.rows")["intValue"], "3");
+
+        tokio::task::spawn_blocking(move || provider.shutdown()).await??;
+        Ok(())
+    }
+}
@@ -0,0 +1,327 @@
+// Query/Mutationのルートフィールドには必ず @public か @guarded をつけさせる
+//
+// async-graphqlのregistryにはguardの情報が残らず、guard属性のGuardはresolverの中で本体の直前に動くので、
+// 外からは「印だけでguardがない」ものを止められない。そこで @guarded には名前つきのPolicyを書かせ
+// `#[graphql(directive = guarded::apply("admin".to_string()))]`
+// extensionがresolverを動かす前にそのPolicyを確認する。登録されていない名前はschemaを作るときにエラーにする
+//
+// ```ignore
+// let schema = DefaultDeny::new()
+//     .with_policy("authenticated", Authenticated)
+//     .with_policy("admin", RoleGuard::new("admin"))
+//     .finish(schema_builder)?;
+// ```
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+
+use async_graphql::{
+    extensions::{Extension, ExtensionContext, ExtensionFactory, NextResolve, ResolveInfo},
+    parser::types::{TypeDefinition, TypeKind, TypeSystemDefinition},
+    ObjectType, Schema, SchemaBuilder, ServerResult, SubscriptionType, TypeDirective, Value,
+};
+
+#[TypeDirective(location = "FieldDefinition")]
+pub fn public() {}
+
+#[TypeDirective(location = "FieldDefinition")]
+pub fn guarded(policy: String) {}
+
+// resolverより前に動くので、Contextではなくリクエストのdataだけで判断する
+pub trait Policy: Send + Sync + 'static {
+    fn check(&self, ctx: &ExtensionContext<'_>) -> async_graphql::Result<()>;
+}
+
+impl<F> Policy for F
+where
+    F: Fn(&ExtensionContext<'_>) -> async_graphql::Result<()> + Send + Sync + 'static,
+{
+    fn check(&self, ctx: &ExtensionContext<'_>) -> async_graphql::Result<()> {
+        self(ctx)
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct DefaultDeny {
+    policies: HashMap<String, Arc<dyn Policy>>,
+    // `型.フィールド` -> Policyの名前。schemaを作った後に入れる
+    guarded: Arc<OnceLock<HashMap<String, String>>>,
+}
+
+impl DefaultDeny {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_policy(mut self, name: impl Into<String>, policy: impl Policy) -> Self {
+        self.policies.insert(name.into(), Arc::new(policy));
+        self
+    }
+
+    pub fn finish<Q, M, S>(
+        self,
+        schema_builder: SchemaBuilder<Q, M, S>,
+    ) -> anyhow::Result<Schema<Q, M, S>>
+    where
+        Q: ObjectType + 'static,
+        M: ObjectType + 'static,
+        S: SubscriptionType + 'static,
+    {
+        let schema = schema_builder.extension(self.clone()).finish();
+        check_default_deny(&schema)?;
+        let fields: HashMap<_, _> = root_fields(&schema, guarded_fields)?.into_iter().collect();
+        let mut unknown = fields
+            .iter()
+            .filter(|(_, policy)| !self.policies.contains_key(*policy))
+            .map(|(field, policy)| format!("{field} ({policy})"))
+            .collect::<Vec<_>>();
+        if !unknown.is_empty() {
+            unknown.sort();
+            return Err(anyhow::anyhow!(
+                "fields guarded by unknown policy: {}",
+                unknown.join(", ")
+            ));
+        }
+        let _ = self.guarded.set(fields);
+        Ok(schema)
+    }
+}
+
+impl ExtensionFactory for DefaultDeny {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(self.clone())
+    }
+}
+
+#[async_graphql::async_trait::async_trait]
+impl Extension for DefaultDeny {
+    async fn resolve(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        info: ResolveInfo<'_>,
+        next: NextResolve<'_>,
+    ) -> ServerResult<Option<Value>> {
+        let field = format!("{}.{}", info.parent_type, info.name);
+        let Some(name) = self.guarded.get().and_then(|fields| fields.get(&field)) else {
+            return next.run(ctx, info).await;
+        };
+        // finishで名前は確認済み
+        let policy = &self.policies[name];
+        if let Err(err) = policy.check(ctx) {
+            let mut err = err.into_server_error(Default::default());
+            err.locations.clear();
+            return Err(err);
+        }
+        next.run(ctx, info).await
+    }
+}
+
+// @guarded(policy: "...") のついたフィールドと、そのPolicyの名前
+fn guarded_fields(ty: &TypeDefinition) -> Vec<(String, String)> {
+    let TypeKind::Object(object) = &ty.kind else {
+        return vec![];
+    };
+    object
+        .fields
+        .iter()
+        .filter_map(|field| {
+            let directive = field
+                .node
+                .directives
+                .iter()
+                .find(|x| x.node.name.node == "guarded")?;
+            let policy = match directive.node.get_argument("policy").map(|x| &x.node) {
+                Some(async_graphql::Value::String(policy)) => policy.clone(),
+                _ => String::new(),
+            };
+            Some((format!("{}.{}", ty.name.node, field.node.name.node), policy))
+        })
+        .collect()
+}
+
+fn unmarked_fields(ty: &TypeDefinition) -> Vec<String> {
+    let TypeKind::Object(object) = &ty.kind else {
+        return vec![];
+    };
+    object
+        .fields
+        .iter()
+        .filter(|field| !field.node.name.node.starts_with('_'))
+        .filter(|field| {
+            !field.node.directives.iter().any(|directive| {
+                directive.node.name.node == "public" || directive.node.name.node == "guarded"
+            })
+        })
+        .map(|field| format!("{}.{}", ty.name.node, field.node.name.node))
+        .collect()
+}
+
+fn root_fields<Q, M, S, T>(
+    schema: &Schema<Q, M, S>,
+    f: impl Fn(&TypeDefinition) -> Vec<T>,
+) -> anyhow::Result<Vec<T>>
+where
+    Q: ObjectType + 'static,
+    M: ObjectType + 'static,
+    S: SubscriptionType + 'static,
+{
+    let roots = [Q::type_name(), M::type_name()];
+    let document = async_graphql::parser::parse_schema(schema.sdl())?;
+    Ok(document
+        .definitions
+        .iter()
+        .filter_map(|definition| match definition {
+            TypeSystemDefinition::Type(ty)
+                if roots.iter().any(|x| x == ty.node.name.node.as_str()) =>
+            {
+                Some(f(&ty.node))
+            }
+            _ => None,
+        })
+        .flatten()
+        .collect())
+}
+
+pub fn check_default_deny<Q, M, S>(schema: &Schema<Q, M, S>) -> anyhow::Result<()>
+where
+    Q: ObjectType + 'static,
+    M: ObjectType + 'static,
+    S: SubscriptionType + 'static,
+{
+    let unmarked = root_fields(schema, unmarked_fields)?;
+
+    if unmarked.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "fields without guard or public marker: {}",
+            unmarked.join(", ")
+        ))
+    }
+}
+
+// Policyを使わない (@guarded のない) schema用
+pub fn finish_default_deny<Q, M, S>(
+    schema_builder: SchemaBuilder<Q, M, S>,
+) -> anyhow::Result<Schema<Q, M, S>>
+where
+    Q: ObjectType + 'static,
+    M: ObjectType + 'static,
+    S: SubscriptionType + 'static,
+{
+    DefaultDeny::new().finish(schema_builder)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::tools::error_code::ErrorCode;
+    use async_graphql::{Context, EmptySubscription, Object};
+
+    struct Query;
+
+    #[Object]
+    impl Query {
+        #[graphql(directive = public::apply())]
+        async fn open(&self) -> i32 {
+            1
+        }
+
+        #[graphql(directive = guarded::apply("allow".to_string()))]
+        async fn closed(&self) -> i32 {
+            2
+        }
+    }
+
+    struct Mutation;
+
+    #[Object]
+    impl Mutation {
+        // guard属性はなく、印だけ
+        #[graphql(directive = guarded::apply("deny".to_string()))]
+        async fn increment(&self, ctx: &Context<'_>) -> usize {
+            ctx.data_unchecked::<Arc<AtomicUsize>>()
+                .fetch_add(1, Ordering::SeqCst)
+        }
+    }
+
+    struct Unmarked;
+
+    #[Object]
+    impl Unmarked {
+        #[graphql(directive = public::apply())]
+        async fn open(&self) -> i32 {
+            1
+        }
+
+        async fn forgotten(&self) -> i32 {
+            2
+        }
+    }
+
+    fn deny(_: &ExtensionContext<'_>) -> async_graphql::Result<()> {
+        Err(ErrorCode::Forbidden.error("access denied"))
+    }
+
+    #[test]
+    fn test_default_deny() -> anyhow::Result<()> {
+        DefaultDeny::new()
+            .with_policy("allow", |_: &ExtensionContext<'_>| Ok(()))
+            .with_policy("deny", deny)
+            .finish(Schema::build(Query, Mutation, EmptySubscription).enable_federation())?;
+
+        let Err(err) = finish_default_deny(Schema::build(
+            Unmarked,
+            async_graphql::EmptyMutation,
+            EmptySubscription,
+        )) else {
+            panic!();
+        };
+        assert_eq!(
+            err.to_string(),
+            "fields without guard or public marker: Unmarked.forgotten"
+        );
+
+        let Err(err) = DefaultDeny::new()
+            .with_policy("allow", |_: &ExtensionContext<'_>| Ok(()))
+            .finish(Schema::build(Query, Mutation, EmptySubscription))
+        else {
+            panic!();
+        };
+        assert_eq!(
+            err.to_string(),
+            "fields guarded by unknown policy: Mutation.increment (deny)"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_policy_runs_before_resolver() -> anyhow::Result<()> {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let schema = DefaultDeny::new()
+            .with_policy("allow", |_: &ExtensionContext<'_>| Ok(()))
+            .with_policy("deny", deny)
+            .finish(Schema::build(Query, Mutation, EmptySubscription).data(counter.clone()))?;
+
+        let resp = schema.execute("{ open closed alias: closed }").await;
+        assert!(resp.errors.is_empty(), "{:?}", resp.errors);
+        assert_eq!(
+            resp.data,
+            async_graphql::value!({ "open": 1, "closed": 2, "alias": 2 })
+        );
+
+        let resp = schema.execute("mutation { increment }").await;
+        assert_eq!(resp.data, Value::Null);
+        assert_eq!(
+            resp.errors
+                .iter()
+                .map(ErrorCode::from_server_error)
+                .collect::<Vec<_>>(),
+            vec![Some(ErrorCode::Forbidden)]
+        );
+        // resolverは動いていない
+        assert_eq!(counter.load(Ordering::SeqCst), 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,352 @@
+// ISO 8601の期間 (P1Y2M3DT4H5M6.5S) のscalar
+// Postgresのintervalと同じく月、日、マイクロ秒を別に持つ (1か月や1日の長さは日付とタイムゾーンで変わるため)
+// 年は12か月、週は7日として扱う。符号は全体 (-P1D) にも各要素 (P-1DT2H) にも付けられる
+//
+// ```ignore
+// #[sea_orm(column_type = "Interval(None, None)", select_as = "text", save_as = "interval")]
+// pub duration: Duration,
+// ```
+// intervalの読み込みはPostgresの標準の出力 (1 year 2 mons -3 days +04:05:06.5) も受け付ける
+use std::fmt;
+use std::str::FromStr;
+
+use async_graphql::{InputValueError, InputValueResult, Scalar, ScalarType, Value};
+use serde::{Deserialize, Serialize};
+
+const MICROS_PER_SECOND: i64 = 1_000_000;
+const MICROS_PER_MINUTE: i64 = 60 * MICROS_PER_SECOND;
+const MICROS_PER_HOUR: i64 = 60 * MICROS_PER_MINUTE;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(into = "String", try_from = "String")]
+pub struct Duration {
+    pub months: i32,
+    pub days: i32,
+    pub microseconds: i64,
+}
+
+impl Duration {
+    pub fn new(months: i32, days: i32, microseconds: i64) -> Self {
+        Self {
+            months,
+            days,
+            microseconds,
+        }
+    }
+
+    pub fn from_time_delta(t: chrono::TimeDelta) -> Option<Self> {
+        Some(Self::new(0, 0, t.num_microseconds()?))
+    }
+
+    // 月があると長さが決まらないのでNone。1日は24時間とする
+    pub fn to_time_delta(self) -> Option<chrono::TimeDelta> {
+        if self.months != 0 {
+            return None;
+        }
+        chrono::TimeDelta::try_days(self.days.into())?
+            .checked_add(&chrono::TimeDelta::microseconds(self.microseconds))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn to_pg_text(self) -> String {
+        self.to_string()
+    }
+
+    pub fn from_pg_text(value: &str) -> Option<Self> {
+        parse_iso(value).or_else(|| parse_postgres(value))
+    }
+
+    fn from_parts(months: i64, days: i64, microseconds: i64) -> Option<Self> {
+        Some(Self::new(
+            months.try_into().ok()?,
+            days.try_into().ok()?,
+            microseconds,
+        ))
+    }
+}
+
+// 秒をマイクロ秒にする (小数点以下は6桁まで、それより下は切り捨て)
+fn parse_seconds(value: &str) -> Option<i64> {
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(value) => (true, value),
+        None => (false, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let (integer, fraction) = value.split_once(['.', ',']).unwrap_or((value, ""));
+    if integer.is_empty()
+        || !integer
+            .bytes()
+            .chain(fraction.bytes())
+            .all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+    let fraction: String = fraction.chars().chain("000000".chars()).take(6).collect();
+    let micros = integer
+        .parse::<i64>()
+        .ok()?
+        .checked_mul(MICROS_PER_SECOND)?
+        .checked_add(fraction.parse::<i64>().ok()?)?;
+    Some(if negative { -micros } else { micros })
+}
+
+// 数字と単位の組に分ける。単位はunitsの順に1回ずつしか使えない
+fn components<'a>(value: &'a str, units: &str) -> Option<Vec<(&'a str, char)>> {
+    let mut result = Vec::new();
+    let mut start = 0;
+    let mut last = None;
+    for (i, c) in value.char_indices() {
+        if !c.is_ascii_alphabetic() {
+            continue;
+        }
+        let index = units.find(c)?;
+        if last.is_some_and(|last| index <= last) || start == i {
+            return None;
+        }
+        result.push((&value[start..i], c));
+        start = i + 1;
+        last = Some(index);
+    }
+    (start == value.len()).then_some(result)
+}
+
+fn parse_iso(value: &str) -> Option<Duration> {
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(value) => (true, value),
+        None => (false, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let value = value.strip_prefix('P')?;
+    let (date, time) = match value.split_once('T') {
+        Some((date, time)) if !time.is_empty() => (date, time),
+        Some(_) => return None,
+        None => (value, ""),
+    };
+    if date.is_empty() && time.is_empty() {
+        return None;
+    }
+    let (mut months, mut days, mut micros) = (0i64, 0i64, 0i64);
+    for (number, unit) in components(date, "YMWD")? {
+        let number: i64 = number.parse().ok()?;
+        match unit {
+            'Y' => months = months.checked_add(number.checked_mul(12)?)?,
+            'M' => months = months.checked_add(number)?,
+            'W' => days = days.checked_add(number.checked_mul(7)?)?,
+            _ => days = days.checked_add(number)?,
+        }
+    }
+    for (number, unit) in components(time, "HMS")? {
+        let number = match unit {
+            'H' => number.parse::<i64>().ok()?.checked_mul(MICROS_PER_HOUR)?,
+            'M' => number.parse::<i64>().ok()?.checked_mul(MICROS_PER_MINUTE)?,
+            _ => parse_seconds(number)?,
+        };
+        micros = micros.checked_add(number)?;
+    }
+    if negative {
+        (months, days, micros) = (-months, -days, micros.checked_neg()?);
+    }
+    Duration::from_parts(months, days, micros)
+}
+
+// IntervalStyle = postgres の出力
+fn parse_postgres(value: &str) -> Option<Duration> {
+    let (mut months, mut days, mut micros) = (0i64, 0i64, 0i64);
+    let mut tokens = value.split_whitespace().peekable();
+    tokens.peek()?;
+    while let Some(token) = tokens.next() {
+        if token.contains(':') {
+            let (negative, token) = match token.strip_prefix('-') {
+                Some(token) => (true, token),
+                None => (false, token.strip_prefix('+').unwrap_or(token)),
+            };
+            let mut parts = token.split(':');
+            let hours: i64 = parts.next()?.parse().ok()?;
+            let minutes: i64 = parts.next()?.parse().ok()?;
+            let seconds = parts.next().map_or(Some(0), parse_seconds)?;
+            if parts.next().is_some() {
+                return None;
+            }
+            let time = hours
+                .checked_mul(MICROS_PER_HOUR)?
+                .checked_add(minutes.checked_mul(MICROS_PER_MINUTE)?)?
+                .checked_add(seconds)?;
+            micros = micros.checked_add(if negative { -time } else { time })?;
+        } else {
+            let number: i64 = token.parse().ok()?;
+            match tokens.next()? {
+                "year" | "years" => months = months.checked_add(number.checked_mul(12)?)?,
+                "mon" | "mons" => months = months.checked_add(number)?,
+                "day" | "days" => days = days.checked_add(number)?,
+                _ => return None,
+            }
+        }
+    }
+    Duration::from_parts(months, days, micros)
+}
+
+impl fmt::Display for Duration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_zero() {
+            return f.write_str("PT0S");
+        }
+        f.write_str("P")?;
+        for (number, unit) in [
+            (self.months / 12, 'Y'),
+            (self.months % 12, 'M'),
+            (self.days, 'D'),
+        ] {
+            if number != 0 {
+                write!(f, "{number}{unit}")?;
+            }
+        }
+        if self.microseconds == 0 {
+            return Ok(());
+        }
+        f.write_str("T")?;
+        let micros = self.microseconds;
+        let (hours, micros) = (micros / MICROS_PER_HOUR, micros % MICROS_PER_HOUR);
+        let (minutes, micros) = (micros / MICROS_PER_MINUTE, micros % MICROS_PER_MINUTE);
+        for (number, unit) in [(hours, 'H'), (minutes, 'M')] {
+            if number != 0 {
+                write!(f, "{number}{unit}")?;
+            }
+        }
+        if micros != 0 {
+            let sign = if micros < 0 { "-" } else { "" };
+            let micros = micros.unsigned_abs();
+            let (seconds, fraction) = (micros / 1_000_000, micros % 1_000_000);
+            if fraction == 0 {
+                write!(f, "{sign}{seconds}S")?;
+            } else {
+                let fraction = format!("{fraction:06}");
+                write!(f, "{sign}{seconds}.{}S", fraction.trim_end_matches('0'))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Duration {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_iso(s).ok_or_else(|| anyhow::anyhow!("invalid ISO 8601 duration {s}"))
+    }
+}
+
+impl From<Duration> for String {
+    fn from(value: Duration) -> Self {
+        value.to_string()
+    }
+}
+
+impl TryFrom<String> for Duration {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+#[Scalar]
+impl ScalarType for Duration {
+    fn parse(value: Value) -> InputValueResult<Self> {
+        if let Value::String(value) = &value {
+            value
+                .parse()
+                .map_err(|e: anyhow::Error| InputValueError::custom(e.to_string()))
+        } else {
+            Err(InputValueError::expected_type(value))
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        Value::String(self.to_string())
+    }
+}
+
+#[cfg(feature = "with-sea-orm")]
+super::pg_value::impl_pg_text_value!(
+    Duration,
+    sea_orm::sea_query::ColumnType::Interval(None, None)
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize() -> anyhow::Result<()> {
+        for (value, expected) in [
+            ("P1DT2H", Duration::new(0, 1, 2 * MICROS_PER_HOUR)),
+            ("P1Y2M", Duration::new(14, 0, 0)),
+            ("P2W", Duration::new(0, 14, 0)),
+            ("PT1M30.25S", Duration::new(0, 0, 90_250_000)),
+            ("PT0S", Duration::default()),
+            ("-P1DT1S", Duration::new(0, -1, -MICROS_PER_SECOND)),
+            ("P-1DT2H", Duration::new(0, -1, 2 * MICROS_PER_HOUR)),
+        ] {
+            let d = Duration::parse(Value::String(value.to_string())).unwrap();
+            assert_eq!(d, expected, "{value}");
+            assert_eq!(d.to_string().parse::<Duration>()?, d, "{value}");
+        }
+        assert_eq!(
+            Duration::new(14, 3, 90_250_000).to_string(),
+            "P1Y2M3DT1M30.25S"
+        );
+        assert_eq!(
+            Duration::new(0, -1, -MICROS_PER_SECOND).to_value(),
+            Value::String("P-1DT-1S".to_string())
+        );
+        assert_eq!(serde_json::to_string(&Duration::new(0, 1, 0))?, r#""P1D""#);
+        assert_eq!(
+            serde_json::from_str::<Duration>(r#""PT2H""#)?,
+            Duration::new(0, 0, 2 * MICROS_PER_HOUR)
+        );
+
+        for value in ["", "P", "PT", "P1H", "PT1D", "P1D2Y", "P1.5D", "1D", "P1DT"] {
+            assert!(
+                Duration::parse(Value::String(value.to_string())).is_err(),
+                "{value}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_pg_text() {
+        for (value, expected) in [
+            (
+                "1 year 2 mons 3 days 04:05:06.5",
+                Duration::new(
+                    14,
+                    3,
+                    4 * MICROS_PER_HOUR + 5 * MICROS_PER_MINUTE + 6_500_000,
+                ),
+            ),
+            (
+                "-1 days +02:00:00",
+                Duration::new(0, -1, 2 * MICROS_PER_HOUR),
+            ),
+            ("-00:00:01", Duration::new(0, 0, -MICROS_PER_SECOND)),
+            ("00:00:00", Duration::default()),
+            ("P1DT2H", Duration::new(0, 1, 2 * MICROS_PER_HOUR)),
+        ] {
+            assert_eq!(Duration::from_pg_text(value), Some(expected), "{value}");
+        }
+        assert_eq!(Duration::from_pg_text("1 fortnight"), None);
+    }
+
+    #[test]
+    fn test_time_delta() {
+        let d = Duration::new(0, 1, MICROS_PER_HOUR);
+        assert_eq!(d.to_time_delta(), Some(chrono::TimeDelta::hours(25)));
+        assert_eq!(Duration::new(1, 0, 0).to_time_delta(), None);
+        assert_eq!(
+            Duration::from_time_delta(chrono::TimeDelta::minutes(90)),
+            Some(Duration::new(0, 0, 90 * MICROS_PER_MINUTE))
+        );
+    }
+}
@@ -0,0 +1,248 @@
+// Apollo Federationの `_entities` をDataLoader経由で解決するためのヘルパー
+//
+// `_entities` は representations を並行に解決するので、
+// entity resolverの中で `load_entity` を使えば型ごとに1クエリにまとまる
+//
+// ```ignore
+// #[graphql(entity)]
+// async fn find_user_by_id(&self, ctx: &Context<'_>, id: i32) -> Result<Option<User>> {
+//     Ok(load_entity::<user::Entity, _>(ctx, id).await?.map(User::from))
+// }
+// ```
+use std::{
+    collections::HashMap,
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+    sync::Arc,
+};
+
+use async_graphql::{dataloader::Loader, Context, Name, Request, Value, Variables};
+use sea_orm::{
+    sea_query::ValueType, ColumnTrait, DbErr, EntityTrait, Iterable, ModelTrait,
+    PrimaryKeyToColumn, QueryFilter,
+};
+
+use super::db::{get_data_loader_from_ctx, Database};
+
+// 単一カラムの主キーで引くためのDataLoaderのキー
+pub struct EntityKey<E, V> {
+    pub value: V,
+    _entity: PhantomData<fn() -> E>,
+}
+
+impl<E, V> EntityKey<E, V> {
+    pub fn new(value: V) -> Self {
+        Self {
+            value,
+            _entity: PhantomData,
+        }
+    }
+}
+
+impl<E, V: Clone> Clone for EntityKey<E, V> {
+    fn clone(&self) -> Self {
+        Self::new(self.value.clone())
+    }
+}
+
+impl<E, V: PartialEq> PartialEq for EntityKey<E, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl<E, V: Eq> Eq for EntityKey<E, V> {}
+
+impl<E, V: Hash> Hash for EntityKey<E, V> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.value.hash(state)
+    }
+}
+
+fn primary_key_column<E: EntityTrait>() -> Result<E::Column, DbErr> {
+    let mut primary_keys = E::PrimaryKey::iter();
+    match (primary_keys.next(), primary_keys.next()) {
+        (Some(primary_key), None) => Ok(primary_key.into_column()),
+        _ => Err(DbErr::Custom(
+            "EntityKey requires a single column primary key".to_string(),
+        )),
+    }
+}
+
+impl<E, V> Loader<EntityKey<E, V>> for Database
+where
+    E: EntityTrait,
+    E::Model: Clone + Send + Sync,
+    V: ValueType + Into<sea_orm::Value> + Clone + Hash + Eq + Send + Sync + 'static,
+{
+    type Value = E::Model;
+    type Error = Arc<DbErr>;
+
+    async fn load(
+        &self,
+        keys: &[EntityKey<E, V>],
+    ) -> Result<HashMap<EntityKey<E, V>, Self::Value>, Self::Error> {
+        let column = primary_key_column::<E>()?;
+        E::find()
+            .filter(column.is_in(keys.iter().map(|x| x.value.clone())))
+            .all(&self.traced())
+            .await?
+            .into_iter()
+            .map(|model| {
+                V::try_from(model.get(column))
+                    .map(|value| (EntityKey::new(value), model))
+                    .map_err(|e| Arc::new(DbErr::Type(e.to_string())))
+            })
+            .collect()
+    }
+}
+
+pub async fn load_entity<E, V>(ctx: &Context<'_>, key: V) -> async_graphql::Result<Option<E::Model>>
+where
+    E: EntityTrait,
+    E::Model: Clone + Send + Sync,
+    V: ValueType + Into<sea_orm::Value> + Clone + Hash + Eq + Send + Sync + 'static,
+{
+    Ok(get_data_loader_from_ctx(ctx)
+        .load_one(EntityKey::<E, V>::new(key))
+        .await?)
+}
+
+// テストやゲートウェイの代わりに `_entities` を叩くためのリクエストを作る
+// representations には `{ "__typename": "User", "id": 1 }` のような値を渡す
+pub fn entities_request(typename: &str, selection: &str, representations: Vec<Value>) -> Request {
+    let query = format!(
+        "query($representations: [_Any!]!) {{ _entities(representations: $representations) {{ ... on {typename} {{ {selection} }} }} }}"
+    );
+    let mut variables = Variables::default();
+    variables.insert(Name::new("representations"), Value::List(representations));
+    Request::new(query).variables(variables)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_graphql::{value, EmptyMutation, EmptySubscription, Object, Schema, SimpleObject};
+    use sea_orm::{DatabaseBackend, MockDatabase};
+
+    mod user {
+        use sea_orm::entity::prelude::*;
+
+        #[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+        #[sea_orm(table_name = "user")]
+        pub struct Model {
+            #[sea_orm(primary_key)]
+            pub id: i32,
+            pub name: String,
+            pub weight: i32,
+        }
+
+        #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+        pub enum Relation {}
+
+        impl ActiveModelBehavior for ActiveModel {}
+    }
+
+    #[derive(SimpleObject)]
+    #[graphql(complex)]
+    struct User {
+        id: i32,
+        name: String,
+        #[graphql(external)]
+        weight: Option<i32>,
+    }
+
+    #[async_graphql::ComplexObject]
+    impl User {
+        #[graphql(requires = "weight")]
+        async fn shipping_cost(&self) -> Option<i32> {
+            self.weight.map(|x| x * 10)
+        }
+    }
+
+    impl From<user::Model> for User {
+        fn from(model: user::Model) -> Self {
+            Self {
+                id: model.id,
+                name: model.name,
+                weight: None,
+            }
+        }
+    }
+
+    struct Query;
+
+    #[Object]
+    impl Query {
+        #[graphql(entity)]
+        async fn find_user_by_id(
+            &self,
+            ctx: &Context<'_>,
+            #[graphql(key)] id: i32,
+            weight: Option<i32>,
+        ) -> async_graphql::Result<Option<User>> {
+            Ok(load_entity::<user::Entity, _>(ctx, id)
+                .await?
+                .map(|model| User {
+                    weight,
+                    ..model.into()
+                }))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_entities() -> anyhow::Result<()> {
+        let connection = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([[
+                user::Model {
+                    id: 1,
+                    name: "alice".to_string(),
+                    weight: 3,
+                },
+                user::Model {
+                    id: 2,
+                    name: "bob".to_string(),
+                    weight: 5,
+                },
+            ]])
+            .into_connection();
+        let connection = Arc::new(connection);
+        let schema = Schema::build(Query, EmptyMutation, EmptySubscription)
+            .enable_federation()
+            .data(async_graphql::dataloader::DataLoader::new(
+                Database {
+                    connection: connection.clone(),
+                },
+                tokio::task::spawn,
+            ))
+            .finish();
+
+        let resp = schema
+            .execute(entities_request(
+                "User",
+                "id name shippingCost",
+                vec![
+                    value!({ "__typename": "User", "id": 2, "weight": 5 }),
+                    value!({ "__typename": "User", "id": 1, "weight": 3 }),
+                    value!({ "__typename": "User", "id": 3, "weight": 1 }),
+                ],
+            ))
+            .await;
+        assert!(resp.errors.is_empty(), "{:?}", resp.errors);
+        assert_eq!(
+            resp.data,
+            value!({
+                "_entities": [
+                    { "id": 2, "name": "bob", "shippingCost": 50 },
+                    { "id": 1, "name": "alice", "shippingCost": 30 },
+                    null,
+                ]
+            })
+        );
+
+        drop(schema);
+        let log = Arc::into_inner(connection).unwrap().into_transaction_log();
+        assert_eq!(log.len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,70 @@
+use async_graphql::{ErrorExtensions, ServerError, Value};
+
+// extensions.code に入れるエラーコード
+// https://www.apollographql.com/docs/apollo-server/data/errors#built-in-error-codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    BadUserInput,
+    Unauthenticated,
+    Forbidden,
+    NotFound,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::BadUserInput => "BAD_USER_INPUT",
+            Self::Unauthenticated => "UNAUTHENTICATED",
+            Self::Forbidden => "FORBIDDEN",
+            Self::NotFound => "NOT_FOUND",
+            Self::Internal => "INTERNAL_SERVER_ERROR",
+        }
+    }
+
+    pub fn parse(code: &str) -> Option<Self> {
+        match code {
+            "BAD_USER_INPUT" => Some(Self::BadUserInput),
+            "UNAUTHENTICATED" => Some(Self::Unauthenticated),
+            "FORBIDDEN" => Some(Self::Forbidden),
+            "NOT_FOUND" => Some(Self::NotFound),
+            "INTERNAL_SERVER_ERROR" => Some(Self::Internal),
+            _ => None,
+        }
+    }
+
+    pub fn from_server_error(err: &ServerError) -> Option<Self> {
+        match err.extensions.as_ref()?.get("code")? {
+            Value::String(code) => Self::parse(code),
+            _ => None,
+        }
+    }
+
+    pub fn is_client_error(&self) -> bool {
+        !matches!(self, Self::Internal)
+    }
+
+    pub fn error(self, message: impl Into<String>) -> async_graphql::Error {
+        async_graphql::Error::new(message).extend_with(|_, e| e.set("code", self.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() -> anyhow::Result<()> {
+        let err = ErrorCode::Forbidden
+            .error("denied")
+            .into_server_error(async_graphql::Pos::default());
+        assert_eq!(err.message, "denied");
+        assert_eq!(
+            ErrorCode::from_server_error(&err),
+            Some(ErrorCode::Forbidden)
+        );
+        assert!(ErrorCode::Forbidden.is_client_error());
+        assert!(!ErrorCode::Internal.is_client_error());
+        Ok(())
+    }
+}
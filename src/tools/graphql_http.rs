@@ -0,0 +1,411 @@
+// GraphQL over HTTPのリクエストを受け取るextractor
+// - GET: クエリ文字列から読む。CSRF対策でquery以外(mutation/subscription)は405にする
+// - POST: JSON(バッチ含む)とmultipart(Upload)
+//   multipartはCORSのsimple requestで送れてしまうので、CSRF対策で
+//   Apollo-Require-Preflight か X-Apollo-Operation-Name ヘッダーがないものは400にする
+// - 成功したGETのクエリのレスポンスには get_cache_control (GRAPHQL_GET_CACHE_CONTROL) のCache-Controlをつける
+//
+// multipartのファイルはtempfileで `std::env::temp_dir()` (TMPDIR) に書き出しながら受け取る
+use async_graphql::{
+    http::MultipartOptions,
+    parser::types::{DocumentOperations, OperationType},
+    BatchRequest, BatchResponse, ParseRequestError,
+};
+use async_graphql_axum::GraphQLResponse;
+use axum::{
+    async_trait,
+    extract::{FromRequest, Request},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures_util::TryStreamExt;
+use tokio_util::compat::TokioAsyncReadCompatExt;
+
+#[derive(Debug, Clone)]
+pub struct GraphQLHttpConfig {
+    pub max_batch_size: usize,
+    pub max_file_size: usize,
+    pub max_num_files: usize,
+    // 例: "public, max-age=60"。Noneならつけない
+    pub get_cache_control: Option<String>,
+}
+
+impl Default for GraphQLHttpConfig {
+    fn default() -> Self {
+        Self {
+            max_batch_size: 10,
+            max_file_size: 10 * 1024 * 1024,
+            max_num_files: 10,
+            get_cache_control: None,
+        }
+    }
+}
+
+impl GraphQLHttpConfig {
+    pub fn new_from_env() -> anyhow::Result<Self> {
+        let default = Self::default();
+        let parse = |name: &str, default: usize| -> anyhow::Result<usize> {
+            match std::env::var(name) {
+                Ok(value) => value
+                    .parse()
+                    .map_err(|e| anyhow::anyhow!("invalid {name}={value}: {e}")),
+                Err(_) => Ok(default),
+            }
+        };
+        Ok(Self {
+            max_batch_size: parse("GRAPHQL_MAX_BATCH_SIZE", default.max_batch_size)?,
+            max_file_size: parse("GRAPHQL_MAX_FILE_SIZE", default.max_file_size)?,
+            max_num_files: parse("GRAPHQL_MAX_NUM_FILES", default.max_num_files)?,
+            get_cache_control: match std::env::var("GRAPHQL_GET_CACHE_CONTROL") {
+                Ok(value) => {
+                    HeaderValue::from_str(&value).map_err(|e| {
+                        anyhow::anyhow!("invalid GRAPHQL_GET_CACHE_CONTROL={value}: {e}")
+                    })?;
+                    Some(value)
+                }
+                Err(_) => default.get_cache_control,
+            },
+        })
+    }
+
+    // 成功したGETのクエリにだけget_cache_controlをつける。@cacheControlから決まったものがあればそちらを使う
+    pub fn respond(&self, method: &Method, resp: BatchResponse) -> Response {
+        let is_ok = resp.is_ok();
+        let mut resp = GraphQLResponse(resp).into_response();
+        if method == Method::GET && is_ok && !resp.headers().contains_key(header::CACHE_CONTROL) {
+            if let Some(value) = self
+                .get_cache_control
+                .as_deref()
+                .and_then(|x| HeaderValue::from_str(x).ok())
+            {
+                resp.headers_mut().insert(header::CACHE_CONTROL, value);
+            }
+        }
+        resp
+    }
+}
+
+#[derive(Debug)]
+pub enum GraphQLHttpRejection {
+    Parse(ParseRequestError),
+    BatchTooLarge(usize),
+    MethodNotAllowed(String),
+    PreflightRequired,
+}
+
+impl IntoResponse for GraphQLHttpRejection {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Parse(ParseRequestError::PayloadTooLarge) => {
+                (StatusCode::PAYLOAD_TOO_LARGE, "payload too large").into_response()
+            }
+            Self::Parse(err) => (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+            Self::BatchTooLarge(max) => (
+                StatusCode::BAD_REQUEST,
+                format!("batch size exceeds the limit of {max}"),
+            )
+                .into_response(),
+            Self::MethodNotAllowed(message) => (
+                StatusCode::METHOD_NOT_ALLOWED,
+                [(header::ALLOW, "POST")],
+                message,
+            )
+                .into_response(),
+            Self::PreflightRequired => (
+                StatusCode::BAD_REQUEST,
+                "multipart requests must have a non-empty Apollo-Require-Preflight or X-Apollo-Operation-Name header",
+            )
+                .into_response(),
+        }
+    }
+}
+
+fn ensure_query(request: &mut async_graphql::Request) -> Result<(), GraphQLHttpRejection> {
+    let operation_name = request.operation_name.clone();
+    let document = request.parsed_query().map_err(|e| {
+        GraphQLHttpRejection::Parse(ParseRequestError::InvalidRequest(e.message.into()))
+    })?;
+    let operation_type = match (&document.operations, operation_name) {
+        (DocumentOperations::Single(operation), _) => Some(operation.node.ty),
+        (DocumentOperations::Multiple(operations), Some(name)) => operations
+            .get(name.as_str())
+            .map(|operation| operation.node.ty),
+        // operationNameなしで複数operationがある場合は実行時にエラーになる
+        (DocumentOperations::Multiple(_), None) => None,
+    };
+    match operation_type {
+        Some(OperationType::Mutation) | Some(OperationType::Subscription) => Err(
+            GraphQLHttpRejection::MethodNotAllowed("only queries are allowed over GET".to_string()),
+        ),
+        _ => Ok(()),
+    }
+}
+
+// ブラウザはこれらのヘッダーをつけるとpreflightするので、他のoriginからは送れない
+fn has_preflight_header(headers: &HeaderMap) -> bool {
+    ["apollo-require-preflight", "x-apollo-operation-name"]
+        .iter()
+        .any(|name| headers.get(*name).is_some_and(|value| !value.is_empty()))
+}
+
+// operationNameがなければドキュメント中の唯一のoperationの名前を使う
+fn operation_name(request: &mut async_graphql::Request) -> Option<String> {
+    if let Some(name) = request.operation_name.clone() {
+        return Some(name);
+    }
+    match &request.parsed_query().ok()?.operations {
+        DocumentOperations::Single(_) => None,
+        DocumentOperations::Multiple(operations) if operations.len() == 1 => {
+            operations.keys().next().map(ToString::to_string)
+        }
+        DocumentOperations::Multiple(_) => None,
+    }
+}
+
+pub struct GraphQLHttpRequest(pub BatchRequest);
+
+impl GraphQLHttpRequest {
+    pub fn into_inner(self) -> BatchRequest {
+        self.0
+    }
+
+    // サンプリングやspanの属性に使う
+    pub fn operation_names(&mut self) -> Vec<String> {
+        self.0.iter_mut().filter_map(operation_name).collect()
+    }
+}
+
+#[async_trait]
+impl<S> FromRequest<S> for GraphQLHttpRequest
+where
+    S: Send + Sync,
+{
+    type Rejection = GraphQLHttpRejection;
+
+    async fn from_request(req: Request, _state: &S) -> Result<Self, Self::Rejection> {
+        let config = req
+            .extensions()
+            .get::<GraphQLHttpConfig>()
+            .cloned()
+            .unwrap_or_default();
+
+        if req.method() == Method::GET {
+            let mut request =
+                async_graphql::http::parse_query_string(req.uri().query().unwrap_or_default())
+                    .map_err(GraphQLHttpRejection::Parse)?;
+            ensure_query(&mut request)?;
+            return Ok(Self(BatchRequest::Single(request)));
+        }
+
+        let content_type = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(ToString::to_string);
+        if content_type
+            .as_deref()
+            .is_some_and(|x| x.starts_with("multipart/"))
+            && !has_preflight_header(req.headers())
+        {
+            return Err(GraphQLHttpRejection::PreflightRequired);
+        }
+        let body_reader = tokio_util::io::StreamReader::new(
+            req.into_body()
+                .into_data_stream()
+                .map_err(|err| std::io::Error::other(err.to_string())),
+        )
+        .compat();
+        let batch = async_graphql::http::receive_batch_body(
+            content_type,
+            body_reader,
+            MultipartOptions::default()
+                .max_file_size(config.max_file_size)
+                .max_num_files(config.max_num_files),
+        )
+        .await
+        .map_err(GraphQLHttpRejection::Parse)?;
+
+        if let BatchRequest::Batch(requests) = &batch {
+            if requests.len() > config.max_batch_size {
+                return Err(GraphQLHttpRejection::BatchTooLarge(config.max_batch_size));
+            }
+        }
+        Ok(Self(batch))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_graphql::{EmptySubscription, Object, Schema, Upload};
+    use axum::{body::Body, routing::get, Extension, Router};
+    use tower::ServiceExt;
+
+    struct Query;
+
+    #[Object]
+    impl Query {
+        async fn value(&self) -> i32 {
+            1
+        }
+    }
+
+    struct Mutation;
+
+    #[Object]
+    impl Mutation {
+        async fn upload(
+            &self,
+            ctx: &async_graphql::Context<'_>,
+            file: Upload,
+        ) -> std::io::Result<u64> {
+            file.value(ctx)?.size()
+        }
+    }
+
+    type TestSchema = Schema<Query, Mutation, EmptySubscription>;
+
+    async fn handler(
+        Extension(schema): Extension<TestSchema>,
+        Extension(config): Extension<GraphQLHttpConfig>,
+        method: Method,
+        req: GraphQLHttpRequest,
+    ) -> Response {
+        config.respond(&method, schema.execute_batch(req.into_inner()).await)
+    }
+
+    fn router() -> Router {
+        Router::new()
+            .route("/", get(handler).post(handler))
+            .layer(Extension(Schema::new(Query, Mutation, EmptySubscription)))
+            .layer(Extension(GraphQLHttpConfig {
+                max_batch_size: 2,
+                // multerはmax_file_size * max_num_filesをリクエスト全体の上限にもする
+                max_file_size: 512,
+                max_num_files: 2,
+                get_cache_control: Some("public, max-age=60".to_string()),
+            }))
+    }
+
+    async fn send(req: axum::http::Request<Body>) -> anyhow::Result<(StatusCode, String)> {
+        let (status, _, body) = send_with_headers(req).await?;
+        Ok((status, body))
+    }
+
+    async fn send_with_headers(
+        req: axum::http::Request<Body>,
+    ) -> anyhow::Result<(StatusCode, HeaderMap, String)> {
+        let resp = router().oneshot(req).await?;
+        let status = resp.status();
+        let headers = resp.headers().clone();
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await?;
+        Ok((status, headers, String::from_utf8(body.to_vec())?))
+    }
+
+    fn multipart(content: &str) -> anyhow::Result<axum::http::Request<Body>> {
+        let mut req = multipart_without_preflight(content)?;
+        req.headers_mut()
+            .insert("apollo-require-preflight", HeaderValue::from_static("true"));
+        Ok(req)
+    }
+
+    fn multipart_without_preflight(content: &str) -> anyhow::Result<axum::http::Request<Body>> {
+        let body = format!(
+            "--boundary\r\n\
+             Content-Disposition: form-data; name=\"operations\"\r\n\r\n\
+             {{\"query\": \"mutation($file: Upload!) {{ upload(file: $file) }}\", \"variables\": {{\"file\": null}}}}\r\n\
+             --boundary\r\n\
+             Content-Disposition: form-data; name=\"map\"\r\n\r\n\
+             {{\"0\": [\"variables.file\"]}}\r\n\
+             --boundary\r\n\
+             Content-Disposition: form-data; name=\"0\"; filename=\"a.txt\"\r\n\
+             Content-Type: text/plain\r\n\r\n\
+             {content}\r\n\
+             --boundary--\r\n"
+        );
+        Ok(axum::http::Request::post("/")
+            .header(
+                header::CONTENT_TYPE,
+                "multipart/form-data; boundary=boundary",
+            )
+            .body(Body::from(body))?)
+    }
+
+    #[tokio::test]
+    async fn test_get() -> anyhow::Result<()> {
+        let (status, headers, body) =
+            send_with_headers(axum::http::Request::get("/?query=%7Bvalue%7D").body(Body::empty())?)
+                .await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, r#"{"data":{"value":1}}"#);
+        assert_eq!(headers[header::CACHE_CONTROL], "public, max-age=60");
+
+        // エラーのときとPOSTにはつけない
+        let (_, headers, _) = send_with_headers(
+            axum::http::Request::get("/?query=%7Bmissing%7D").body(Body::empty())?,
+        )
+        .await?;
+        assert!(!headers.contains_key(header::CACHE_CONTROL));
+        let (_, headers, _) = send_with_headers(
+            axum::http::Request::post("/")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(r#"{"query": "{value}"}"#))?,
+        )
+        .await?;
+        assert!(!headers.contains_key(header::CACHE_CONTROL));
+
+        let mut req = GraphQLHttpRequest(BatchRequest::Batch(vec![
+            async_graphql::Request::new("query A { value }"),
+            async_graphql::Request::new("{ value }"),
+            async_graphql::Request::new("query B { value } query C { value }").operation_name("C"),
+        ]));
+        assert_eq!(req.operation_names(), vec!["A", "C"]);
+
+        let (status, _) =
+            send(axum::http::Request::get("/?query=mutation%7Bvalue%7D").body(Body::empty())?)
+                .await?;
+        assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_batch() -> anyhow::Result<()> {
+        let post = |body: &'static str| {
+            axum::http::Request::post("/")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body))
+        };
+        let (status, body) = send(post(r#"[{"query": "{value}"}, {"query": "{value}"}]"#)?).await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, r#"[{"data":{"value":1}},{"data":{"value":1}}]"#);
+
+        let (status, _) = send(post(
+            r#"[{"query": "{value}"}, {"query": "{value}"}, {"query": "{value}"}]"#,
+        )?)
+        .await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_upload() -> anyhow::Result<()> {
+        let (status, body) = send(multipart("hello")?).await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, r#"{"data":{"upload":5}}"#);
+
+        let (status, _) = send(multipart(&"x".repeat(600))?).await?;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        // preflightの必要なヘッダーがなければCSRFとみなす
+        let (status, _) = send(multipart_without_preflight("hello")?).await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let mut req = multipart_without_preflight("hello")?;
+        req.headers_mut().insert(
+            "x-apollo-operation-name",
+            HeaderValue::from_static("Upload"),
+        );
+        let (status, _) = send(req).await?;
+        assert_eq!(status, StatusCode::OK);
+        Ok(())
+    }
+}
@@ -0,0 +1,244 @@
+// Authenticated / RoleGuard / ScopeGuard は guard属性のGuardとしても、DefaultDenyのPolicyとしても使える
+use async_graphql::{extensions::ExtensionContext, Context, DataContext, Guard, Result};
+
+use super::auth::{get_claims_from_ctx, Claims};
+use super::default_deny::Policy;
+use super::error_code::ErrorCode;
+
+pub struct Authenticated;
+
+impl Authenticated {
+    fn authorize<'a>(&self, ctx: &impl DataContext<'a>) -> Result<()> {
+        get_claims_from_ctx::<Claims>(ctx).map(|_| ())
+    }
+}
+
+impl Guard for Authenticated {
+    async fn check(&self, ctx: &Context<'_>) -> Result<()> {
+        self.authorize(ctx)
+    }
+}
+
+impl Policy for Authenticated {
+    fn check(&self, ctx: &ExtensionContext<'_>) -> Result<()> {
+        self.authorize(ctx)
+    }
+}
+
+pub struct RoleGuard {
+    role: String,
+}
+
+impl RoleGuard {
+    pub fn new(role: impl Into<String>) -> Self {
+        Self { role: role.into() }
+    }
+
+    fn authorize<'a>(&self, ctx: &impl DataContext<'a>) -> Result<()> {
+        if get_claims_from_ctx::<Claims>(ctx)?.has_role(&self.role) {
+            Ok(())
+        } else {
+            Err(ErrorCode::Forbidden.error(format!("role {} is required", self.role)))
+        }
+    }
+}
+
+impl Guard for RoleGuard {
+    async fn check(&self, ctx: &Context<'_>) -> Result<()> {
+        self.authorize(ctx)
+    }
+}
+
+impl Policy for RoleGuard {
+    fn check(&self, ctx: &ExtensionContext<'_>) -> Result<()> {
+        self.authorize(ctx)
+    }
+}
+
+pub struct ScopeGuard {
+    scope: String,
+}
+
+impl ScopeGuard {
+    pub fn new(scope: impl Into<String>) -> Self {
+        Self {
+            scope: scope.into(),
+        }
+    }
+
+    fn authorize<'a>(&self, ctx: &impl DataContext<'a>) -> Result<()> {
+        if get_claims_from_ctx::<Claims>(ctx)?.has_scope(&self.scope) {
+            Ok(())
+        } else {
+            Err(ErrorCode::Forbidden.error(format!("scope {} is required", self.scope)))
+        }
+    }
+}
+
+impl Guard for ScopeGuard {
+    async fn check(&self, ctx: &Context<'_>) -> Result<()> {
+        self.authorize(ctx)
+    }
+}
+
+impl Policy for ScopeGuard {
+    fn check(&self, ctx: &ExtensionContext<'_>) -> Result<()> {
+        self.authorize(ctx)
+    }
+}
+
+// フィールドの引数を使って `guard = "OwnerGuard::new(|claims| claims.sub == user_id)"` のように書く
+// 引数はresolverの中でしか取れないのでPolicyにはならない。@guarded(policy: "authenticated") などと併用する
+pub struct OwnerGuard<F> {
+    predicate: F,
+}
+
+impl<F> OwnerGuard<F>
+where
+    F: Fn(&Claims) -> bool + Send + Sync,
+{
+    pub fn new(predicate: F) -> Self {
+        Self { predicate }
+    }
+}
+
+impl<F> Guard for OwnerGuard<F>
+where
+    F: Fn(&Claims) -> bool + Send + Sync,
+{
+    async fn check(&self, ctx: &Context<'_>) -> Result<()> {
+        if (self.predicate)(get_claims_from_ctx::<Claims>(ctx)?) {
+            Ok(())
+        } else {
+            Err(ErrorCode::Forbidden.error("not the owner of this resource"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::auth::{AccessToken, AuthError};
+    use crate::tools::default_deny::{guarded, DefaultDeny};
+    use async_graphql::{EmptyMutation, EmptySubscription, Object, Request, Schema};
+
+    struct Query;
+
+    #[Object]
+    impl Query {
+        #[graphql(guard = "Authenticated")]
+        async fn me(&self, ctx: &Context<'_>) -> Result<String> {
+            Ok(get_claims_from_ctx::<Claims>(ctx)?.sub.clone())
+        }
+
+        #[graphql(guard = "RoleGuard::new(\"admin\").or(ScopeGuard::new(\"admin:read\"))")]
+        async fn admin(&self) -> i32 {
+            1
+        }
+
+        #[graphql(guard = "OwnerGuard::new(|claims| claims.sub == user_id)")]
+        async fn profile(&self, user_id: String) -> String {
+            user_id
+        }
+    }
+
+    struct PolicyQuery;
+
+    #[Object]
+    impl PolicyQuery {
+        #[graphql(directive = guarded::apply("authenticated".to_string()))]
+        async fn me(&self, ctx: &Context<'_>) -> Result<String> {
+            Ok(get_claims_from_ctx::<Claims>(ctx)?.sub.clone())
+        }
+
+        #[graphql(directive = guarded::apply("admin".to_string()))]
+        async fn admin(&self) -> i32 {
+            1
+        }
+    }
+
+    fn token(roles: &[&str], scope: Option<&str>) -> Result<AccessToken, AuthError> {
+        Ok(AccessToken {
+            claims: Claims {
+                sub: "user-1".to_string(),
+                exp: 0,
+                scope: scope.map(|x| x.to_string()),
+                roles: roles.iter().map(|x| x.to_string()).collect(),
+                extra: Default::default(),
+            },
+            token: String::new(),
+        })
+    }
+
+    async fn execute(query: &str, token: Result<AccessToken, AuthError>) -> Vec<Option<ErrorCode>> {
+        let schema = Schema::new(Query, EmptyMutation, EmptySubscription);
+        schema
+            .execute(Request::new(query).data(token))
+            .await
+            .errors
+            .iter()
+            .map(ErrorCode::from_server_error)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_guards() -> anyhow::Result<()> {
+        assert_eq!(execute("{ me }", token(&[], None)).await, vec![]);
+        assert_eq!(
+            execute("{ me }", Err(AuthError::MissingToken)).await,
+            vec![Some(ErrorCode::Unauthenticated)]
+        );
+
+        assert_eq!(execute("{ admin }", token(&["admin"], None)).await, vec![]);
+        assert_eq!(
+            execute("{ admin }", token(&[], Some("admin:read"))).await,
+            vec![]
+        );
+        assert_eq!(
+            execute("{ admin }", token(&["user"], Some("read"))).await,
+            vec![Some(ErrorCode::Forbidden)]
+        );
+
+        assert_eq!(
+            execute("{ profile(userId: \"user-1\") }", token(&[], None)).await,
+            vec![]
+        );
+        assert_eq!(
+            execute("{ profile(userId: \"user-2\") }", token(&[], None)).await,
+            vec![Some(ErrorCode::Forbidden)]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_policies() -> anyhow::Result<()> {
+        let schema = DefaultDeny::new()
+            .with_policy("authenticated", Authenticated)
+            .with_policy("admin", RoleGuard::new("admin"))
+            .finish(Schema::build(PolicyQuery, EmptyMutation, EmptySubscription))?;
+        let execute = |query: &'static str, token| {
+            let schema = schema.clone();
+            async move {
+                schema
+                    .execute(Request::new(query).data(token))
+                    .await
+                    .errors
+                    .iter()
+                    .map(ErrorCode::from_server_error)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(execute("{ me }", token(&[], None)).await, vec![]);
+        assert_eq!(
+            execute("{ me }", Err(AuthError::MissingToken)).await,
+            vec![Some(ErrorCode::Unauthenticated)]
+        );
+        assert_eq!(execute("{ admin }", token(&["admin"], None)).await, vec![]);
+        assert_eq!(
+            execute("{ admin }", token(&["user"], None)).await,
+            vec![Some(ErrorCode::Forbidden)]
+        );
+        Ok(())
+    }
+}
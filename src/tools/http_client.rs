@@ -0,0 +1,610 @@
+// 他のサービスを呼ぶためのHTTPクライアント
+// - 今のContextをpropagator (traceparent, tracestate, baggageなど。with_propagatorで変えられる) と
+//   x-request-id でヘッダーに入れる
+// - 1回の送信ごとにクライアントのspanを作る (再送はhttp.request.resend_count)
+// - タイムアウト、冪等なメソッドだけのリトライ、連続で失敗したら一定時間止めるサーキットブレーカー
+// - 失敗はHttpClientErrorにまとめ、GraphQLのエラーやaxumのレスポンスにできる
+//
+// ```ignore
+// let client = HttpClient::new("users", HttpClientConfig::default())?;
+// let user: User = client.json(client.get(format!("{base}/users/{id}"))).await?;
+// // resolverなら .map_err(|e| e.to_graphql_error())?
+// ```
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::{
+    http::{HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+use opentelemetry::{
+    global::BoxedTracer,
+    propagation::TextMapPropagator,
+    trace::{SpanKind, Status, TraceContextExt, Tracer},
+    Context, KeyValue,
+};
+use opentelemetry_http::HeaderInjector;
+use serde::de::DeserializeOwned;
+use tokio::time::Instant;
+
+#[cfg(feature = "with-graphql")]
+use super::error_code::ErrorCode;
+use super::request_id::{RequestId, REQUEST_ID_HEADER};
+
+const TRACER_NAME: &str = "http-client";
+// エラーのメッセージに入れるレスポンスの長さ
+const MAX_ERROR_BODY_LENGTH: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub struct HttpClientConfig {
+    // 1回の送信ごと
+    pub timeout: Duration,
+    pub connect_timeout: Duration,
+    // 最初の送信を除いた回数。GET, HEAD, PUT, DELETE, OPTIONSのみ
+    pub max_retries: u32,
+    // retry_backoff, 2倍, 4倍, ... と待つ
+    pub retry_backoff: Duration,
+    // 連続でこの回数失敗したらopen_durationの間は送らずにエラーにする
+    pub failure_threshold: u32,
+    pub open_duration: Duration,
+}
+
+impl Default for HttpClientConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            connect_timeout: Duration::from_secs(3),
+            max_retries: 2,
+            retry_backoff: Duration::from_millis(100),
+            failure_threshold: 5,
+            open_duration: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum HttpClientError {
+    Timeout,
+    Connect(String),
+    // 2xx以外のレスポンス
+    Status { status: StatusCode, body: String },
+    // サーキットブレーカーで止めている
+    CircuitOpen(String),
+    // リクエストが作れない、レスポンスが読めないなど
+    Invalid(String),
+}
+
+impl fmt::Display for HttpClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout => write!(f, "request timed out"),
+            Self::Connect(e) => write!(f, "connection failed: {e}"),
+            Self::Status { status, body } => write!(f, "unexpected status {status}: {body}"),
+            Self::CircuitOpen(name) => write!(f, "circuit breaker for {name} is open"),
+            Self::Invalid(e) => write!(f, "invalid request or response: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for HttpClientError {}
+
+impl From<reqwest::Error> for HttpClientError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            Self::Timeout
+        } else if e.is_connect() {
+            Self::Connect(e.to_string())
+        } else {
+            Self::Invalid(e.to_string())
+        }
+    }
+}
+
+impl HttpClientError {
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Self::Status { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    // サーキットブレーカーの失敗として数えるもの (4xxは相手のサービスの問題ではない)
+    fn is_failure(&self) -> bool {
+        match self {
+            Self::Timeout | Self::Connect(_) => true,
+            Self::Status { status, .. } => status.is_server_error(),
+            Self::CircuitOpen(_) | Self::Invalid(_) => false,
+        }
+    }
+
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::Timeout | Self::Connect(_) => true,
+            Self::Status { status, .. } => matches!(
+                *status,
+                StatusCode::BAD_GATEWAY
+                    | StatusCode::SERVICE_UNAVAILABLE
+                    | StatusCode::GATEWAY_TIMEOUT
+            ),
+            Self::CircuitOpen(_) | Self::Invalid(_) => false,
+        }
+    }
+
+    // 相手の404はNOT_FOUND、それ以外はこちらのサーバーのエラー
+    // 相手のレスポンスや接続エラーの中身はクライアントに返さず、ログ (とspan) にだけ残す
+    #[cfg(feature = "with-graphql")]
+    pub fn to_graphql_error(&self) -> async_graphql::Error {
+        tracing::warn!(error = %self, "upstream request failed");
+        match self.status() {
+            Some(StatusCode::NOT_FOUND) => ErrorCode::NotFound.error("upstream resource not found"),
+            _ => ErrorCode::Internal.error("upstream request failed"),
+        }
+    }
+}
+
+impl IntoResponse for HttpClientError {
+    fn into_response(self) -> Response {
+        tracing::warn!(error = %self, "upstream request failed");
+        let (status, message) = match self {
+            Self::Timeout => (StatusCode::GATEWAY_TIMEOUT, "upstream request timed out"),
+            Self::CircuitOpen(_) => (StatusCode::SERVICE_UNAVAILABLE, "upstream unavailable"),
+            _ => (StatusCode::BAD_GATEWAY, "upstream request failed"),
+        };
+        (status, message).into_response()
+    }
+}
+
+#[derive(Debug)]
+enum CircuitState {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    // open_durationが過ぎたら1つだけ通して、成功したら戻す
+    // 通したものがキャンセルされたときのために、untilが過ぎたらもう1つ通す
+    HalfOpen { until: Instant },
+}
+
+#[derive(Debug)]
+struct CircuitBreaker {
+    state: Mutex<CircuitState>,
+    failure_threshold: u32,
+    open_duration: Duration,
+}
+
+impl CircuitBreaker {
+    fn new(config: &HttpClientConfig) -> Self {
+        Self {
+            state: Mutex::new(CircuitState::Closed { failures: 0 }),
+            failure_threshold: config.failure_threshold.max(1),
+            open_duration: config.open_duration,
+        }
+    }
+
+    fn allow(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            CircuitState::Closed { .. } => true,
+            CircuitState::Open { until } | CircuitState::HalfOpen { until }
+                if Instant::now() >= until =>
+            {
+                *state = CircuitState::HalfOpen {
+                    until: Instant::now() + self.open_duration,
+                };
+                true
+            }
+            CircuitState::Open { .. } | CircuitState::HalfOpen { .. } => false,
+        }
+    }
+
+    fn record(&self, success: bool) {
+        let mut state = self.state.lock().unwrap();
+        *state = match (&*state, success) {
+            (_, true) => CircuitState::Closed { failures: 0 },
+            (CircuitState::Closed { failures }, false) if failures + 1 < self.failure_threshold => {
+                CircuitState::Closed {
+                    failures: failures + 1,
+                }
+            }
+            (_, false) => CircuitState::Open {
+                until: Instant::now() + self.open_duration,
+            },
+        };
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    name: String,
+    client: reqwest::Client,
+    config: HttpClientConfig,
+    breaker: Arc<CircuitBreaker>,
+    tracer: Arc<BoxedTracer>,
+    // Noneならグローバルに登録されているもの
+    propagator: Option<Arc<dyn TextMapPropagator + Send + Sync>>,
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS
+    )
+}
+
+impl HttpClient {
+    // nameはサーキットブレーカーのエラーメッセージに使う
+    pub fn new(name: impl Into<String>, config: HttpClientConfig) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(config.timeout)
+            .connect_timeout(config.connect_timeout)
+            .build()?;
+        Ok(Self {
+            name: name.into(),
+            client,
+            breaker: Arc::new(CircuitBreaker::new(&config)),
+            config,
+            tracer: Arc::new(opentelemetry::global::tracer(TRACER_NAME)),
+            propagator: None,
+        })
+    }
+
+    // グローバルに登録していないTracerProviderを使うとき
+    pub fn with_tracer(self, tracer: BoxedTracer) -> Self {
+        Self {
+            tracer: Arc::new(tracer),
+            ..self
+        }
+    }
+
+    pub fn with_propagator(
+        self,
+        propagator: impl TextMapPropagator + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            propagator: Some(Arc::new(propagator)),
+            ..self
+        }
+    }
+
+    pub fn request(&self, method: Method, url: impl reqwest::IntoUrl) -> reqwest::RequestBuilder {
+        self.client.request(method, url)
+    }
+
+    pub fn get(&self, url: impl reqwest::IntoUrl) -> reqwest::RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub fn post(&self, url: impl reqwest::IntoUrl) -> reqwest::RequestBuilder {
+        self.client.post(url)
+    }
+
+    // 2xxならそのまま返し、それ以外はHttpClientError::Statusにする
+    pub async fn send(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, HttpClientError> {
+        let mut request = Some(request.build()?);
+        let retries = match request.as_ref() {
+            Some(request) if is_idempotent(request.method()) => self.config.max_retries,
+            _ => 0,
+        };
+
+        let mut attempt = 0;
+        while let Some(current) = request.take() {
+            if !self.breaker.allow() {
+                return Err(HttpClientError::CircuitOpen(self.name.clone()));
+            }
+            // bodyがstreamのものは作り直せないのでリトライしない
+            if attempt < retries {
+                request = current.try_clone();
+            }
+            let result = self.send_once(current, attempt).await;
+            self.breaker
+                .record(!result.as_ref().is_err_and(HttpClientError::is_failure));
+            match result {
+                Err(e) if request.is_some() && e.is_retryable() => {
+                    tracing::warn!(service = self.name, attempt, error = %e, "retrying request");
+                    tokio::time::sleep(self.config.retry_backoff * 2u32.pow(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+        unreachable!()
+    }
+
+    pub async fn json<T: DeserializeOwned>(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<T, HttpClientError> {
+        Ok(self.send(request).await?.json().await?)
+    }
+
+    async fn send_once(
+        &self,
+        mut request: reqwest::Request,
+        attempt: u32,
+    ) -> Result<reqwest::Response, HttpClientError> {
+        let url = request.url();
+        let mut attributes = vec![
+            KeyValue::new("http.request.method", request.method().to_string()),
+            KeyValue::new("url.full", {
+                let mut url = url.clone();
+                let _ = url.set_password(None);
+                let _ = url.set_username("");
+                url.to_string()
+            }),
+        ];
+        if let Some(host) = url.host_str() {
+            attributes.push(KeyValue::new("server.address", host.to_string()));
+        }
+        if let Some(port) = url.port_or_known_default() {
+            attributes.push(KeyValue::new("server.port", i64::from(port)));
+        }
+        if attempt > 0 {
+            attributes.push(KeyValue::new(
+                "http.request.resend_count",
+                i64::from(attempt),
+            ));
+        }
+        let span = self
+            .tracer
+            .span_builder(request.method().to_string())
+            .with_kind(SpanKind::Client)
+            .with_attributes(attributes)
+            .start_with_context(self.tracer.as_ref(), &Context::current());
+        let cx = Context::current_with_span(span);
+
+        let mut injector = HeaderInjector(request.headers_mut());
+        match self.propagator.as_deref() {
+            Some(propagator) => propagator.inject_context(&cx, &mut injector),
+            None => opentelemetry::global::get_text_map_propagator(|prop| {
+                prop.inject_context(&cx, &mut injector)
+            }),
+        }
+        if let Some(request_id) = RequestId::current() {
+            if let Ok(value) = HeaderValue::from_str(&request_id.0) {
+                request.headers_mut().insert(REQUEST_ID_HEADER, value);
+            }
+        }
+
+        let result = match self.client.execute(request).await {
+            Ok(resp) if resp.status().is_success() => Ok(resp),
+            Ok(resp) => {
+                let status = resp.status();
+                let mut body = resp.text().await.unwrap_or_default();
+                if body.len() > MAX_ERROR_BODY_LENGTH {
+                    let mut end = MAX_ERROR_BODY_LENGTH;
+                    while !body.is_char_boundary(end) {
+                        end -= 1;
+                    }
+                    body.truncate(end);
+                }
+                Err(HttpClientError::Status { status, body })
+            }
+            Err(e) => Err(e.into()),
+        };
+
+        let span = cx.span();
+        match result.as_ref() {
+            Ok(resp) => span.set_attribute(KeyValue::new(
+                "http.response.status_code",
+                i64::from(resp.status().as_u16()),
+            )),
+            Err(e) => {
+                // クライアントのspanは4xxもエラーにする
+                let error_type = match e {
+                    HttpClientError::Status { status, .. } => {
+                        span.set_attribute(KeyValue::new(
+                            "http.response.status_code",
+                            i64::from(status.as_u16()),
+                        ));
+                        status.as_u16().to_string()
+                    }
+                    HttpClientError::Timeout => "timeout".to_string(),
+                    HttpClientError::Connect(_) => "connect".to_string(),
+                    _ => "_OTHER".to_string(),
+                };
+                span.set_attribute(KeyValue::new("error.type", error_type));
+                span.set_status(Status::error(e.to_string()));
+            }
+        }
+        span.end();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+    use axum::{extract::State, http::HeaderMap, routing::get, Json, Router};
+    use opentelemetry::trace::{Span as _, TracerProvider as _};
+
+    async fn start_server() -> anyhow::Result<(SocketAddr, Arc<AtomicU32>)> {
+        let calls = Arc::new(AtomicU32::new(0));
+        let router = Router::new()
+            .route(
+                "/echo",
+                get(|headers: HeaderMap| async move {
+                    let header = |name: &str| {
+                        headers
+                            .get(name)
+                            .and_then(|x| x.to_str().ok())
+                            .map(String::from)
+                    };
+                    Json(serde_json::json!({
+                        "traceparent": header("traceparent"),
+                        "request_id": header(REQUEST_ID_HEADER),
+                    }))
+                }),
+            )
+            // 2回目までは503
+            .route(
+                "/flaky",
+                get(|State(calls): State<Arc<AtomicU32>>| async move {
+                    if calls.fetch_add(1, Ordering::SeqCst) < 2 {
+                        (StatusCode::SERVICE_UNAVAILABLE, "busy")
+                    } else {
+                        (StatusCode::OK, "ok")
+                    }
+                })
+                .post(|State(calls): State<Arc<AtomicU32>>| async move {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    (StatusCode::SERVICE_UNAVAILABLE, "busy")
+                }),
+            )
+            .route(
+                "/slow",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    "slow"
+                }),
+            )
+            .route("/missing", get(|| async { StatusCode::NOT_FOUND }))
+            .route(
+                "/secret",
+                get(|| async { (StatusCode::BAD_REQUEST, "internal detail") }),
+            )
+            .with_state(calls.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        Ok((addr, calls))
+    }
+
+    fn config() -> HttpClientConfig {
+        HttpClientConfig {
+            timeout: Duration::from_millis(200),
+            retry_backoff: Duration::from_millis(1),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_propagation() -> anyhow::Result<()> {
+        let (addr, _) = start_server().await?;
+        let provider = opentelemetry_sdk::trace::TracerProvider::builder().build();
+        let client = HttpClient::new("echo", config())?
+            .with_tracer(BoxedTracer::new(Box::new(provider.tracer("test"))))
+            .with_propagator(opentelemetry_sdk::propagation::TraceContextPropagator::new());
+
+        let parent = provider.tracer("test").start("parent");
+        let trace_id = parent.span_context().trace_id();
+        let cx = Context::current_with_span(parent);
+        let body: serde_json::Value = RequestId("req-1".to_string())
+            .scope(opentelemetry::trace::FutureExt::with_context(
+                client.json(client.get(format!("http://{addr}/echo"))),
+                cx,
+            ))
+            .await?;
+        assert!(body["traceparent"]
+            .as_str()
+            .unwrap()
+            .starts_with(&format!("00-{trace_id}-")));
+        assert_eq!(body["request_id"], "req-1");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retry_and_errors() -> anyhow::Result<()> {
+        let (addr, calls) = start_server().await?;
+        let client = HttpClient::new("flaky", config())?;
+
+        let resp = client
+            .send(client.get(format!("http://{addr}/flaky")))
+            .await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(calls.swap(0, Ordering::SeqCst), 3);
+
+        // POSTはリトライしない
+        let err = client
+            .send(client.post(format!("http://{addr}/flaky")))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), Some(StatusCode::SERVICE_UNAVAILABLE));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let err = client
+            .send(client.get(format!("http://{addr}/missing")))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), Some(StatusCode::NOT_FOUND));
+        #[cfg(feature = "with-graphql")]
+        {
+            let err = err.to_graphql_error();
+            assert_eq!(err.message, "upstream resource not found");
+            assert_eq!(
+                err.extensions.unwrap().get("code").cloned(),
+                Some(async_graphql::Value::from("NOT_FOUND"))
+            );
+        }
+
+        // 相手のレスポンスの中身は返さない
+        let err = client
+            .send(client.get(format!("http://{addr}/secret")))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("internal detail"), "{err}");
+        #[cfg(feature = "with-graphql")]
+        assert_eq!(err.to_graphql_error().message, "upstream request failed");
+        let resp = err.into_response();
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(
+            axum::body::to_bytes(resp.into_body(), usize::MAX).await?,
+            "upstream request failed"
+        );
+
+        let client = HttpClient::new(
+            "slow",
+            HttpClientConfig {
+                max_retries: 0,
+                ..config()
+            },
+        )?;
+        let err = client
+            .send(client.get(format!("http://{addr}/slow")))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, HttpClientError::Timeout), "{err}");
+        assert_eq!(err.into_response().status(), StatusCode::GATEWAY_TIMEOUT);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker() -> anyhow::Result<()> {
+        let (addr, calls) = start_server().await?;
+        let client = HttpClient::new(
+            "flaky",
+            HttpClientConfig {
+                max_retries: 0,
+                failure_threshold: 2,
+                open_duration: Duration::from_millis(100),
+                ..config()
+            },
+        )?;
+        let url = format!("http://{addr}/flaky");
+
+        for _ in 0..2 {
+            let err = client.send(client.get(&url)).await.unwrap_err();
+            assert_eq!(err.status(), Some(StatusCode::SERVICE_UNAVAILABLE));
+        }
+        // 開いている間は送らない
+        let err = client.send(client.get(&url)).await.unwrap_err();
+        assert!(matches!(err, HttpClientError::CircuitOpen(_)), "{err}");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // 時間が過ぎたら1つ通し、成功したら閉じる
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(
+            client.send(client.get(&url)).await?.status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            client.send(client.get(&url)).await?.status(),
+            StatusCode::OK
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,357 @@
+// すべてのルートにHTTPサーバーのspanを作るtowerのLayer
+// 親は登録されているpropagator (OTEL_PROPAGATORS。with_propagatorで変えられる) でヘッダーから取り出し、
+// レスポンスにはtraceresponseヘッダー (W3C Trace Context Level 2) を付ける
+//
+// ```ignore
+// let router = router.layer(http_trace::HttpTraceLayer::new());
+// ```
+// Router::layerで付けるとMatchedPathがあるので、span名は `GET /users/:id` のようになる
+// 5xxとinnerのサービスのエラーはspanのstatusをerrorにする (4xxはクライアントのエラーなのでそのまま)
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use axum::{
+    extract::{ConnectInfo, MatchedPath},
+    http::{header, HeaderValue, Request, Response, Version},
+};
+use opentelemetry::{
+    global::BoxedTracer,
+    propagation::TextMapPropagator,
+    trace::{FutureExt, SpanKind, Status, TraceContextExt, Tracer},
+    KeyValue,
+};
+use tower::{Layer, Service};
+
+use super::parent_trace_context::ParentTraceContext;
+
+pub const TRACERESPONSE_HEADER: &str = "traceresponse";
+const TRACER_NAME: &str = "http";
+
+type Propagator = Arc<dyn TextMapPropagator + Send + Sync>;
+
+#[derive(Debug, Clone)]
+pub struct HttpTraceLayer {
+    tracer: Arc<BoxedTracer>,
+    // Noneならグローバルに登録されているもの
+    propagator: Option<Propagator>,
+}
+
+impl Default for HttpTraceLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HttpTraceLayer {
+    pub fn new() -> Self {
+        Self::from_tracer(opentelemetry::global::tracer(TRACER_NAME))
+    }
+
+    // グローバルに登録していないTracerProviderを使うとき
+    pub fn from_tracer(tracer: BoxedTracer) -> Self {
+        Self {
+            tracer: Arc::new(tracer),
+            propagator: None,
+        }
+    }
+
+    pub fn with_propagator(
+        mut self,
+        propagator: impl TextMapPropagator + Send + Sync + 'static,
+    ) -> Self {
+        self.propagator = Some(Arc::new(propagator));
+        self
+    }
+}
+
+impl<S> Layer<S> for HttpTraceLayer {
+    type Service = HttpTrace<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        HttpTrace {
+            inner,
+            tracer: self.tracer.clone(),
+            propagator: self.propagator.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpTrace<S> {
+    inner: S,
+    tracer: Arc<BoxedTracer>,
+    propagator: Option<Propagator>,
+}
+
+fn header_str<B>(req: &Request<B>, name: header::HeaderName) -> Option<&str> {
+    req.headers().get(name).and_then(|x| x.to_str().ok())
+}
+
+// プロキシの後ろならx-forwarded-forの最初、なければ接続元
+fn client_address<B>(req: &Request<B>) -> Option<String> {
+    header_str(req, header::HeaderName::from_static("x-forwarded-for"))
+        .and_then(|x| x.split(',').next())
+        .map(|x| x.trim().to_string())
+        .filter(|x| !x.is_empty())
+        .or_else(|| {
+            req.extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|x| x.0.ip().to_string())
+        })
+}
+
+fn protocol_version(version: Version) -> &'static str {
+    match version {
+        Version::HTTP_09 => "0.9",
+        Version::HTTP_10 => "1.0",
+        Version::HTTP_11 => "1.1",
+        Version::HTTP_2 => "2",
+        Version::HTTP_3 => "3",
+        _ => "",
+    }
+}
+
+// 値にはトークンやGraphQLのクエリ、変数が入るので、キーだけ残す (`a=1&b=2` -> `a&b`)
+fn query_keys(query: &str) -> String {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split('=').next())
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+fn request_attributes<B>(req: &Request<B>, route: Option<&str>) -> Vec<KeyValue> {
+    let mut attributes = vec![
+        KeyValue::new("http.request.method", req.method().to_string()),
+        KeyValue::new("url.path", req.uri().path().to_string()),
+        KeyValue::new("network.protocol.version", protocol_version(req.version())),
+    ];
+    if let Some(route) = route {
+        attributes.push(KeyValue::new("http.route", route.to_string()));
+    }
+    if let Some(query) = req.uri().query().map(query_keys).filter(|x| !x.is_empty()) {
+        attributes.push(KeyValue::new("url.query", query));
+    }
+    if let Some(scheme) = req.uri().scheme_str() {
+        attributes.push(KeyValue::new("url.scheme", scheme.to_string()));
+    }
+    if let Some(host) = header_str(req, header::HOST) {
+        attributes.push(KeyValue::new("server.address", host.to_string()));
+    }
+    if let Some(user_agent) = header_str(req, header::USER_AGENT) {
+        attributes.push(KeyValue::new("user_agent.original", user_agent.to_string()));
+    }
+    if let Some(client) = client_address(req) {
+        attributes.push(KeyValue::new("client.address", client));
+    }
+    attributes
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for HttpTrace<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: std::fmt::Display,
+    ReqBody: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        // poll_readyしたものを使う
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let parent_cx = match self.propagator.as_deref() {
+            Some(propagator) => ParentTraceContext::from_headers_with(req.headers(), propagator)
+                .extract_with(propagator),
+            None => ParentTraceContext::from_headers(req.headers()).get(),
+        };
+        let route = req
+            .extensions()
+            .get::<MatchedPath>()
+            .map(|x| x.as_str().to_string());
+        let name = match route.as_deref() {
+            Some(route) => format!("{} {route}", req.method()),
+            None => req.method().to_string(),
+        };
+        let span = self
+            .tracer
+            .span_builder(name)
+            .with_kind(SpanKind::Server)
+            .with_attributes(request_attributes(&req, route.as_deref()))
+            .start_with_context(self.tracer.as_ref(), &parent_cx);
+        let cx = parent_cx.with_span(span);
+        let span_cx = cx.clone();
+
+        Box::pin(
+            async move {
+                let span = span_cx.span();
+                let result = inner.call(req).await;
+                match result.as_ref() {
+                    Ok(resp) => {
+                        let status = resp.status();
+                        span.set_attribute(KeyValue::new(
+                            "http.response.status_code",
+                            i64::from(status.as_u16()),
+                        ));
+                        if status.is_server_error() {
+                            span.set_attribute(KeyValue::new(
+                                "error.type",
+                                status.as_u16().to_string(),
+                            ));
+                            span.set_status(Status::error(status.to_string()));
+                        }
+                    }
+                    Err(e) => {
+                        span.set_attribute(KeyValue::new("error.type", "_OTHER"));
+                        span.add_event(
+                            "exception",
+                            vec![KeyValue::new("exception.message", e.to_string())],
+                        );
+                        span.set_status(Status::error(e.to_string()));
+                    }
+                }
+                let span_context = span.span_context().clone();
+                span.end();
+
+                result.map(|mut resp| {
+                    if span_context.is_valid() {
+                        let value = format!(
+                            "00-{}-{}-{:02x}",
+                            span_context.trace_id(),
+                            span_context.span_id(),
+                            span_context.trace_flags().to_u8()
+                        );
+                        if let Ok(value) = HeaderValue::from_str(&value) {
+                            resp.headers_mut().insert(TRACERESPONSE_HEADER, value);
+                        }
+                    }
+                    resp
+                })
+            }
+            .with_context(cx),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::{
+        otel_setup::build_tracer_provider,
+        otlp_collector::OtlpCollector,
+        tracing_config::{ExporterConfig, Protocol, TracingConfig},
+    };
+    use axum::{body::Body, http::StatusCode, routing::get, Router};
+    use opentelemetry::trace::TracerProvider as _;
+    use tower::ServiceExt;
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+    fn attribute<'a>(span: &'a serde_json::Value, key: &str) -> &'a serde_json::Value {
+        span["attributes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|x| x["key"] == key)
+            .map(|x| &x["value"])
+            .unwrap_or(&serde_json::Value::Null)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_http_trace() -> anyhow::Result<()> {
+        let collector = OtlpCollector::start().await?;
+        let config = TracingConfig {
+            exporter: Some(ExporterConfig {
+                protocol: Protocol::HttpJson,
+                ..ExporterConfig::new(collector.endpoint())
+            }),
+            ..Default::default()
+        };
+        let provider = build_tracer_provider(&config)?.unwrap();
+
+        let router = Router::new()
+            .route("/users/:id", get(|| async { "ok" }))
+            .route(
+                "/fail",
+                get(|| async { (StatusCode::INTERNAL_SERVER_ERROR, "fail") }),
+            )
+            .layer(
+                HttpTraceLayer::from_tracer(BoxedTracer::new(Box::new(provider.tracer("test"))))
+                    .with_propagator(opentelemetry_sdk::propagation::TraceContextPropagator::new()),
+            );
+
+        let resp = router
+            .clone()
+            .oneshot(
+                Request::get("/users/1?x=1&token=secret&flag")
+                    .header("traceparent", format!("00-{TRACE_ID}-00f067aa0ba902b7-01"))
+                    .header("user-agent", "test-agent")
+                    .header("x-forwarded-for", "203.0.113.1, 10.0.0.1")
+                    .body(Body::empty())?,
+            )
+            .await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let traceresponse = resp.headers()[TRACERESPONSE_HEADER].to_str()?.to_string();
+        assert!(traceresponse.starts_with(&format!("00-{TRACE_ID}-")));
+        assert!(traceresponse.ends_with("-01"));
+
+        let resp = router
+            .oneshot(Request::get("/fail").body(Body::empty())?)
+            .await?;
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let provider = tokio::task::spawn_blocking(move || {
+            assert!(provider.force_flush().iter().all(Result::is_ok));
+            provider
+        })
+        .await?;
+
+        let spans = collector.spans();
+        let span = spans
+            .iter()
+            .find(|x| x["name"] == "GET /users/:id")
+            .unwrap();
+        // SpanKind::Server
+        assert_eq!(span["kind"], 2);
+        assert_eq!(span["traceId"], TRACE_ID);
+        assert_eq!(span["parentSpanId"], "00f067aa0ba902b7");
+        assert_eq!(
+            traceresponse,
+            format!("00-{TRACE_ID}-{}-01", span["spanId"].as_str().unwrap())
+        );
+        assert_eq!(attribute(span, "http.route")["stringValue"], "/users/:id");
+        assert_eq!(attribute(span, "http.request.method")["stringValue"], "GET");
+        assert_eq!(attribute(span, "url.query")["stringValue"], "x&token&flag");
+        assert_eq!(
+            attribute(span, "http.response.status_code")["intValue"],
+            "200"
+        );
+        assert_eq!(
+            attribute(span, "user_agent.original")["stringValue"],
+            "test-agent"
+        );
+        assert_eq!(
+            attribute(span, "client.address")["stringValue"],
+            "203.0.113.1"
+        );
+
+        let span = spans.iter().find(|x| x["name"] == "GET /fail").unwrap();
+        // STATUS_CODE_ERROR
+        assert_eq!(span["status"]["code"], 2);
+        assert_eq!(attribute(span, "error.type")["stringValue"], "500");
+
+        tokio::task::spawn_blocking(move || provider.shutdown()).await??;
+        Ok(())
+    }
+}
@@ -0,0 +1,230 @@
+// 標準出力のログを1行1つのJSONにする (LOG_FORMAT=json)
+//
+// ```json
+// {"timestamp":"2024-01-01T00:00:00.000000Z","level":"INFO","target":"app","file":"src/main.rs","line":10,
+//  "message":"hello","fields":{"user_id":1},"spans":[{"name":"request","method":"GET"}],
+//  "trace_id":"...","span_id":"...","request_id":"..."}
+// ```
+// trace_id/span_idはtracing-opentelemetryのspanか、なければ今のOpenTelemetryのContextから取る (with-opentelemetry)
+// spanのフィールドもJSONで持っておきたいので、fmt_fieldsにJsonFieldsを一緒に指定する
+//
+// tracing-subscriberのjson featureはtracing-serdeが必要なので使わずに自前で書いている
+use std::fmt;
+
+#[cfg(feature = "with-opentelemetry")]
+use opentelemetry::trace::TraceContextExt;
+use serde_json::{Map, Value};
+use tracing::{field::Field, span::Record, Event, Subscriber};
+use tracing_subscriber::{
+    field::RecordFields,
+    fmt::{
+        format::Writer,
+        time::{FormatTime, SystemTime},
+        FmtContext, FormatEvent, FormatFields, FormattedFields,
+    },
+    registry::LookupSpan,
+};
+
+#[cfg(feature = "with-opentelemetry")]
+use super::otel_log_layer::span_context;
+
+struct Visitor<'a>(&'a mut Map<String, Value>);
+
+impl Visitor<'_> {
+    fn insert(&mut self, field: &Field, value: Value) {
+        self.0.insert(field.name().to_string(), value);
+    }
+}
+
+impl tracing::field::Visit for Visitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert(field, Value::from(format!("{value:?}")))
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, Value::from(value))
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, Value::from(value))
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, Value::from(value))
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, Value::from(value))
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, Value::from(value))
+    }
+}
+
+// spanのフィールドをJSONのオブジェクトとしてextensionsに入れておく
+#[derive(Debug, Default, Clone, Copy)]
+pub struct JsonFields;
+
+impl<'writer> FormatFields<'writer> for JsonFields {
+    fn format_fields<R: RecordFields>(
+        &self,
+        mut writer: Writer<'writer>,
+        fields: R,
+    ) -> fmt::Result {
+        let mut map = Map::new();
+        fields.record(&mut Visitor(&mut map));
+        write!(writer, "{}", Value::Object(map))
+    }
+
+    // span.recordで後から追加されたフィールドは既存のオブジェクトにマージする
+    fn add_fields(
+        &self,
+        current: &'writer mut FormattedFields<Self>,
+        fields: &Record<'_>,
+    ) -> fmt::Result {
+        let mut map = serde_json::from_str(&current.fields).unwrap_or_default();
+        fields.record(&mut Visitor(&mut map));
+        current.fields = Value::Object(map).to_string();
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct JsonFormat;
+
+impl<S> FormatEvent<S, JsonFields> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, JsonFields>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let metadata = event.metadata();
+        let mut line = Map::new();
+
+        let mut timestamp = String::new();
+        SystemTime.format_time(&mut Writer::new(&mut timestamp))?;
+        line.insert("timestamp".into(), timestamp.into());
+        line.insert("level".into(), metadata.level().as_str().into());
+        line.insert("target".into(), metadata.target().into());
+        if let Some(file) = metadata.file() {
+            line.insert("file".into(), file.into());
+        }
+        if let Some(number) = metadata.line() {
+            line.insert("line".into(), number.into());
+        }
+
+        let mut fields = Map::new();
+        event.record(&mut Visitor(&mut fields));
+        if let Some(message) = fields.remove("message") {
+            line.insert("message".into(), message);
+        }
+        if !fields.is_empty() {
+            line.insert("fields".into(), fields.into());
+        }
+
+        let spans: Vec<Value> = ctx
+            .event_scope()
+            .into_iter()
+            .flat_map(|scope| scope.from_root())
+            .map(|span| {
+                let mut fields = span
+                    .extensions()
+                    .get::<FormattedFields<JsonFields>>()
+                    .and_then(|x| serde_json::from_str(&x.fields).ok())
+                    .unwrap_or_else(Map::new);
+                fields.insert("name".into(), span.name().into());
+                Value::Object(fields)
+            })
+            .collect();
+        if !spans.is_empty() {
+            line.insert("spans".into(), spans.into());
+        }
+
+        #[cfg(feature = "with-opentelemetry")]
+        let span_context = ctx.event_scope().and_then(span_context).or_else(|| {
+            let cx = opentelemetry::Context::current();
+            let span_context = cx.span().span_context().clone();
+            span_context.is_valid().then_some(span_context)
+        });
+        #[cfg(feature = "with-opentelemetry")]
+        if let Some(span_context) = span_context {
+            line.insert(
+                "trace_id".into(),
+                span_context.trace_id().to_string().into(),
+            );
+            line.insert("span_id".into(), span_context.span_id().to_string().into());
+        }
+
+        #[cfg(feature = "with-axum")]
+        if let Some(request_id) = super::request_id::RequestId::current() {
+            line.insert("request_id".into(), request_id.0.into());
+        }
+
+        writeln!(writer, "{}", Value::Object(line))
+    }
+}
+
+#[cfg(all(test, feature = "with-opentelemetry"))]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::TracerProvider as _;
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_json_format() -> anyhow::Result<()> {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let provider = opentelemetry_sdk::trace::TracerProvider::builder().build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")))
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .fmt_fields(JsonFields)
+                    .event_format(JsonFormat)
+                    .with_writer(move || writer.clone()),
+            );
+        tracing::subscriber::with_default(subscriber, || {
+            let span =
+                tracing::info_span!("request", method = "GET", user_id = tracing::field::Empty);
+            span.record("user_id", 1);
+            let _enter = span.enter();
+            tracing::info!(count = 2, "hello");
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone())?;
+        let line: Value = serde_json::from_str(output.trim())?;
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["message"], "hello");
+        assert_eq!(line["fields"]["count"], 2);
+        assert_eq!(
+            line["spans"][0],
+            serde_json::json!({"name": "request", "method": "GET", "user_id": 1})
+        );
+        assert_eq!(line["trace_id"].as_str().unwrap().len(), 32);
+        assert_eq!(line["span_id"].as_str().unwrap().len(), 16);
+        assert!(line["file"]
+            .as_str()
+            .unwrap()
+            .ends_with("json_log_format.rs"));
+        Ok(())
+    }
+}
@@ -0,0 +1,70 @@
+use async_graphql::{InputValueError, InputValueResult, Scalar, ScalarType, Value};
+use serde::{Deserialize, Serialize};
+
+// 日付のない時刻 (HH:MM[:SS[.fff]])。出力は秒まで付ける
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct LocalTime(#[serde(with = "time_serializer")] pub chrono::NaiveTime);
+
+impl LocalTime {
+    pub fn new(t: chrono::NaiveTime) -> LocalTime {
+        LocalTime(t)
+    }
+}
+
+fn parse_time(value: &str) -> chrono::ParseResult<chrono::NaiveTime> {
+    chrono::NaiveTime::parse_from_str(value, "%H:%M:%S%.f")
+        .or_else(|_| chrono::NaiveTime::parse_from_str(value, "%H:%M"))
+}
+
+#[Scalar]
+impl ScalarType for LocalTime {
+    fn parse(value: Value) -> InputValueResult<Self> {
+        if let Value::String(value) = &value {
+            parse_time(value).map(LocalTime).map_err(|e| {
+                InputValueError::custom(format!("invalid time format HH:MM[:SS]. error={e:?}"))
+            })
+        } else {
+            Err(InputValueError::expected_type(value))
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        Value::String(self.0.to_string())
+    }
+}
+
+mod time_serializer {
+    use chrono::NaiveTime;
+    use serde::{de::Error, Deserialize, Deserializer, Serialize as _, Serializer};
+
+    pub fn serialize<S: Serializer>(time: &NaiveTime, serializer: S) -> Result<S::Ok, S::Error> {
+        time.to_string().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveTime, D::Error> {
+        let time_str: String = Deserialize::deserialize(deserializer)?;
+        super::parse_time(&time_str).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize() -> anyhow::Result<()> {
+        let t = LocalTime::parse(Value::String("09:30".to_string())).unwrap();
+        assert_eq!(t.0, chrono::NaiveTime::from_hms_opt(9, 30, 0).unwrap());
+        assert_eq!(t.to_value(), Value::String("09:30:00".to_string()));
+
+        let t = LocalTime::parse(Value::String("23:59:30.5".to_string())).unwrap();
+        assert_eq!(t.to_value(), Value::String("23:59:30.500".to_string()));
+        let parsed: LocalTime = serde_json::from_str(&serde_json::to_string(&t)?)?;
+        assert_eq!(parsed, t);
+
+        for value in ["24:00", "9", "09:30+09:00"] {
+            assert!(LocalTime::parse(Value::String(value.to_string())).is_err());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,282 @@
+// 標準出力・Sentry・トレースに使うフィルタ (RUST_LOG) を実行中に変える
+//
+// - set: 一時的にdirectivesを変える。ttlが経ったら元 (default) に戻る
+// - set_default: 元のdirectivesを変える (SIGHUPで設定を読み直したとき)
+// - 管理用のHTTPエンドポイント (ADMIN_TOKENのBearerトークンが必要)
+//   - GET /admin/log-filter: 今の状態
+//   - PUT /admin/log-filter {"directives": "info,app=debug", "ttl_secs": 600}
+//   - DELETE /admin/log-filter: 元に戻す
+//   - require_token: ほかのルート (/metrics) にも同じトークンを要求する
+//
+// ```ignore
+// let router = router.merge(log_filter::router(guard.log_filter(), token));
+// ```
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::time::Instant;
+use tracing_subscriber::{reload, EnvFilter};
+
+#[cfg(feature = "with-axum")]
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+
+// ttlを指定しなかったときと、指定できる最大
+pub const DEFAULT_TTL: Duration = Duration::from_secs(10 * 60);
+pub const MAX_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+type Reload = dyn Fn(EnvFilter) -> anyhow::Result<()> + Send + Sync;
+
+struct Inner {
+    default: String,
+    directives: String,
+    expires_at: Option<Instant>,
+    // ttlで戻すときに、その間に別の値がsetされていたら戻さない
+    generation: u64,
+}
+
+#[derive(Clone)]
+pub struct LogFilter {
+    reload: Arc<Reload>,
+    state: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LogFilterStatus {
+    pub directives: String,
+    pub default: String,
+    pub ttl_remaining_secs: Option<u64>,
+}
+
+impl LogFilter {
+    pub fn new<S: 'static>(handle: reload::Handle<EnvFilter, S>, default: String) -> Self {
+        Self {
+            reload: Arc::new(move |filter| Ok(handle.reload(filter)?)),
+            state: Arc::new(Mutex::new(Inner {
+                directives: default.clone(),
+                default,
+                expires_at: None,
+                generation: 0,
+            })),
+        }
+    }
+
+    pub fn status(&self) -> LogFilterStatus {
+        let state = self.state.lock().unwrap();
+        LogFilterStatus {
+            directives: state.directives.clone(),
+            default: state.default.clone(),
+            ttl_remaining_secs: state
+                .expires_at
+                .map(|x| x.saturating_duration_since(Instant::now()).as_secs()),
+        }
+    }
+
+    fn apply(
+        &self,
+        state: &mut Inner,
+        directives: String,
+        ttl: Option<Duration>,
+    ) -> anyhow::Result<u64> {
+        (self.reload)(EnvFilter::try_new(&directives)?)?;
+        state.directives = directives;
+        state.expires_at = ttl.map(|x| Instant::now() + x);
+        state.generation += 1;
+        Ok(state.generation)
+    }
+
+    // ttlが経ったらdefaultに戻す。tokioのランタイムの中で呼ぶ
+    pub fn set(&self, directives: &str, ttl: Duration) -> anyhow::Result<()> {
+        anyhow::ensure!(ttl <= MAX_TTL, "ttl must be at most {}s", MAX_TTL.as_secs());
+        let generation = {
+            let mut state = self.state.lock().unwrap();
+            self.apply(&mut state, directives.to_string(), Some(ttl))?
+        };
+        tracing::warn!(directives, ttl_secs = ttl.as_secs(), "log filter changed");
+
+        let this = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(ttl).await;
+            let mut state = this.state.lock().unwrap();
+            if state.generation == generation {
+                let default = state.default.clone();
+                if let Err(e) = this.apply(&mut state, default, None) {
+                    tracing::error!("failed to revert log filter: {e}");
+                }
+            }
+        });
+        Ok(())
+    }
+
+    pub fn reset(&self) -> anyhow::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let default = state.default.clone();
+        self.apply(&mut state, default, None)?;
+        Ok(())
+    }
+
+    // 一時的な変更も取り消して新しいdefaultにする
+    pub fn set_default(&self, directives: &str) -> anyhow::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        self.apply(&mut state, directives.to_string(), None)?;
+        state.default = directives.to_string();
+        Ok(())
+    }
+}
+
+#[cfg(feature = "with-axum")]
+#[derive(Debug, serde::Deserialize)]
+struct SetLogFilter {
+    directives: String,
+    #[serde(default)]
+    ttl_secs: Option<u64>,
+}
+
+#[cfg(feature = "with-axum")]
+async fn get_filter(State(filter): State<LogFilter>) -> Json<LogFilterStatus> {
+    Json(filter.status())
+}
+
+#[cfg(feature = "with-axum")]
+async fn put_filter(State(filter): State<LogFilter>, Json(body): Json<SetLogFilter>) -> Response {
+    let ttl = body.ttl_secs.map_or(DEFAULT_TTL, Duration::from_secs);
+    match filter.set(&body.directives, ttl) {
+        Ok(()) => Json(filter.status()).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[cfg(feature = "with-axum")]
+async fn delete_filter(State(filter): State<LogFilter>) -> Response {
+    match filter.reset() {
+        Ok(()) => Json(filter.status()).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+// 長さで早く抜けないように全部比べる
+#[cfg(feature = "with-axum")]
+fn token_matches(expected: &str, actual: &str) -> bool {
+    expected.len() == actual.len()
+        && expected
+            .bytes()
+            .zip(actual.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+#[cfg(feature = "with-axum")]
+async fn authorize(State(token): State<Arc<str>>, req: Request, next: Next) -> Response {
+    let authorized = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.strip_prefix("Bearer "))
+        .is_some_and(|x| token_matches(&token, x));
+    if authorized {
+        next.run(req).await
+    } else {
+        StatusCode::UNAUTHORIZED.into_response()
+    }
+}
+
+// ほかの管理用のエンドポイント (/metrics) にも同じトークンを要求する
+#[cfg(feature = "with-axum")]
+pub fn require_token(router: Router, token: &str) -> Router {
+    router.layer(axum::middleware::from_fn_with_state(
+        Arc::<str>::from(token),
+        authorize,
+    ))
+}
+
+#[cfg(feature = "with-axum")]
+pub fn router(filter: LogFilter, token: &str) -> Router {
+    require_token(
+        Router::new()
+            .route(
+                "/admin/log-filter",
+                get(get_filter).put(put_filter).delete(delete_filter),
+            )
+            .with_state(filter),
+        token,
+    )
+}
+
+#[cfg(all(test, feature = "with-axum"))]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use tower::ServiceExt;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    fn request(method: &str, token: &str, body: Option<&str>) -> anyhow::Result<Request> {
+        Ok(axum::http::Request::builder()
+            .method(method)
+            .uri("/admin/log-filter")
+            .header(header::AUTHORIZATION, format!("Bearer {token}"))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(body.map(|x| Body::from(x.to_string())).unwrap_or_default())?)
+    }
+
+    async fn status(
+        router: &Router,
+        req: Request,
+    ) -> anyhow::Result<(StatusCode, serde_json::Value)> {
+        let resp = router.clone().oneshot(req).await?;
+        let status = resp.status();
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await?;
+        Ok((status, serde_json::from_slice(&body).unwrap_or_default()))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_log_filter() -> anyhow::Result<()> {
+        let (layer, handle) = reload::Layer::new(EnvFilter::new("warn"));
+        let subscriber = tracing_subscriber::registry().with(layer);
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let filter = LogFilter::new(handle, "warn".to_string());
+        let router = router(filter.clone(), "secret");
+
+        assert!(!tracing::enabled!(tracing::Level::DEBUG));
+        let (code, _) = status(&router, request("GET", "wrong", None)?).await?;
+        assert_eq!(code, StatusCode::UNAUTHORIZED);
+
+        let (code, body) = status(
+            &router,
+            request(
+                "PUT",
+                "secret",
+                Some(r#"{"directives":"debug","ttl_secs":60}"#),
+            )?,
+        )
+        .await?;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(body["directives"], "debug");
+        assert_eq!(body["ttl_remaining_secs"], 60);
+        assert!(tracing::enabled!(tracing::Level::DEBUG));
+
+        let (code, _) = status(
+            &router,
+            request("PUT", "secret", Some(r#"{"directives":"app=loud"}"#))?,
+        )
+        .await?;
+        assert_eq!(code, StatusCode::BAD_REQUEST);
+
+        // ttlが経つと元に戻る
+        tokio::time::sleep(Duration::from_secs(61)).await;
+        assert_eq!(filter.status().directives, "warn");
+        assert!(!tracing::enabled!(tracing::Level::DEBUG));
+
+        filter.set_default("info")?;
+        let (_, body) = status(&router, request("GET", "secret", None)?).await?;
+        assert_eq!(body["default"], "info");
+        assert!(tracing::enabled!(tracing::Level::INFO));
+        Ok(())
+    }
+}
@@ -0,0 +1,363 @@
+// メトリクスの共通定義と、axumのルートごとのREDメトリクス、Prometheusの `/metrics`
+//
+// ```ignore
+// let router = router
+//     .layer(axum::middleware::from_fn_with_state(HttpMetrics::new(), metrics::track_http))
+//     .merge(log_filter::require_token(
+//         Router::new().route("/metrics", get(metrics::prometheus_handler).layer(Extension(exporter))),
+//         &admin_token,
+//     ));
+// ```
+// /metricsはoperation名などを含むので、管理用のエンドポイントと同じくトークンを要求する
+// http.routeにはMatchedPath (`/users/:id` のようなパターン) を使うので、パスの値ごとに系列が増えることはない
+//
+// opentelemetry-prometheusはopentelemetry 0.25に対応したものがないので、
+// ManualReaderで集めたものを自前でPrometheusのtext formatにしている
+use std::fmt::Write;
+use std::sync::{Arc, Weak};
+
+use opentelemetry_sdk::metrics::{
+    data::{Gauge, Histogram as HistogramData, ResourceMetrics, Sum, Temporality},
+    reader::{AggregationSelector, MetricReader, TemporalitySelector},
+    Aggregation, InstrumentKind, ManualReader, Pipeline,
+};
+#[cfg(feature = "with-axum")]
+use std::time::Instant;
+
+#[cfg(feature = "with-axum")]
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
+#[cfg(feature = "with-axum")]
+use opentelemetry::{
+    metrics::{Histogram, Meter, UpDownCounter},
+    KeyValue,
+};
+
+pub const METER_NAME: &str = "rust-web-tools";
+
+// 秒単位のdurationに使うバケット (OpenTelemetryのsemantic conventionsの推奨値)
+pub const DURATION_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.075, 0.1, 0.25, 0.5, 0.75, 1.0, 2.5, 5.0, 7.5, 10.0,
+];
+
+#[cfg(feature = "with-axum")]
+#[derive(Clone)]
+pub struct HttpMetrics {
+    duration: Histogram<f64>,
+    active_requests: UpDownCounter<i64>,
+}
+
+#[cfg(feature = "with-axum")]
+impl Default for HttpMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "with-axum")]
+impl HttpMetrics {
+    pub fn new() -> Self {
+        Self::from_meter(&opentelemetry::global::meter(METER_NAME))
+    }
+
+    pub fn from_meter(meter: &Meter) -> Self {
+        Self {
+            duration: meter
+                .f64_histogram("http.server.request.duration")
+                .with_unit("s")
+                .with_description("Duration of HTTP server requests")
+                .init(),
+            active_requests: meter
+                .i64_up_down_counter("http.server.active_requests")
+                .with_description("Number of in-flight HTTP server requests")
+                .init(),
+        }
+    }
+}
+
+#[cfg(feature = "with-axum")]
+pub async fn track_http(State(metrics): State<HttpMetrics>, req: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let method = KeyValue::new("http.request.method", req.method().to_string());
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|x| x.as_str().to_string())
+        .unwrap_or_default();
+    let route = KeyValue::new("http.route", route);
+
+    metrics
+        .active_requests
+        .add(1, &[method.clone(), route.clone()]);
+    let resp = next.run(req).await;
+    metrics
+        .active_requests
+        .add(-1, &[method.clone(), route.clone()]);
+
+    metrics.duration.record(
+        start.elapsed().as_secs_f64(),
+        &[
+            method,
+            route,
+            KeyValue::new(
+                "http.response.status_code",
+                i64::from(resp.status().as_u16()),
+            ),
+        ],
+    );
+    resp
+}
+
+#[cfg(feature = "with-axum")]
+pub async fn prometheus_handler(Extension(exporter): Extension<PrometheusExporter>) -> Response {
+    match exporter.render() {
+        Ok(body) => ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PrometheusExporter {
+    reader: Arc<ManualReader>,
+}
+
+impl Default for PrometheusExporter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PrometheusExporter {
+    pub fn new() -> Self {
+        Self {
+            reader: Arc::new(ManualReader::builder().build()),
+        }
+    }
+
+    pub fn render(&self) -> anyhow::Result<String> {
+        let mut metrics = ResourceMetrics {
+            resource: opentelemetry_sdk::Resource::empty(),
+            scope_metrics: vec![],
+        };
+        self.reader.collect(&mut metrics)?;
+
+        let mut out = String::new();
+        for metric in metrics.scope_metrics.iter().flat_map(|x| &x.metrics) {
+            let mut name = sanitize(&metric.name);
+            if metric.unit == "s" {
+                name.push_str("_seconds");
+            }
+            let data = metric.data.as_any();
+            let _ = render_numbers::<u64>(&mut out, &name, &metric.description, data)
+                || render_numbers::<i64>(&mut out, &name, &metric.description, data)
+                || render_numbers::<f64>(&mut out, &name, &metric.description, data)
+                || render_histogram::<u64>(&mut out, &name, &metric.description, data)
+                || render_histogram::<i64>(&mut out, &name, &metric.description, data)
+                || render_histogram::<f64>(&mut out, &name, &metric.description, data);
+        }
+        Ok(out)
+    }
+}
+
+impl TemporalitySelector for PrometheusExporter {
+    fn temporality(&self, _kind: InstrumentKind) -> Temporality {
+        Temporality::Cumulative
+    }
+}
+
+impl AggregationSelector for PrometheusExporter {
+    fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+        self.reader.aggregation(kind)
+    }
+}
+
+impl MetricReader for PrometheusExporter {
+    fn register_pipeline(&self, pipeline: Weak<Pipeline>) {
+        self.reader.register_pipeline(pipeline)
+    }
+
+    fn collect(&self, rm: &mut ResourceMetrics) -> opentelemetry::metrics::Result<()> {
+        self.reader.collect(rm)
+    }
+
+    fn force_flush(&self) -> opentelemetry::metrics::Result<()> {
+        self.reader.force_flush()
+    }
+
+    fn shutdown(&self) -> opentelemetry::metrics::Result<()> {
+        self.reader.shutdown()
+    }
+}
+
+trait AsF64: Copy + 'static {
+    fn as_f64(self) -> f64;
+}
+
+impl AsF64 for u64 {
+    fn as_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl AsF64 for i64 {
+    fn as_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl AsF64 for f64 {
+    fn as_f64(self) -> f64 {
+        self
+    }
+}
+
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == ':' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn labels(attributes: &[opentelemetry::KeyValue], extra: Option<(&str, String)>) -> String {
+    let mut labels: Vec<(String, String)> = attributes
+        .iter()
+        .map(|kv| (sanitize(kv.key.as_str()), kv.value.as_str().to_string()))
+        .collect();
+    labels.sort();
+    labels.extend(extra.map(|(k, v)| (k.to_string(), v)));
+    if labels.is_empty() {
+        return String::new();
+    }
+    let labels: Vec<String> = labels
+        .into_iter()
+        .map(|(k, v)| {
+            let v = v
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{k}=\"{v}\"")
+        })
+        .collect();
+    format!("{{{}}}", labels.join(","))
+}
+
+fn header(out: &mut String, name: &str, description: &str, ty: &str) {
+    if !description.is_empty() {
+        let _ = writeln!(out, "# HELP {name} {description}");
+    }
+    let _ = writeln!(out, "# TYPE {name} {ty}");
+}
+
+fn render_numbers<T: AsF64>(
+    out: &mut String,
+    name: &str,
+    description: &str,
+    data: &dyn std::any::Any,
+) -> bool {
+    let (name, ty, points) = if let Some(sum) = data.downcast_ref::<Sum<T>>() {
+        if sum.is_monotonic {
+            (format!("{name}_total"), "counter", &sum.data_points)
+        } else {
+            (name.to_string(), "gauge", &sum.data_points)
+        }
+    } else if let Some(gauge) = data.downcast_ref::<Gauge<T>>() {
+        (name.to_string(), "gauge", &gauge.data_points)
+    } else {
+        return false;
+    };
+    header(out, &name, description, ty);
+    for point in points {
+        let _ = writeln!(
+            out,
+            "{name}{} {}",
+            labels(&point.attributes, None),
+            point.value.as_f64()
+        );
+    }
+    true
+}
+
+fn render_histogram<T: AsF64>(
+    out: &mut String,
+    name: &str,
+    description: &str,
+    data: &dyn std::any::Any,
+) -> bool {
+    let Some(histogram) = data.downcast_ref::<HistogramData<T>>() else {
+        return false;
+    };
+    header(out, name, description, "histogram");
+    for point in &histogram.data_points {
+        let mut count = 0;
+        for (bound, bucket) in point.bounds.iter().zip(&point.bucket_counts) {
+            count += bucket;
+            let _ = writeln!(
+                out,
+                "{name}_bucket{} {count}",
+                labels(&point.attributes, Some(("le", bound.to_string())))
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{name}_bucket{} {}",
+            labels(&point.attributes, Some(("le", "+Inf".to_string()))),
+            point.count
+        );
+        let attributes = labels(&point.attributes, None);
+        let _ = writeln!(out, "{name}_sum{attributes} {}", point.sum.as_f64());
+        let _ = writeln!(out, "{name}_count{attributes} {}", point.count);
+    }
+    true
+}
+
+#[cfg(all(test, feature = "with-axum"))]
+mod tests {
+    use super::*;
+    use axum::{body::Body, routing::get, Router};
+    use opentelemetry::metrics::MeterProvider;
+    use opentelemetry_sdk::metrics::SdkMeterProvider;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_http_metrics() -> anyhow::Result<()> {
+        let exporter = PrometheusExporter::new();
+        let provider = SdkMeterProvider::builder()
+            .with_reader(exporter.clone())
+            .build();
+        let router = Router::new()
+            .route("/users/:id", get(|| async { "ok" }))
+            .layer(axum::middleware::from_fn_with_state(
+                HttpMetrics::from_meter(&provider.meter("test")),
+                track_http,
+            ));
+
+        for path in ["/users/1", "/users/2"] {
+            let resp = router
+                .clone()
+                .oneshot(axum::http::Request::get(path).body(Body::empty())?)
+                .await?;
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+
+        let body = exporter.render()?;
+        assert!(
+            body.contains(r#"http_server_request_duration_seconds_count{http_request_method="GET",http_response_status_code="200",http_route="/users/:id"} 2"#),
+            "{body}"
+        );
+        assert!(body.contains("# TYPE http_server_request_duration_seconds histogram"));
+        assert!(body.contains(
+            r#"http_server_active_requests{http_request_method="GET",http_route="/users/:id"} 0"#
+        ));
+        Ok(())
+    }
+}
@@ -1,32 +1,118 @@
 #[cfg(all(feature = "with-graphql", feature = "with-sentry"))]
 pub mod async_graphql_sentry_extension;
 
+#[cfg(all(feature = "with-graphql", feature = "with-opentelemetry"))]
+pub mod async_graphql_metrics_extension;
+
+#[cfg(feature = "with-sentry")]
+pub mod sentry_breadcrumb;
+
+#[cfg(feature = "with-sentry")]
+pub mod sentry_options;
+
 #[cfg(feature = "with-graphql")]
 pub mod date_time_rfc3339;
 
+#[cfg(feature = "with-graphql")]
+pub mod date_time_offset;
+
+#[cfg(feature = "with-graphql")]
+pub mod time_zone;
+
 #[cfg(feature = "with-graphql")]
 pub mod date;
 
 #[cfg(feature = "with-graphql")]
 pub mod month;
 
+#[cfg(feature = "with-graphql")]
+pub mod local_time;
+
+#[cfg(feature = "with-graphql")]
+pub mod duration;
+
+#[cfg(feature = "with-graphql")]
+pub mod date_range;
+
+#[cfg(all(feature = "with-sea-orm", feature = "with-graphql"))]
+mod pg_value;
+
 #[cfg(all(feature = "with-sea-orm", feature = "with-graphql"))]
 pub mod db;
 
+#[cfg(all(feature = "with-sea-orm", feature = "with-graphql"))]
+pub mod entity;
+
 #[cfg(feature = "with-sea-orm")]
 pub mod connection;
 
+#[cfg(feature = "with-sea-orm")]
+pub mod sql;
+
 #[cfg(feature = "with-axum")]
 pub mod error;
 
-#[cfg(all(feature = "with-opentelemetry", feature = "with-sentry"))]
+#[cfg(feature = "with-graphql")]
+pub mod error_code;
+
+#[cfg(all(feature = "with-auth", feature = "with-axum", feature = "with-graphql"))]
+pub mod auth;
+
+#[cfg(all(feature = "with-auth", feature = "with-axum", feature = "with-graphql"))]
+pub mod guard;
+
+#[cfg(feature = "with-graphql")]
+pub mod default_deny;
+
 pub mod setup_tracing;
 
+pub mod tracing_config;
+
 #[cfg(all(feature = "with-axum", feature = "with-opentelemetry"))]
 pub mod parent_trace_context;
 
+#[cfg(all(feature = "with-axum", feature = "with-opentelemetry"))]
+pub mod http_trace;
+
+#[cfg(all(feature = "with-axum", feature = "with-opentelemetry"))]
+pub mod http_client;
+
+#[cfg(feature = "with-opentelemetry")]
+pub mod sampler;
+
+#[cfg(feature = "with-opentelemetry")]
+pub mod propagator;
+
+#[cfg(feature = "with-opentelemetry")]
+pub mod metrics;
+
+#[cfg(feature = "with-opentelemetry")]
+pub mod otel_log_layer;
+
+pub mod json_log_format;
+
+pub mod log_filter;
+
+#[cfg(feature = "with-axum")]
+pub mod request_id;
+
+#[cfg(feature = "with-opentelemetry")]
+pub mod otlp_exporter;
+
+#[cfg(feature = "with-opentelemetry")]
+pub mod otel_setup;
+
+#[cfg(all(test, feature = "with-axum", feature = "with-opentelemetry"))]
+pub mod otlp_collector;
+
 #[cfg(feature = "with-axum")]
 pub mod server;
 
+#[cfg(all(feature = "with-axum", feature = "with-graphql"))]
+pub mod graphql_http;
+
+#[cfg(feature = "with-graphql")]
+pub mod schema_diff;
+
 #[cfg(feature = "with-graphql")]
 pub mod vec_for_input;
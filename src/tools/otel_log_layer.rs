@@ -0,0 +1,151 @@
+// tracingのeventをOpenTelemetryのログとしてOTLPで送るlayer
+//
+// 今いるspanのtrace id/span idをつけるので、同じバックエンドでトレースとログを紐づけられる
+// span idはtracing-opentelemetryのlayerが作ったOtelDataから取るので、そのlayerと一緒に使う
+//
+// ```ignore
+// let layer = OtelLogLayer::new(&logger_provider).with_filter(otel_log_layer::filter("info")?);
+// ```
+// opentelemetry-appender-tracingはopentelemetry 0.25に対応したものがないので自前で書いている
+use opentelemetry::{
+    logs::{AnyValue, LogRecord as _, Logger as _, LoggerProvider as _, Severity},
+    trace::{SpanContext, TraceContextExt, TraceState},
+};
+use opentelemetry_sdk::logs::{LogRecord, Logger, LoggerProvider, TraceContext};
+use tracing::{field::Field, Event, Level, Subscriber};
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::{
+    layer::Context,
+    registry::{LookupSpan, SpanRef},
+    EnvFilter, Layer,
+};
+
+// exporterが使っているクレートのログを送ると、それを送るためにまたログが出てループするので常に除外する
+const EXPORTER_TARGETS: &[&str] = &["h2", "hyper", "hyper_util", "reqwest", "tonic", "tower"];
+
+// OTLPに送るログのフィルタ。書き方はRUST_LOGと同じ
+pub fn filter(directives: &str) -> anyhow::Result<EnvFilter> {
+    let mut filter = EnvFilter::builder().parse(directives)?;
+    for target in EXPORTER_TARGETS {
+        filter = filter.add_directive(format!("{target}=off").parse()?);
+    }
+    Ok(filter)
+}
+
+pub struct OtelLogLayer {
+    logger: Logger,
+}
+
+impl OtelLogLayer {
+    pub fn new(provider: &LoggerProvider) -> Self {
+        Self {
+            logger: provider.logger("tracing"),
+        }
+    }
+}
+
+fn severity(level: &Level) -> Severity {
+    match *level {
+        Level::TRACE => Severity::Trace,
+        Level::DEBUG => Severity::Debug,
+        Level::INFO => Severity::Info,
+        Level::WARN => Severity::Warn,
+        Level::ERROR => Severity::Error,
+    }
+}
+
+struct Visitor<'a>(&'a mut LogRecord);
+
+impl Visitor<'_> {
+    fn record(&mut self, field: &Field, value: AnyValue) {
+        match field.name() {
+            "message" => self.0.set_body(value),
+            // tracing-logで変換されたlogのメタデータ
+            name if name.starts_with("log.") => {}
+            name => self.0.add_attribute(name, value),
+        }
+    }
+}
+
+impl tracing::field::Visit for Visitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.record(field, format!("{value:?}").into())
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record(field, value.to_string().into())
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.record(field, value.into())
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        match i64::try_from(value) {
+            Ok(value) => self.record(field, value.into()),
+            Err(_) => self.record(field, value.to_string().into()),
+        }
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.record(field, value.into())
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.record(field, value.into())
+    }
+}
+
+// spanを内側から順に見て、一番近いOtelDataからspan contextを作る
+// JSONのログ出力 (json_log_format) でも使う
+pub fn span_context<'a, R>(mut scope: impl Iterator<Item = SpanRef<'a, R>>) -> Option<SpanContext>
+where
+    R: LookupSpan<'a> + 'a,
+{
+    scope.find_map(|span| {
+        let extensions = span.extensions();
+        let data = extensions.get::<OtelData>()?;
+        let parent = data.parent_cx.span();
+        let parent = parent.span_context();
+        Some(SpanContext::new(
+            data.builder.trace_id.unwrap_or(parent.trace_id()),
+            data.builder.span_id?,
+            parent.trace_flags(),
+            false,
+            TraceState::default(),
+        ))
+    })
+}
+
+impl<S> Layer<S> for OtelLogLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let mut record = self.logger.create_log_record();
+        record.set_timestamp(std::time::SystemTime::now());
+        record.set_severity_number(severity(metadata.level()));
+        record.set_severity_text(metadata.level().as_str());
+        record.set_target(metadata.target());
+        if let Some(file) = metadata.file() {
+            record.add_attribute("code.filepath", file);
+        }
+        if let Some(line) = metadata.line() {
+            record.add_attribute("code.lineno", i64::from(line));
+        }
+        if let Some(module) = metadata.module_path() {
+            record.add_attribute("code.namespace", module);
+        }
+        #[cfg(feature = "with-axum")]
+        if let Some(request_id) = super::request_id::RequestId::current() {
+            record.add_attribute("request_id", request_id.0);
+        }
+        event.record(&mut Visitor(&mut record));
+        record.trace_context = ctx
+            .event_scope(event)
+            .and_then(span_context)
+            .map(|x| TraceContext::from(&x));
+        self.logger.emit(record);
+    }
+}
@@ -0,0 +1,349 @@
+// OpenTelemetryのTracerProvider/MeterProvider/LoggerProviderをTracingConfigから作る
+// setup_tracing::setupから使う。グローバルに登録せずに使いたい場合はbuild_*を直接呼ぶ
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_sdk::logs::{BatchLogProcessor, LoggerProvider};
+use opentelemetry_sdk::metrics::{
+    new_view, reader::DefaultAggregationSelector, reader::DefaultTemporalitySelector, Aggregation,
+    Instrument, PeriodicReader, SdkMeterProvider, Stream,
+};
+use opentelemetry_sdk::trace::{BatchSpanProcessor, Sampler, ShouldSample, TracerProvider};
+use tracing::Subscriber;
+use tracing_subscriber::{registry::LookupSpan, Layer};
+
+use super::metrics::{PrometheusExporter, DURATION_BUCKETS};
+use super::otel_log_layer::{self, OtelLogLayer};
+use super::otlp_exporter::exporter_builder;
+use super::propagator;
+use super::sampler::{ErrorSpanProcessor, RootPromotionProcessor, RuleSampler};
+use super::tracing_config::{SamplerConfig, TracingConfig};
+
+// 作ったproviderをまとめて持っておき、dropで終了する
+#[derive(Default)]
+pub struct OtelProviders {
+    pub tracer_provider: Option<TracerProvider>,
+    pub meter_provider: Option<SdkMeterProvider>,
+    pub logger_provider: Option<LoggerProvider>,
+    pub prometheus_exporter: Option<PrometheusExporter>,
+}
+
+impl OtelProviders {
+    // providerを作ってグローバルに登録する
+    pub fn install(config: &TracingConfig) -> anyhow::Result<Self> {
+        // exporterがなくてもbaggageなどは受け取れるようにpropagatorは常に登録する
+        opentelemetry::global::set_text_map_propagator(propagator::build(&config.propagators));
+        let tracer_provider = build_tracer_provider(config)?;
+        if let Some(provider) = tracer_provider.as_ref() {
+            opentelemetry::global::set_tracer_provider(provider.clone());
+        }
+
+        let prometheus_exporter = config.metrics.prometheus.then(PrometheusExporter::new);
+        let meter_provider = build_meter_provider(config, prometheus_exporter.as_ref())?;
+        if let Some(meter_provider) = meter_provider.as_ref() {
+            opentelemetry::global::set_meter_provider(meter_provider.clone());
+        }
+
+        Ok(Self {
+            tracer_provider,
+            meter_provider,
+            logger_provider: build_logger_provider(config)?,
+            prometheus_exporter,
+        })
+    }
+
+    // tracingのspanをOpenTelemetryのspanにするlayer
+    pub fn trace_layer<S>(&self) -> Option<impl Layer<S>>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        self.tracer_provider.as_ref().map(|provider| {
+            tracing_opentelemetry::layer().with_tracer(provider.tracer("tracing-otel-subscriber"))
+        })
+    }
+
+    // tracingのeventをOTLPのログにするlayer。標準出力とは別のフィルタ (logs.filter) を使う
+    pub fn log_layer<S>(&self, config: &TracingConfig) -> anyhow::Result<Option<impl Layer<S>>>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let Some(logger_provider) = self.logger_provider.as_ref() else {
+            return Ok(None);
+        };
+        Ok(Some(
+            OtelLogLayer::new(logger_provider)
+                .with_filter(otel_log_layer::filter(&config.logs.filter)?),
+        ))
+    }
+}
+
+impl Drop for OtelProviders {
+    fn drop(&mut self) {
+        if let Some(meter_provider) = self.meter_provider.take() {
+            let _ = meter_provider.shutdown();
+        }
+        if let Some(logger_provider) = self.logger_provider.take() {
+            let _ = logger_provider.shutdown();
+        }
+        if self.tracer_provider.take().is_some() {
+            opentelemetry::global::shutdown_tracer_provider();
+        }
+    }
+}
+
+fn build_sampler(config: &SamplerConfig) -> Box<dyn ShouldSample> {
+    match config {
+        SamplerConfig::AlwaysOn => Box::new(Sampler::AlwaysOn),
+        SamplerConfig::AlwaysOff => Box::new(Sampler::AlwaysOff),
+        SamplerConfig::TraceIdRatio { ratio } => Box::new(Sampler::TraceIdRatioBased(*ratio)),
+        SamplerConfig::ParentBasedAlwaysOn => {
+            Box::new(Sampler::ParentBased(Box::new(Sampler::AlwaysOn)))
+        }
+        SamplerConfig::ParentBasedAlwaysOff => {
+            Box::new(Sampler::ParentBased(Box::new(Sampler::AlwaysOff)))
+        }
+        SamplerConfig::ParentBasedTraceIdRatio { ratio } => Box::new(Sampler::ParentBased(
+            Box::new(Sampler::TraceIdRatioBased(*ratio)),
+        )),
+        SamplerConfig::RuleBased {
+            rules,
+            record_errors,
+        } => Box::new(RuleSampler::new(rules.clone()).with_record_errors(*record_errors)),
+    }
+}
+
+fn error_ratio(config: &SamplerConfig) -> Option<f64> {
+    match config {
+        SamplerConfig::RuleBased {
+            rules,
+            record_errors,
+        } => RuleSampler::new(rules.clone())
+            .with_record_errors(*record_errors)
+            .error_ratio(),
+        _ => None,
+    }
+}
+
+fn build_resource(config: &TracingConfig) -> opentelemetry_sdk::Resource {
+    let mut attributes = vec![opentelemetry::KeyValue::new(
+        "service.name",
+        config.service_name.clone(),
+    )];
+    attributes.extend(
+        config
+            .resource_attributes
+            .iter()
+            .map(|(k, v)| opentelemetry::KeyValue::new(k.clone(), v.clone())),
+    );
+    opentelemetry_sdk::Resource::new(attributes)
+}
+
+// グローバルには登録せずにTracerProviderを作る
+pub fn build_tracer_provider(config: &TracingConfig) -> anyhow::Result<Option<TracerProvider>> {
+    let Some(exporter) = config.exporter.as_ref() else {
+        return Ok(None);
+    };
+
+    let mut trace_config = opentelemetry_sdk::trace::Config::default()
+        .with_id_generator(opentelemetry_sdk::trace::RandomIdGenerator::default())
+        .with_max_events_per_span(config.span_limits.max_events_per_span)
+        .with_max_attributes_per_span(config.span_limits.max_attributes_per_span)
+        .with_max_links_per_span(config.span_limits.max_links_per_span)
+        .with_max_attributes_per_event(config.span_limits.max_attributes_per_event)
+        .with_resource(build_resource(config));
+    trace_config.sampler = build_sampler(&config.sampler);
+
+    // install_simpleだと動作しない・・・？
+    let processor = BatchSpanProcessor::builder(
+        exporter_builder::<opentelemetry_otlp::SpanExporterBuilder>(exporter, "/v1/traces")?
+            .build_span_exporter()?,
+        opentelemetry_sdk::runtime::Tokio,
+    )
+    .with_batch_config(
+        opentelemetry_sdk::trace::BatchConfigBuilder::default()
+            .with_scheduled_delay(std::time::Duration::from_millis(
+                exporter.scheduled_delay_ms,
+            ))
+            .build(),
+    )
+    .build();
+    let builder = TracerProvider::builder().with_config(trace_config);
+    let builder = match (&config.sampler, error_ratio(&config.sampler)) {
+        (SamplerConfig::RuleBased { .. }, Some(ratio)) => builder.with_span_processor(
+            RootPromotionProcessor::new(ErrorSpanProcessor::new(processor, ratio)),
+        ),
+        (SamplerConfig::RuleBased { .. }, None) => {
+            builder.with_span_processor(RootPromotionProcessor::new(processor))
+        }
+        _ => builder.with_span_processor(processor),
+    };
+    Ok(Some(builder.build()))
+}
+
+// グローバルには登録せずにMeterProviderを作る
+// prometheusを使う場合はexporterを渡す。OTLPにもPrometheusにも出さないならNone
+pub fn build_meter_provider(
+    config: &TracingConfig,
+    prometheus_exporter: Option<&PrometheusExporter>,
+) -> anyhow::Result<Option<SdkMeterProvider>> {
+    let exporter = config.exporter.as_ref().filter(|_| config.metrics.otlp);
+    if exporter.is_none() && prometheus_exporter.is_none() {
+        return Ok(None);
+    }
+
+    let mut builder = SdkMeterProvider::builder()
+        .with_resource(build_resource(config))
+        .with_view(new_view(
+            Instrument::new().name("*.duration"),
+            Stream::new().aggregation(Aggregation::ExplicitBucketHistogram {
+                boundaries: DURATION_BUCKETS.to_vec(),
+                record_min_max: true,
+            }),
+        )?);
+    if let Some(exporter) = exporter {
+        let exporter = exporter_builder::<opentelemetry_otlp::MetricsExporterBuilder>(
+            exporter,
+            "/v1/metrics",
+        )?
+        .build_metrics_exporter(
+            Box::new(DefaultTemporalitySelector::new()),
+            Box::new(DefaultAggregationSelector::new()),
+        )?;
+        builder = builder.with_reader(
+            PeriodicReader::builder(exporter, opentelemetry_sdk::runtime::Tokio)
+                .with_interval(std::time::Duration::from_millis(
+                    config.metrics.export_interval_ms,
+                ))
+                .build(),
+        );
+    }
+    if let Some(prometheus_exporter) = prometheus_exporter {
+        builder = builder.with_reader(prometheus_exporter.clone());
+    }
+    Ok(Some(builder.build()))
+}
+
+// グローバルには登録せずにLoggerProviderを作る。tracingのeventはOtelLogLayerでここに流す
+pub fn build_logger_provider(config: &TracingConfig) -> anyhow::Result<Option<LoggerProvider>> {
+    let Some(exporter) = config.exporter.as_ref().filter(|_| config.logs.otlp) else {
+        return Ok(None);
+    };
+
+    let processor = BatchLogProcessor::builder(
+        exporter_builder::<opentelemetry_otlp::LogExporterBuilder>(exporter, "/v1/logs")?
+            .build_log_exporter()?,
+        opentelemetry_sdk::runtime::Tokio,
+    )
+    .with_batch_config(
+        opentelemetry_sdk::logs::BatchConfigBuilder::default()
+            .with_scheduled_delay(std::time::Duration::from_millis(
+                exporter.scheduled_delay_ms,
+            ))
+            .build(),
+    )
+    .build();
+    Ok(Some(
+        LoggerProvider::builder()
+            .with_resource(build_resource(config))
+            .with_log_processor(processor)
+            .build(),
+    ))
+}
+
+#[cfg(all(test, feature = "with-axum"))]
+mod tests {
+    use super::*;
+    use crate::tools::{
+        otel_log_layer::{self, OtelLogLayer},
+        otlp_collector::OtlpCollector,
+        tracing_config::{Compression, ExporterConfig, Protocol},
+    };
+    use opentelemetry::trace::{Span, Tracer};
+    use tracing_subscriber::{layer::SubscriberExt, Layer};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_export_http_json() -> anyhow::Result<()> {
+        let collector = OtlpCollector::start().await?;
+        let config = TracingConfig {
+            exporter: Some(ExporterConfig {
+                protocol: Protocol::HttpJson,
+                headers: [("authorization".to_string(), "Bearer xxx".to_string())].into(),
+                compression: Some(Compression::Gzip),
+                ..ExporterConfig::new(collector.endpoint())
+            }),
+            ..Default::default()
+        };
+        config.validate()?;
+        let provider = build_tracer_provider(&config)?.unwrap();
+
+        let mut span = provider.tracer("test").start("hello");
+        span.end();
+        // force_flushはexportが終わるまでブロックする
+        let provider = tokio::task::spawn_blocking(move || {
+            assert!(provider.force_flush().iter().all(Result::is_ok));
+            provider
+        })
+        .await?;
+
+        assert_eq!(collector.span_names(), vec!["hello"]);
+        let request = &collector.requests()[0];
+        assert_eq!(request.headers["authorization"], "Bearer xxx");
+        assert_eq!(request.headers["content-encoding"], "gzip");
+        assert_eq!(
+            request.body["resourceSpans"][0]["resource"]["attributes"][0]["value"]["stringValue"],
+            "not-set"
+        );
+        tokio::task::spawn_blocking(move || provider.shutdown()).await??;
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_export_logs() -> anyhow::Result<()> {
+        let collector = OtlpCollector::start().await?;
+        let config = TracingConfig {
+            exporter: Some(ExporterConfig {
+                protocol: Protocol::HttpJson,
+                ..ExporterConfig::new(collector.endpoint())
+            }),
+            ..Default::default()
+        };
+        let provider = build_tracer_provider(&config)?.unwrap();
+        let logger_provider = build_logger_provider(&config)?.unwrap();
+
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")))
+            .with(OtelLogLayer::new(&logger_provider).with_filter(otel_log_layer::filter("info")?));
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("request");
+            let _enter = span.enter();
+            tracing::info!(user_id = 1, "hello");
+            tracing::debug!("filtered");
+        });
+        let (provider, logger_provider) = tokio::task::spawn_blocking(move || {
+            assert!(provider.force_flush().iter().all(Result::is_ok));
+            assert!(logger_provider.force_flush().iter().all(Result::is_ok));
+            (provider, logger_provider)
+        })
+        .await?;
+
+        let spans = collector.spans();
+        let logs = collector.log_records();
+        assert_eq!(logs.len(), 1);
+        let log = &logs[0];
+        assert_eq!(log["body"]["stringValue"], "hello");
+        assert_eq!(log["severityText"], "INFO");
+        assert_eq!(log["traceId"], spans[0]["traceId"]);
+        assert_eq!(log["spanId"], spans[0]["spanId"]);
+        assert!(log["attributes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|x| x["key"] == "user_id" && x["value"]["intValue"] == "1"));
+
+        tokio::task::spawn_blocking(move || {
+            provider.shutdown()?;
+            logger_provider.shutdown()?;
+            anyhow::Ok(())
+        })
+        .await??;
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+// テスト用のOTLP/HTTPのcollector
+// http/jsonで受け取ったリクエストを溜めておくので、exportされた内容をテストで確認できる
+//
+// ```ignore
+// let collector = OtlpCollector::start().await?;
+// let config = ExporterConfig { protocol: Protocol::HttpJson, ..ExporterConfig::new(collector.endpoint()) };
+// ...
+// assert_eq!(collector.span_names(), vec!["request"]);
+// ```
+use std::{
+    io::Read,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{header, HeaderMap, StatusCode, Uri},
+    routing::post,
+    Router,
+};
+
+#[derive(Debug, Clone)]
+pub struct CollectedRequest {
+    pub path: String,
+    pub headers: HeaderMap,
+    pub body: serde_json::Value,
+}
+
+#[derive(Debug, Clone)]
+pub struct OtlpCollector {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<CollectedRequest>>>,
+}
+
+async fn collect(
+    State(requests): State<Arc<Mutex<Vec<CollectedRequest>>>>,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<&'static str, (StatusCode, String)> {
+    let body = if headers
+        .get(header::CONTENT_ENCODING)
+        .is_some_and(|x| x == "gzip")
+    {
+        let mut decoded = vec![];
+        flate2::read::GzDecoder::new(&body[..])
+            .read_to_end(&mut decoded)
+            .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+        decoded
+    } else {
+        body.to_vec()
+    };
+    let body =
+        serde_json::from_slice(&body).map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    requests.lock().unwrap().push(CollectedRequest {
+        path: uri.path().to_string(),
+        headers,
+        body,
+    });
+    Ok("{}")
+}
+
+impl OtlpCollector {
+    pub async fn start() -> anyhow::Result<Self> {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let router = Router::new()
+            .route("/v1/traces", post(collect))
+            .route("/v1/metrics", post(collect))
+            .route("/v1/logs", post(collect))
+            .with_state(requests.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        Ok(Self { addr, requests })
+    }
+
+    pub fn endpoint(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn requests(&self) -> Vec<CollectedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    // /v1/traces, /v1/logs で受け取ったspanやlogRecordを全部並べる
+    fn records(
+        &self,
+        path: &str,
+        resource: &str,
+        scope: &str,
+        records: &str,
+    ) -> Vec<serde_json::Value> {
+        let array =
+            |x: &serde_json::Value, key: &str| x[key].as_array().cloned().unwrap_or_default();
+        self.requests()
+            .iter()
+            .filter(|x| x.path == path)
+            .flat_map(|x| array(&x.body, resource))
+            .flat_map(|x| array(&x, scope))
+            .flat_map(|x| array(&x, records))
+            .collect()
+    }
+
+    pub fn spans(&self) -> Vec<serde_json::Value> {
+        self.records("/v1/traces", "resourceSpans", "scopeSpans", "spans")
+    }
+
+    pub fn log_records(&self) -> Vec<serde_json::Value> {
+        self.records("/v1/logs", "resourceLogs", "scopeLogs", "logRecords")
+    }
+
+    pub fn span_names(&self) -> Vec<String> {
+        self.spans()
+            .iter()
+            .filter_map(|x| x["name"].as_str().map(ToString::to_string))
+            .collect()
+    }
+}
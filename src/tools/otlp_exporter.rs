@@ -0,0 +1,126 @@
+// ExporterConfigからOTLPのexporterのbuilderを作る
+// - grpc: tonic。TLSはClientTlsConfig、圧縮はtonicのgzip
+// - http/protobuf, http/json: reqwest。TLSはreqwest::Clientで設定する
+//   opentelemetry-otlp 0.25のHTTP exporterは圧縮に対応していないので、GzipHttpClientでbodyを圧縮する
+//
+// endpointはOTEL_EXPORTER_OTLP_ENDPOINTと同じくベースURLで、HTTPの場合はシグナルごとのパスを後ろにつける
+// SpanExporterBuilderなど、TonicExporterBuilderとHttpExporterBuilderから作れるものなら何でも返せる
+// ```ignore
+// let exporter: opentelemetry_otlp::SpanExporterBuilder =
+//     otlp_exporter::exporter_builder(&config, "/v1/traces")?;
+// ```
+use std::time::Duration;
+
+use async_trait::async_trait;
+use opentelemetry_http::{Bytes, HttpClient, HttpError, Request, Response};
+use opentelemetry_otlp::{HttpExporterBuilder, TonicExporterBuilder, WithExportConfig};
+
+use super::tracing_config::{Compression, ExporterConfig, Protocol, TlsConfig};
+
+fn read(path: &std::path::Path) -> anyhow::Result<Vec<u8>> {
+    std::fs::read(path).map_err(|e| anyhow::anyhow!("failed to read {}: {e}", path.display()))
+}
+
+fn tonic_tls_config(tls: Option<&TlsConfig>) -> anyhow::Result<tonic::transport::ClientTlsConfig> {
+    let mut config = tonic::transport::ClientTlsConfig::new().with_native_roots();
+    let Some(tls) = tls else {
+        return Ok(config);
+    };
+    if let Some(path) = tls.ca_certificate.as_ref() {
+        config = config.ca_certificate(tonic::transport::Certificate::from_pem(read(path)?));
+    }
+    if let (Some(cert), Some(key)) = (tls.client_certificate.as_ref(), tls.client_key.as_ref()) {
+        config = config.identity(tonic::transport::Identity::from_pem(
+            read(cert)?,
+            read(key)?,
+        ));
+    }
+    Ok(config)
+}
+
+fn tonic_builder(config: &ExporterConfig) -> anyhow::Result<TonicExporterBuilder> {
+    let mut headers = http::HeaderMap::new();
+    for (key, value) in &config.headers {
+        headers.insert(
+            http::HeaderName::from_bytes(key.as_bytes())?,
+            http::HeaderValue::from_str(value)?,
+        );
+    }
+    let mut builder = opentelemetry_otlp::new_exporter()
+        .tonic()
+        .with_endpoint(config.endpoint.clone())
+        .with_timeout(Duration::from_millis(config.timeout_ms))
+        .with_metadata(tonic::metadata::MetadataMap::from_headers(headers));
+    if config.compression == Some(Compression::Gzip) {
+        builder = builder.with_compression(opentelemetry_otlp::Compression::Gzip);
+    }
+    if config.endpoint.starts_with("https://") {
+        builder = builder.with_tls_config(tonic_tls_config(config.tls.as_ref())?);
+    }
+    Ok(builder)
+}
+
+fn reqwest_client(config: &ExporterConfig) -> anyhow::Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder().timeout(Duration::from_millis(config.timeout_ms));
+    if let Some(tls) = config.tls.as_ref() {
+        if let Some(path) = tls.ca_certificate.as_ref() {
+            builder = builder.add_root_certificate(reqwest::Certificate::from_pem(&read(path)?)?);
+        }
+        if let (Some(cert), Some(key)) = (tls.client_certificate.as_ref(), tls.client_key.as_ref())
+        {
+            let mut pem = read(cert)?;
+            pem.extend(read(key)?);
+            builder = builder.identity(reqwest::Identity::from_pem(&pem)?);
+        }
+        builder = builder.danger_accept_invalid_certs(tls.insecure);
+    }
+    Ok(builder.build()?)
+}
+
+#[derive(Debug)]
+struct GzipHttpClient<C>(C);
+
+#[async_trait]
+impl<C: HttpClient> HttpClient for GzipHttpClient<C> {
+    async fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Bytes>, HttpError> {
+        use std::io::Write;
+
+        let (mut parts, body) = request.into_parts();
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&body)?;
+        parts.headers.insert(
+            http::header::CONTENT_ENCODING,
+            http::HeaderValue::from_static("gzip"),
+        );
+        self.0
+            .send(Request::from_parts(parts, encoder.finish()?))
+            .await
+    }
+}
+
+fn http_builder(config: &ExporterConfig, path: &str) -> anyhow::Result<HttpExporterBuilder> {
+    let builder = opentelemetry_otlp::new_exporter()
+        .http()
+        .with_endpoint(format!("{}{path}", config.endpoint.trim_end_matches('/')))
+        .with_timeout(Duration::from_millis(config.timeout_ms))
+        .with_protocol(match config.protocol {
+            Protocol::HttpJson => opentelemetry_otlp::Protocol::HttpJson,
+            _ => opentelemetry_otlp::Protocol::HttpBinary,
+        })
+        .with_headers(config.headers.clone().into_iter().collect());
+    let client = reqwest_client(config)?;
+    Ok(match config.compression {
+        Some(Compression::Gzip) => builder.with_http_client(GzipHttpClient(client)),
+        None => builder.with_http_client(client),
+    })
+}
+
+pub fn exporter_builder<B>(config: &ExporterConfig, http_path: &str) -> anyhow::Result<B>
+where
+    B: From<TonicExporterBuilder> + From<HttpExporterBuilder>,
+{
+    Ok(match config.protocol {
+        Protocol::Grpc => tonic_builder(config)?.into(),
+        Protocol::HttpProtobuf | Protocol::HttpJson => http_builder(config, http_path)?.into(),
+    })
+}
@@ -1,12 +1,205 @@
+// 呼び出し元のトレースのヘッダーとbaggageを受け取るextractor
+// 読むヘッダーは登録されているpropagator (OTEL_PROPAGATORS, tools/propagator.rs) のfields()
+// 外から来る値なので、ASCIIでないものや形式が合わないものはwarnのログを出して無視する (リクエストは失敗させない)
+//
+// ```ignore
+// async fn handler(parent_trace_context: ParentTraceContext, ...) {
+//     let cx = parent_trace_context.get(); // baggageも入っている
+//     let req = req.data(parent_trace_context.baggage().clone());
+// }
+//
+// // resolverの中では
+// let tenant = ctx.data::<Baggage>()?.get("tenant");
+// ```
 use std::collections::HashMap;
+use std::convert::Infallible;
+
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{request::Parts, HeaderMap},
+};
+use opentelemetry::propagation::TextMapPropagator;
+use percent_encoding::percent_decode_str;
 
-use axum::{async_trait, extract::FromRequestParts, http::request::Parts, response::Response};
 const TRACEPARENT_HEADER: &str = "traceparent";
 const TRACESTATE_HEADER: &str = "tracestate";
+const BAGGAGE_HEADER: &str = "baggage";
+
+// 00のtraceparentは55文字。後のバージョンで後ろに足される分を見込む
+const MAX_TRACEPARENT_LENGTH: usize = 256;
+// W3C Baggageの上限
+const MAX_BAGGAGE_LENGTH: usize = 8192;
+const MAX_BAGGAGE_ENTRIES: usize = 64;
+// tracestateは32メンバーまでなので、それより大幅に長いものは捨てる
+const MAX_TRACESTATE_LENGTH: usize = 512 * 32;
+// b3, uber-trace-idなど
+const MAX_HEADER_LENGTH: usize = 512;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BaggageEntry {
+    pub key: String,
+    pub value: String,
+    // `;` 以降のプロパティ (そのままの文字列)
+    pub metadata: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Baggage {
+    pub entries: Vec<BaggageEntry>,
+}
+
+impl Baggage {
+    // 形式が合わないメンバーは飛ばす
+    pub fn parse(header: &str) -> Self {
+        let mut entries = Vec::new();
+        for member in header.split(',').filter(|x| !x.trim().is_empty()) {
+            if entries.len() >= MAX_BAGGAGE_ENTRIES {
+                tracing::warn!("too many baggage entries, ignoring the rest");
+                break;
+            }
+            match parse_member(member) {
+                Some(entry) => entries.push(entry),
+                None => tracing::warn!(member, "ignoring malformed baggage entry"),
+            }
+        }
+        Self { entries }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|x| x.key == key)
+            .map(|x| x.value.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+fn is_token(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&c))
+}
 
-#[derive(Debug, Clone)]
+fn parse_member(member: &str) -> Option<BaggageEntry> {
+    let (pair, metadata) = member.split_once(';').unwrap_or((member, ""));
+    let (key, value) = pair.split_once('=')?;
+    let key = key.trim();
+    if !is_token(key) {
+        return None;
+    }
+    let value = percent_decode_str(value.trim()).decode_utf8().ok()?;
+    Some(BaggageEntry {
+        key: key.to_string(),
+        value: value.into_owned(),
+        metadata: metadata.trim().to_string(),
+    })
+}
+
+// version-trace_id-parent_id-flags (00-<32桁>-<16桁>-<2桁>)。ffとすべて0のIDは無効
+fn is_valid_traceparent(value: &str) -> bool {
+    let is_hex = |x: &str, len: usize| {
+        x.len() == len && x.bytes().all(|c| matches!(c, b'0'..=b'9' | b'a'..=b'f'))
+    };
+    let parts: Vec<&str> = value.split('-').collect();
+    match parts.as_slice() {
+        [version, trace_id, span_id, flags, rest @ ..] => {
+            is_hex(version, 2)
+                && *version != "ff"
+                && (*version != "00" || rest.is_empty())
+                && is_hex(trace_id, 32)
+                && trace_id.bytes().any(|c| c != b'0')
+                && is_hex(span_id, 16)
+                && span_id.bytes().any(|c| c != b'0')
+                && is_hex(flags, 2)
+        }
+        _ => false,
+    }
+}
+
+fn header(headers: &HeaderMap, name: &str, max_length: usize) -> Option<String> {
+    // 同じヘッダーが複数あるときは , でつなげたものとして扱う
+    let values = headers
+        .get_all(name)
+        .iter()
+        .map(|x| x.to_str().map(str::trim))
+        .collect::<Result<Vec<_>, _>>();
+    let value = match values {
+        Ok(values) if values.is_empty() => return None,
+        Ok(values) => values.join(","),
+        Err(_) => {
+            tracing::warn!(header = name, "ignoring non-ASCII trace context header");
+            return None;
+        }
+    };
+    if value.len() > max_length {
+        tracing::warn!(
+            header = name,
+            length = value.len(),
+            "ignoring too long trace context header"
+        );
+        return None;
+    }
+    Some(value)
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct ParentTraceContext {
-    headers: HashMap<&'static str, Option<String>>,
+    headers: HashMap<String, String>,
+    baggage: Baggage,
+}
+
+impl ParentTraceContext {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        opentelemetry::global::get_text_map_propagator(|prop| {
+            Self::from_headers_with(headers, prop)
+        })
+    }
+
+    pub fn from_headers_with(headers: &HeaderMap, propagator: &dyn TextMapPropagator) -> Self {
+        let mut context = Self::default();
+        for name in propagator.fields() {
+            let max_length = match name {
+                TRACEPARENT_HEADER => MAX_TRACEPARENT_LENGTH,
+                TRACESTATE_HEADER => MAX_TRACESTATE_LENGTH,
+                BAGGAGE_HEADER => MAX_BAGGAGE_LENGTH,
+                _ => MAX_HEADER_LENGTH,
+            };
+            let Some(value) = header(headers, name, max_length) else {
+                continue;
+            };
+            if name == TRACEPARENT_HEADER && !is_valid_traceparent(&value) {
+                tracing::warn!(traceparent = value, "ignoring malformed traceparent");
+                continue;
+            }
+            if name == BAGGAGE_HEADER {
+                context.baggage = Baggage::parse(&value);
+            }
+            context.headers.insert(name.to_string(), value);
+        }
+        // tracestateはtraceparentが正しいときだけ使う
+        if !context.headers.contains_key(TRACEPARENT_HEADER) {
+            context.headers.remove(TRACESTATE_HEADER);
+        }
+        context
+    }
+
+    // 親のspanとbaggageが入ったContext
+    pub fn get(&self) -> opentelemetry::Context {
+        opentelemetry::global::get_text_map_propagator(|prop| self.extract_with(prop))
+    }
+
+    pub fn extract_with(&self, propagator: &dyn TextMapPropagator) -> opentelemetry::Context {
+        propagator.extract(self)
+    }
+
+    pub fn baggage(&self) -> &Baggage {
+        &self.baggage
+    }
 }
 
 #[async_trait]
@@ -14,50 +207,134 @@ impl<S> FromRequestParts<S> for ParentTraceContext
 where
     S: Send + Sync,
 {
-    type Rejection = Response;
+    type Rejection = Infallible;
 
     async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
-        Ok(ParentTraceContext {
-            headers: HashMap::from_iter(
-                [
-                    (
-                        TRACEPARENT_HEADER,
-                        parts
-                            .headers
-                            .get(TRACEPARENT_HEADER)
-                            .map(|x| x.to_str().unwrap().to_string()),
-                    ),
-                    (
-                        TRACESTATE_HEADER,
-                        parts
-                            .headers
-                            .get(TRACESTATE_HEADER)
-                            .map(|x| x.to_str().unwrap().to_string()),
-                    ),
-                ]
-                .into_iter()
-                .filter(|x| x.1.is_some()),
-            ),
-        })
-    }
-}
-
-impl ParentTraceContext {
-    pub fn get(&self) -> opentelemetry::Context {
-        opentelemetry::global::get_text_map_propagator(|prop| prop.extract(self))
+        Ok(Self::from_headers(&parts.headers))
     }
 }
 
 impl opentelemetry::propagation::Extractor for ParentTraceContext {
     fn get(&self, key: &str) -> Option<&str> {
-        self.headers
-            .get(key)
-            .unwrap_or(&None)
-            .as_ref()
-            .map(|x| x as &str)
+        self.headers.get(key).map(|x| x.as_str())
     }
 
     fn keys(&self) -> Vec<&str> {
-        self.headers.keys().copied().collect()
+        self.headers.keys().map(String::as_str).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::propagator;
+    use crate::tools::tracing_config::Propagator;
+    use axum::http::HeaderValue;
+    use opentelemetry::baggage::BaggageExt;
+    use opentelemetry::propagation::{Extractor, TextMapCompositePropagator};
+    use opentelemetry::trace::TraceContextExt;
+
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    fn w3c() -> TextMapCompositePropagator {
+        propagator::build(&[Propagator::TraceContext, Propagator::Baggage])
+    }
+
+    #[test]
+    fn test_malformed_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            TRACEPARENT_HEADER,
+            HeaderValue::from_bytes("00-あ".as_bytes()).unwrap(),
+        );
+        headers.insert(TRACESTATE_HEADER, HeaderValue::from_static("a=b"));
+        headers.insert(
+            BAGGAGE_HEADER,
+            HeaderValue::from_bytes(b"tenant=\xff").unwrap(),
+        );
+        let context = ParentTraceContext::from_headers_with(&headers, &w3c());
+        assert!(context.keys().is_empty());
+        assert!(context.baggage().is_empty());
+
+        for value in [
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        ] {
+            assert!(!is_valid_traceparent(value), "{value}");
+        }
+        assert!(is_valid_traceparent(TRACEPARENT));
+    }
+
+    #[test]
+    fn test_baggage() {
+        let mut headers = HeaderMap::new();
+        headers.insert(TRACEPARENT_HEADER, HeaderValue::from_static(TRACEPARENT));
+        headers.insert(
+            TRACESTATE_HEADER,
+            HeaderValue::from_static("congo=t61rcWkgMzE"),
+        );
+        headers.append(
+            BAGGAGE_HEADER,
+            HeaderValue::from_static("tenant=acme;ttl=60, =x"),
+        );
+        headers.append(
+            BAGGAGE_HEADER,
+            HeaderValue::from_static("bad key=x,novalue,region=%E6%9D%B1%E4%BA%AC"),
+        );
+        let context = ParentTraceContext::from_headers_with(&headers, &w3c());
+        assert_eq!(
+            Extractor::get(&context, TRACEPARENT_HEADER),
+            Some(TRACEPARENT)
+        );
+        assert_eq!(
+            Extractor::get(&context, TRACESTATE_HEADER),
+            Some("congo=t61rcWkgMzE")
+        );
+        assert_eq!(
+            context.baggage().entries,
+            vec![
+                BaggageEntry {
+                    key: "tenant".to_string(),
+                    value: "acme".to_string(),
+                    metadata: "ttl=60".to_string(),
+                },
+                BaggageEntry {
+                    key: "region".to_string(),
+                    value: "東京".to_string(),
+                    metadata: String::new(),
+                },
+            ]
+        );
+        assert_eq!(context.baggage().get("region"), Some("東京"));
+
+        let cx = context.extract_with(&w3c());
+        assert_eq!(
+            cx.baggage().get("tenant").map(|x| x.to_string()),
+            Some("acme".to_string())
+        );
+    }
+
+    #[test]
+    fn test_propagator_fields() {
+        let mut headers = HeaderMap::new();
+        headers.insert(TRACEPARENT_HEADER, HeaderValue::from_static(TRACEPARENT));
+        headers.insert("x-b3-traceid", HeaderValue::from_static("a3ce929d0e0e4736"));
+        headers.insert("x-b3-spanid", HeaderValue::from_static("00f067aa0ba902b7"));
+        headers.insert(BAGGAGE_HEADER, HeaderValue::from_static("tenant=acme"));
+
+        // 登録されていないpropagatorのヘッダーは読まない
+        let propagator = propagator::build(&[Propagator::B3Multi]);
+        let context = ParentTraceContext::from_headers_with(&headers, &propagator);
+        let mut keys = context.keys();
+        keys.sort();
+        assert_eq!(keys, ["x-b3-spanid", "x-b3-traceid"]);
+        assert!(context.baggage().is_empty());
+        let cx = context.extract_with(&propagator);
+        assert_eq!(
+            cx.span().span_context().trace_id().to_string(),
+            "0000000000000000a3ce929d0e0e4736"
+        );
     }
 }
@@ -0,0 +1,150 @@
+// sea-ormの値にない型 (interval, daterange, tstzrange) をPostgresのtext表現でやりとりする
+// 型に `to_pg_text(&self) -> String` と `from_pg_text(&str) -> Option<Self>` を実装して使う
+// 読むときはtextにキャストしないといけないので、カラムには select_as と save_as を付ける
+//
+// ```ignore
+// #[sea_orm(column_type = "Interval(None, None)", select_as = "text", save_as = "interval")]
+// pub duration: Duration,
+// #[sea_orm(column_type = "custom(\"daterange\")", select_as = "text", save_as = "daterange")]
+// pub period: DateRange,
+// ```
+macro_rules! impl_pg_text_value {
+    ($name:ident, $column_type:expr) => {
+        impl From<$name> for sea_orm::Value {
+            fn from(source: $name) -> Self {
+                source.to_pg_text().into()
+            }
+        }
+
+        impl sea_orm::TryGetable for $name {
+            fn try_get_by<I: sea_orm::ColIdx>(
+                res: &sea_orm::QueryResult,
+                idx: I,
+            ) -> std::result::Result<Self, sea_orm::TryGetError> {
+                let text = <String as sea_orm::TryGetable>::try_get_by(res, idx)?;
+                Self::from_pg_text(&text).ok_or_else(|| {
+                    sea_orm::TryGetError::DbErr(sea_orm::DbErr::Type(format!(
+                        "invalid {} {text}",
+                        stringify!($name)
+                    )))
+                })
+            }
+        }
+
+        impl sea_orm::sea_query::ValueType for $name {
+            fn try_from(
+                v: sea_orm::Value,
+            ) -> std::result::Result<Self, sea_orm::sea_query::ValueTypeErr> {
+                match v {
+                    sea_orm::Value::String(Some(text)) => {
+                        Self::from_pg_text(&text).ok_or(sea_orm::sea_query::ValueTypeErr)
+                    }
+                    _ => Err(sea_orm::sea_query::ValueTypeErr),
+                }
+            }
+
+            fn type_name() -> String {
+                stringify!($name).to_owned()
+            }
+
+            fn array_type() -> sea_orm::sea_query::ArrayType {
+                sea_orm::sea_query::ArrayType::String
+            }
+
+            fn column_type() -> sea_orm::sea_query::ColumnType {
+                $column_type
+            }
+        }
+
+        impl sea_orm::sea_query::Nullable for $name {
+            fn null() -> sea_orm::Value {
+                sea_orm::Value::String(None)
+            }
+        }
+    };
+}
+
+pub(crate) use impl_pg_text_value;
+
+#[cfg(test)]
+mod tests {
+    use sea_orm::{
+        ActiveValue::Set, DatabaseBackend, EntityTrait, MockDatabase, MockExecResult, Value,
+    };
+    use std::collections::BTreeMap;
+
+    use crate::tools::date_range::DateRange;
+    use crate::tools::duration::Duration;
+
+    mod schedule {
+        use sea_orm::entity::prelude::*;
+
+        use crate::tools::date_range::DateRange;
+        use crate::tools::duration::Duration;
+
+        #[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+        #[sea_orm(table_name = "schedule")]
+        pub struct Model {
+            #[sea_orm(primary_key)]
+            pub id: i32,
+            #[sea_orm(
+                column_type = "Interval(None, None)",
+                select_as = "text",
+                save_as = "interval"
+            )]
+            pub duration: Duration,
+            #[sea_orm(
+                column_type = "custom(\"daterange\")",
+                select_as = "text",
+                save_as = "daterange"
+            )]
+            pub period: Option<DateRange>,
+        }
+
+        #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+        pub enum Relation {}
+
+        impl ActiveModelBehavior for ActiveModel {}
+    }
+
+    #[tokio::test]
+    async fn test_entity() -> anyhow::Result<()> {
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([[BTreeMap::from([
+                ("id", Value::from(1)),
+                ("duration", Value::from("1 day 02:00:00")),
+                ("period", Value::from("[2024-01-01,2024-02-01)")),
+            ])]])
+            .append_exec_results([MockExecResult {
+                last_insert_id: 2,
+                rows_affected: 1,
+            }])
+            .into_connection();
+
+        let model = schedule::Entity::find_by_id(1).one(&db).await?.unwrap();
+        assert_eq!(model.duration, "P1DT2H".parse::<Duration>()?);
+        assert_eq!(
+            model.period.as_ref().map(DateRange::to_pg_text).as_deref(),
+            Some("[2024-01-01,2024-02-01)")
+        );
+
+        schedule::Entity::insert(schedule::ActiveModel {
+            id: Set(2),
+            duration: Set(model.duration),
+            period: Set(None),
+        })
+        .exec_without_returning(&db)
+        .await?;
+
+        let log = db.into_transaction_log();
+        let select = log[0].statements()[0].to_string();
+        assert!(
+            select.contains(r#"CAST("schedule"."duration" AS text)"#),
+            "{select}"
+        );
+        let insert = log[1].statements()[0].to_string();
+        assert!(insert.contains("CAST('P1DT2H' AS interval)"), "{insert}");
+        assert!(insert.contains("CAST(NULL AS daterange)"), "{insert}");
+        Ok(())
+    }
+}
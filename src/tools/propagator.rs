@@ -0,0 +1,320 @@
+// OTEL_PROPAGATORS (tracecontext, baggage, b3, b3multi, jaeger) から組み立てるpropagator
+// 標準のtracecontextとbaggageはopentelemetry_sdkのもの、B3とJaegerはここで実装している
+//
+// - b3: `b3: {trace_id}-{span_id}-{sampled}` の1ヘッダーで送る
+// - b3multi: `x-b3-traceid`, `x-b3-spanid`, `x-b3-sampled` で送る
+//   受け取るときはどちらも両方の形式を読む
+// - jaeger: `uber-trace-id: {trace_id}:{span_id}:{parent_span_id}:{flags}` (uberctx-* のbaggageは読まない)
+use opentelemetry::propagation::{
+    text_map_propagator::FieldIter, Extractor, Injector, TextMapCompositePropagator,
+    TextMapPropagator,
+};
+use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState};
+use opentelemetry::Context;
+use opentelemetry_sdk::propagation::{BaggagePropagator, TraceContextPropagator};
+use percent_encoding::percent_decode_str;
+
+use super::tracing_config::Propagator;
+
+const B3_SINGLE_HEADER: &str = "b3";
+const B3_TRACE_ID_HEADER: &str = "x-b3-traceid";
+const B3_SPAN_ID_HEADER: &str = "x-b3-spanid";
+const B3_SAMPLED_HEADER: &str = "x-b3-sampled";
+const B3_FLAGS_HEADER: &str = "x-b3-flags";
+const JAEGER_HEADER: &str = "uber-trace-id";
+
+pub fn build(propagators: &[Propagator]) -> TextMapCompositePropagator {
+    TextMapCompositePropagator::new(
+        propagators
+            .iter()
+            .map(|x| -> Box<dyn TextMapPropagator + Send + Sync> {
+                match x {
+                    Propagator::TraceContext => Box::new(TraceContextPropagator::new()),
+                    Propagator::Baggage => Box::new(BaggagePropagator::new()),
+                    Propagator::B3 => Box::new(B3Propagator::new(false)),
+                    Propagator::B3Multi => Box::new(B3Propagator::new(true)),
+                    Propagator::Jaeger => Box::new(JaegerPropagator::new()),
+                }
+            })
+            .collect(),
+    )
+}
+
+// 64bitのIDは前を0で埋める
+fn parse_id<const N: usize>(value: &str) -> Option<String> {
+    (!value.is_empty() && value.len() <= N && value.bytes().all(|c| c.is_ascii_hexdigit()))
+        .then(|| format!("{value:0>N$}").to_ascii_lowercase())
+}
+
+fn remote_context(cx: &Context, trace_id: &str, span_id: &str, sampled: bool) -> Option<Context> {
+    let trace_id = TraceId::from_hex(&parse_id::<32>(trace_id)?).ok()?;
+    let span_id = SpanId::from_hex(&parse_id::<16>(span_id)?).ok()?;
+    let span_context = SpanContext::new(
+        trace_id,
+        span_id,
+        if sampled {
+            TraceFlags::SAMPLED
+        } else {
+            TraceFlags::default()
+        },
+        true,
+        TraceState::default(),
+    );
+    span_context
+        .is_valid()
+        .then(|| cx.with_remote_span_context(span_context))
+}
+
+#[derive(Debug)]
+pub struct B3Propagator {
+    multi_header: bool,
+    fields: Vec<String>,
+}
+
+impl B3Propagator {
+    pub fn new(multi_header: bool) -> Self {
+        Self {
+            multi_header,
+            fields: [
+                B3_SINGLE_HEADER,
+                B3_TRACE_ID_HEADER,
+                B3_SPAN_ID_HEADER,
+                B3_SAMPLED_HEADER,
+                B3_FLAGS_HEADER,
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
+
+    // {trace_id}-{span_id}[-{sampled}[-{parent_span_id}]]。`0` だけのものはサンプリングしない指定なので親にはしない
+    fn extract_single(cx: &Context, value: &str) -> Option<Context> {
+        let mut parts = value.trim().split('-');
+        let (trace_id, span_id) = (parts.next()?, parts.next()?);
+        let sampled = match parts.next() {
+            None | Some("1") | Some("d") => true,
+            Some("0") => false,
+            Some(_) => return None,
+        };
+        remote_context(cx, trace_id, span_id, sampled)
+    }
+
+    fn extract_multi(cx: &Context, extractor: &dyn Extractor) -> Option<Context> {
+        let sampled = extractor.get(B3_FLAGS_HEADER).map(str::trim) == Some("1")
+            || matches!(
+                extractor.get(B3_SAMPLED_HEADER).map(str::trim),
+                None | Some("1") | Some("true")
+            );
+        remote_context(
+            cx,
+            extractor.get(B3_TRACE_ID_HEADER)?.trim(),
+            extractor.get(B3_SPAN_ID_HEADER)?.trim(),
+            sampled,
+        )
+    }
+}
+
+impl TextMapPropagator for B3Propagator {
+    fn inject_context(&self, cx: &Context, injector: &mut dyn Injector) {
+        let span = cx.span();
+        let span_context = span.span_context();
+        if !span_context.is_valid() {
+            return;
+        }
+        let sampled = if span_context.is_sampled() { "1" } else { "0" };
+        if self.multi_header {
+            injector.set(B3_TRACE_ID_HEADER, span_context.trace_id().to_string());
+            injector.set(B3_SPAN_ID_HEADER, span_context.span_id().to_string());
+            injector.set(B3_SAMPLED_HEADER, sampled.to_string());
+        } else {
+            injector.set(
+                B3_SINGLE_HEADER,
+                format!(
+                    "{}-{}-{sampled}",
+                    span_context.trace_id(),
+                    span_context.span_id()
+                ),
+            );
+        }
+    }
+
+    fn extract_with_context(&self, cx: &Context, extractor: &dyn Extractor) -> Context {
+        extractor
+            .get(B3_SINGLE_HEADER)
+            .and_then(|value| Self::extract_single(cx, value))
+            .or_else(|| Self::extract_multi(cx, extractor))
+            .unwrap_or_else(|| cx.clone())
+    }
+
+    fn fields(&self) -> FieldIter<'_> {
+        FieldIter::new(&self.fields)
+    }
+}
+
+#[derive(Debug)]
+pub struct JaegerPropagator {
+    fields: Vec<String>,
+}
+
+impl JaegerPropagator {
+    pub fn new() -> Self {
+        Self {
+            fields: vec![JAEGER_HEADER.to_string()],
+        }
+    }
+
+    // flagsの1bit目がsampled、2bit目がdebug (sampledとして扱う)
+    fn extract(cx: &Context, value: &str) -> Option<Context> {
+        let value = percent_decode_str(value.trim()).decode_utf8().ok()?;
+        let mut parts = value.split(':');
+        let (trace_id, span_id, _parent_span_id, flags) =
+            (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+        if parts.next().is_some() {
+            return None;
+        }
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+        remote_context(cx, trace_id, span_id, flags & 0b11 != 0)
+    }
+}
+
+impl Default for JaegerPropagator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TextMapPropagator for JaegerPropagator {
+    fn inject_context(&self, cx: &Context, injector: &mut dyn Injector) {
+        let span = cx.span();
+        let span_context = span.span_context();
+        if !span_context.is_valid() {
+            return;
+        }
+        injector.set(
+            JAEGER_HEADER,
+            format!(
+                "{}:{}:0:{}",
+                span_context.trace_id(),
+                span_context.span_id(),
+                if span_context.is_sampled() { 1 } else { 0 }
+            ),
+        );
+    }
+
+    fn extract_with_context(&self, cx: &Context, extractor: &dyn Extractor) -> Context {
+        extractor
+            .get(JAEGER_HEADER)
+            .and_then(|value| Self::extract(cx, value))
+            .unwrap_or_else(|| cx.clone())
+    }
+
+    fn fields(&self) -> FieldIter<'_> {
+        FieldIter::new(&self.fields)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+    const SPAN_ID: &str = "00f067aa0ba902b7";
+
+    fn extract(propagator: &dyn TextMapPropagator, headers: &[(&str, &str)]) -> SpanContext {
+        let headers: HashMap<String, String> = headers
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        propagator.extract(&headers).span().span_context().clone()
+    }
+
+    fn inject(propagator: &dyn TextMapPropagator, sampled: bool) -> HashMap<String, String> {
+        let cx = remote_context(&Context::new(), TRACE_ID, SPAN_ID, sampled).unwrap();
+        let mut headers = HashMap::new();
+        propagator.inject_context(&cx, &mut headers);
+        headers
+    }
+
+    #[test]
+    fn test_b3() {
+        let propagator = B3Propagator::new(false);
+        let sc = extract(&propagator, &[("b3", &format!("{TRACE_ID}-{SPAN_ID}-1"))]);
+        assert_eq!(sc.trace_id().to_string(), TRACE_ID);
+        assert_eq!(sc.span_id().to_string(), SPAN_ID);
+        assert!(sc.is_sampled() && sc.is_remote());
+
+        // 64bitのtrace_id、multi header
+        let sc = extract(
+            &propagator,
+            &[
+                ("x-b3-traceid", "a3ce929d0e0e4736"),
+                ("x-b3-spanid", SPAN_ID),
+                ("x-b3-sampled", "0"),
+            ],
+        );
+        assert_eq!(
+            sc.trace_id().to_string(),
+            "0000000000000000a3ce929d0e0e4736"
+        );
+        assert!(!sc.is_sampled());
+
+        assert!(!extract(&propagator, &[("b3", "0")]).is_valid());
+        assert!(!extract(&propagator, &[("b3", "xyz-123")]).is_valid());
+
+        assert_eq!(
+            inject(&propagator, true)["b3"],
+            format!("{TRACE_ID}-{SPAN_ID}-1")
+        );
+        let headers = inject(&B3Propagator::new(true), false);
+        assert_eq!(headers["x-b3-traceid"], TRACE_ID);
+        assert_eq!(headers["x-b3-spanid"], SPAN_ID);
+        assert_eq!(headers["x-b3-sampled"], "0");
+    }
+
+    #[test]
+    fn test_jaeger() {
+        let propagator = JaegerPropagator::new();
+        let sc = extract(
+            &propagator,
+            &[("uber-trace-id", &format!("{TRACE_ID}%3A{SPAN_ID}%3A0%3A1"))],
+        );
+        assert_eq!(sc.trace_id().to_string(), TRACE_ID);
+        assert!(sc.is_sampled());
+        let sc = extract(
+            &propagator,
+            &[("uber-trace-id", "a3ce929d0e0e4736:f067aa0ba902b7:0:0")],
+        );
+        assert_eq!(sc.span_id().to_string(), SPAN_ID);
+        assert!(!sc.is_sampled());
+        assert!(!extract(&propagator, &[("uber-trace-id", "0:0:0:1")]).is_valid());
+
+        assert_eq!(
+            inject(&propagator, true)["uber-trace-id"],
+            format!("{TRACE_ID}:{SPAN_ID}:0:1")
+        );
+    }
+
+    #[test]
+    fn test_composite() {
+        let propagator = build(&[Propagator::TraceContext, Propagator::B3Multi]);
+        let fields: Vec<&str> = propagator.fields().collect();
+        assert!(fields.contains(&"traceparent"));
+        assert!(fields.contains(&"x-b3-traceid"));
+        assert!(!fields.contains(&"uber-trace-id"));
+
+        let sc = extract(
+            &propagator,
+            &[("x-b3-traceid", TRACE_ID), ("x-b3-spanid", SPAN_ID)],
+        );
+        assert_eq!(sc.trace_id().to_string(), TRACE_ID);
+
+        let headers = inject(&propagator, true);
+        assert_eq!(
+            headers["traceparent"],
+            format!("00-{TRACE_ID}-{SPAN_ID}-01")
+        );
+        assert_eq!(headers["x-b3-traceid"], TRACE_ID);
+    }
+}
@@ -0,0 +1,141 @@
+// リクエストID
+// x-request-idヘッダーがあればそれを、なければ生成したものを使い、レスポンスのヘッダーにも返す
+// ハンドラーの中ではtask localに入っているので、ログ (JSONの標準出力、OTLP) にそのまま付く
+//
+// ```ignore
+// let router = router.layer(axum::middleware::from_fn(request_id::middleware));
+//
+// async fn handler(request_id: RequestId) { ... }
+// ```
+use std::convert::Infallible;
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Request},
+    http::{request::Parts, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// 外から来る値なので長すぎるものや制御文字を含むものは使わない
+const MAX_LENGTH: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: RequestId;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    pub fn new() -> Self {
+        Self(uuid::Uuid::new_v4().to_string())
+    }
+
+    // 今処理しているリクエストのID。middlewareの外ならNone
+    pub fn current() -> Option<Self> {
+        REQUEST_ID.try_with(Clone::clone).ok()
+    }
+
+    // middlewareの外 (spawnしたタスクなど) でこのIDを今のリクエストのIDとして実行する
+    pub async fn scope<F: std::future::Future>(self, f: F) -> F::Output {
+        REQUEST_ID.scope(self, f).await
+    }
+
+    fn from_header(value: &HeaderValue) -> Option<Self> {
+        let value = value.to_str().ok()?.trim();
+        (!value.is_empty()
+            && value.len() <= MAX_LENGTH
+            && value.chars().all(|c| c.is_ascii_graphic()))
+        .then(|| Self(value.to_string()))
+    }
+}
+
+impl Default for RequestId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+pub async fn middleware(mut req: Request, next: Next) -> Response {
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(RequestId::from_header)
+        .unwrap_or_default();
+    req.extensions_mut().insert(request_id.clone());
+
+    let mut resp = REQUEST_ID.scope(request_id.clone(), next.run(req)).await;
+    if let Ok(value) = HeaderValue::from_str(&request_id.0) {
+        resp.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    resp
+}
+
+// middlewareを通っていなければここで生成する
+#[async_trait]
+impl<S> FromRequestParts<S> for RequestId
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<RequestId>()
+            .cloned()
+            .or_else(RequestId::current)
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, routing::get, Router};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_request_id() -> anyhow::Result<()> {
+        let router = Router::new()
+            .route(
+                "/",
+                get(|request_id: RequestId| async move {
+                    assert_eq!(RequestId::current(), Some(request_id.clone()));
+                    request_id.0
+                }),
+            )
+            .layer(axum::middleware::from_fn(middleware));
+
+        let resp = router
+            .clone()
+            .oneshot(
+                axum::http::Request::get("/")
+                    .header(REQUEST_ID_HEADER, "abc-123")
+                    .body(Body::empty())?,
+            )
+            .await?;
+        assert_eq!(resp.headers()[REQUEST_ID_HEADER], "abc-123");
+
+        let resp = router
+            .oneshot(
+                axum::http::Request::get("/")
+                    .header(REQUEST_ID_HEADER, "has space")
+                    .body(Body::empty())?,
+            )
+            .await?;
+        let generated = resp.headers()[REQUEST_ID_HEADER].to_str()?;
+        assert_ne!(generated, "has space");
+        assert!(uuid::Uuid::parse_str(generated).is_ok());
+        Ok(())
+    }
+}
@@ -0,0 +1,450 @@
+// ルールベースのサンプラー
+//
+// ルールは上から順に評価して、最初に一致したものの割合でtrace idからサンプルするかを決める
+// どれにも一致しなければ捨てる。サンプルされた親spanやリモートの親がある場合は親の判断に従う (parentbased)
+//
+// HttpTraceLayerのspanのようにルートの時点ではoperation名などが分からないので、
+// サンプルしなかったローカルのルートもRecordOnlyで記録しておき、その直下のspanでもう一度ルールを評価する
+// 直下のspanがサンプルされたら、RootPromotionProcessorでルートもサンプル済みにして送る (親のないspanにならないように)
+//
+// 環境変数では `OTEL_TRACES_SAMPLER=rule_based` で、`OTEL_TRACES_SAMPLER_ARG` に `;` 区切りで書く
+// - `name=<span名>:<割合>`
+// - `<属性名>=<値>:<割合>`: span開始時の属性 (GraphQLなら `graphql.operation.name`)
+// - `error:<割合>`: エラーで終わったspan
+// - `*:<割合>`: 全て
+// 例: `graphql.operation.name=CreateOrder:1;error:1;*:0.01`
+//
+// errorのルールは終了時にしか判定できないので、record_errors (OTEL_TRACES_SAMPLER_RECORD_ERRORS) を
+// 有効にしたときだけ、サンプルしなかったspanもRecordOnlyで記録しておき、ErrorSpanProcessorでエラーになったものだけを送る
+// - サンプルしない全てのspanで属性やイベントを記録するので、*:0.01 のような低い割合でもspanのコストは全部かかる
+// - 送られるのはエラーになったspanだけなので、親のspanはたいてい送られず、トレースの途中のspanになる
+// 無効のときはerrorのルールは使われない (TracingConfig::validateでエラーになる)
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use opentelemetry::{
+    trace::{
+        Link, SamplingDecision, SamplingResult, Span as _, SpanContext, SpanId, SpanKind, Status,
+        TraceContextExt, TraceId, TraceState,
+    },
+    Array, Context, KeyValue, Value,
+};
+use opentelemetry_sdk::{
+    export::trace::SpanData,
+    trace::{Sampler, ShouldSample, Span, SpanProcessor},
+};
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SamplingRule {
+    #[serde(default)]
+    pub span_name: Option<String>,
+    #[serde(default)]
+    pub attribute: Option<String>,
+    #[serde(default)]
+    pub value: Option<String>,
+    #[serde(default)]
+    pub error: bool,
+    pub ratio: f64,
+}
+
+impl SamplingRule {
+    pub fn parse_rules(value: &str) -> anyhow::Result<Vec<Self>> {
+        value
+            .split(';')
+            .map(str::trim)
+            .filter(|x| !x.is_empty())
+            .map(Self::parse)
+            .collect()
+    }
+
+    fn parse(value: &str) -> anyhow::Result<Self> {
+        let (matcher, ratio) = value.rsplit_once(':').ok_or_else(|| {
+            anyhow::anyhow!("invalid sampling rule {value}: expected <match>:<ratio>")
+        })?;
+        let ratio = ratio
+            .trim()
+            .parse()
+            .map_err(|e| anyhow::anyhow!("invalid sampling rule {value}: {e}"))?;
+        let rule = Self {
+            ratio,
+            ..Default::default()
+        };
+        Ok(match matcher.trim() {
+            "*" => rule,
+            "error" => Self {
+                error: true,
+                ..rule
+            },
+            matcher => match matcher.split_once('=') {
+                Some(("name", name)) => Self {
+                    span_name: Some(name.to_string()),
+                    ..rule
+                },
+                Some((key, value)) => Self {
+                    attribute: Some(key.to_string()),
+                    value: Some(value.to_string()),
+                    ..rule
+                },
+                None => anyhow::bail!("invalid sampling rule {value}: unknown matcher {matcher}"),
+            },
+        })
+    }
+
+    // errorのルールはspan開始時には一致しない
+    fn matches(&self, name: &str, attributes: &[KeyValue]) -> bool {
+        if self.error {
+            return false;
+        }
+        if self.span_name.as_ref().is_some_and(|x| x != name) {
+            return false;
+        }
+        match (self.attribute.as_ref(), self.value.as_ref()) {
+            (Some(key), Some(value)) => attributes
+                .iter()
+                .any(|kv| kv.key.as_str() == key && attribute_matches(&kv.value, value)),
+            _ => true,
+        }
+    }
+}
+
+fn attribute_matches(attribute: &Value, value: &str) -> bool {
+    match attribute {
+        Value::Array(Array::String(values)) => values.iter().any(|x| x.as_str() == value),
+        attribute => attribute.as_str() == value,
+    }
+}
+
+fn sample_ratio(ratio: f64, trace_id: TraceId) -> bool {
+    let result = Sampler::TraceIdRatioBased(ratio).should_sample(
+        None,
+        trace_id,
+        "",
+        &SpanKind::Internal,
+        &[],
+        &[],
+    );
+    result.decision == SamplingDecision::RecordAndSample
+}
+
+#[derive(Debug, Clone)]
+pub struct RuleSampler {
+    rules: Vec<SamplingRule>,
+    record_errors: bool,
+}
+
+impl RuleSampler {
+    pub fn new(rules: Vec<SamplingRule>) -> Self {
+        Self {
+            rules,
+            record_errors: false,
+        }
+    }
+
+    pub fn with_record_errors(mut self, record_errors: bool) -> Self {
+        self.record_errors = record_errors;
+        self
+    }
+
+    pub fn error_ratio(&self) -> Option<f64> {
+        if !self.record_errors {
+            return None;
+        }
+        self.rules.iter().find(|x| x.error).map(|x| x.ratio)
+    }
+
+    fn unsampled(&self) -> SamplingDecision {
+        if self.error_ratio().is_some() {
+            SamplingDecision::RecordOnly
+        } else {
+            SamplingDecision::Drop
+        }
+    }
+
+    fn matches(&self, trace_id: TraceId, name: &str, attributes: &[KeyValue]) -> bool {
+        self.rules
+            .iter()
+            .find(|x| x.matches(name, attributes))
+            .is_some_and(|rule| sample_ratio(rule.ratio, trace_id))
+    }
+}
+
+impl ShouldSample for RuleSampler {
+    fn should_sample(
+        &self,
+        parent_context: Option<&Context>,
+        trace_id: TraceId,
+        name: &str,
+        _span_kind: &SpanKind,
+        attributes: &[KeyValue],
+        _links: &[Link],
+    ) -> SamplingResult {
+        let parent = parent_context.filter(|cx| cx.has_active_span());
+        let decision = match parent {
+            Some(cx) if cx.span().span_context().is_sampled() => SamplingDecision::RecordAndSample,
+            // RecordOnlyにしたローカルのルートの直下
+            Some(cx)
+                if !cx.span().span_context().is_remote()
+                    && cx.span().is_recording()
+                    && self.matches(trace_id, name, attributes) =>
+            {
+                SamplingDecision::RecordAndSample
+            }
+            Some(_) => self.unsampled(),
+            None if self.matches(trace_id, name, attributes) => SamplingDecision::RecordAndSample,
+            None => SamplingDecision::RecordOnly,
+        };
+        SamplingResult {
+            decision,
+            attributes: Vec::new(),
+            trace_state: match parent {
+                Some(cx) => cx.span().span_context().trace_state().clone(),
+                None => TraceState::default(),
+            },
+        }
+    }
+}
+
+// 直下のspanがサンプルされたローカルのルートをサンプル済みにして後ろに流す
+// ルートは子より後に終わるので、子の開始時に親のspan idを覚えておく
+#[derive(Debug)]
+pub struct RootPromotionProcessor<P> {
+    inner: P,
+    promoted: Mutex<HashSet<SpanId>>,
+}
+
+impl<P> RootPromotionProcessor<P> {
+    pub fn new(inner: P) -> Self {
+        Self {
+            inner,
+            promoted: Mutex::default(),
+        }
+    }
+}
+
+fn with_sampled(context: &SpanContext) -> SpanContext {
+    SpanContext::new(
+        context.trace_id(),
+        context.span_id(),
+        context.trace_flags().with_sampled(true),
+        context.is_remote(),
+        context.trace_state().clone(),
+    )
+}
+
+impl<P: SpanProcessor> SpanProcessor for RootPromotionProcessor<P> {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        let parent = cx.span();
+        let parent_context = parent.span_context();
+        if span.span_context().is_sampled()
+            && parent_context.is_valid()
+            && !parent_context.is_sampled()
+            && !parent_context.is_remote()
+            && parent.is_recording()
+        {
+            self.promoted
+                .lock()
+                .unwrap()
+                .insert(parent_context.span_id());
+        }
+        self.inner.on_start(span, cx)
+    }
+
+    fn on_end(&self, mut span: SpanData) {
+        if !span.span_context.is_sampled()
+            && self
+                .promoted
+                .lock()
+                .unwrap()
+                .remove(&span.span_context.span_id())
+        {
+            span.span_context = with_sampled(&span.span_context);
+        }
+        self.inner.on_end(span)
+    }
+
+    fn force_flush(&self) -> opentelemetry::trace::TraceResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&self) -> opentelemetry::trace::TraceResult<()> {
+        self.inner.shutdown()
+    }
+
+    fn set_resource(&mut self, resource: &opentelemetry_sdk::Resource) {
+        self.inner.set_resource(resource)
+    }
+}
+
+// サンプルされなかったspanのうち、エラーで終わったものだけをサンプル済みにして後ろに流す
+#[derive(Debug)]
+pub struct ErrorSpanProcessor<P> {
+    inner: P,
+    ratio: f64,
+}
+
+impl<P> ErrorSpanProcessor<P> {
+    pub fn new(inner: P, ratio: f64) -> Self {
+        Self { inner, ratio }
+    }
+}
+
+impl<P: SpanProcessor> SpanProcessor for ErrorSpanProcessor<P> {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        self.inner.on_start(span, cx)
+    }
+
+    fn on_end(&self, mut span: SpanData) {
+        if !span.span_context.is_sampled() {
+            let context = &span.span_context;
+            if !matches!(span.status, Status::Error { .. })
+                || !sample_ratio(self.ratio, context.trace_id())
+            {
+                return;
+            }
+            span.span_context = with_sampled(context);
+        }
+        self.inner.on_end(span)
+    }
+
+    fn force_flush(&self) -> opentelemetry::trace::TraceResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&self) -> opentelemetry::trace::TraceResult<()> {
+        self.inner.shutdown()
+    }
+
+    fn set_resource(&mut self, resource: &opentelemetry_sdk::Resource) {
+        self.inner.set_resource(resource)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{Tracer, TracerProvider as _};
+    use opentelemetry_sdk::trace::{Config, TracerProvider};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug, Clone, Default)]
+    struct Collect(Arc<Mutex<Vec<String>>>);
+
+    impl SpanProcessor for Collect {
+        fn on_start(&self, _span: &mut Span, _cx: &Context) {}
+
+        fn on_end(&self, span: SpanData) {
+            if span.span_context.is_sampled() {
+                self.0.lock().unwrap().push(span.name.to_string());
+            }
+        }
+
+        fn force_flush(&self) -> opentelemetry::trace::TraceResult<()> {
+            Ok(())
+        }
+
+        fn shutdown(&self) -> opentelemetry::trace::TraceResult<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_parse_rules() -> anyhow::Result<()> {
+        let rules = SamplingRule::parse_rules(
+            "graphql.operation.name=CreateOrder:1; error:1;name=health:0;*:0.01",
+        )?;
+        assert_eq!(
+            rules,
+            vec![
+                SamplingRule {
+                    attribute: Some("graphql.operation.name".to_string()),
+                    value: Some("CreateOrder".to_string()),
+                    ratio: 1.0,
+                    ..Default::default()
+                },
+                SamplingRule {
+                    error: true,
+                    ratio: 1.0,
+                    ..Default::default()
+                },
+                SamplingRule {
+                    span_name: Some("health".to_string()),
+                    ratio: 0.0,
+                    ..Default::default()
+                },
+                SamplingRule {
+                    ratio: 0.01,
+                    ..Default::default()
+                },
+            ]
+        );
+        assert!(SamplingRule::parse_rules("foo:1").is_err());
+        assert!(SamplingRule::parse_rules("*").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rule_sampler() -> anyhow::Result<()> {
+        let rules = SamplingRule::parse_rules("graphql.operation.name=CreateOrder:1;error:1;*:0")?;
+        assert_eq!(RuleSampler::new(rules.clone()).error_ratio(), None);
+        let sampler = RuleSampler::new(rules).with_record_errors(true);
+        let collect = Collect::default();
+        let provider = TracerProvider::builder()
+            .with_span_processor(ErrorSpanProcessor::new(
+                collect.clone(),
+                sampler.error_ratio().unwrap(),
+            ))
+            .with_config(Config::default().with_sampler(sampler))
+            .build();
+        let tracer = provider.tracer("test");
+
+        tracer
+            .span_builder("graphql")
+            .with_attributes([KeyValue::new(
+                "graphql.operation.name",
+                Value::Array(Array::String(vec!["CreateOrder".into()])),
+            )])
+            .start(&tracer)
+            .end();
+        tracer.start("ok").end();
+        let mut span = tracer.start("failed");
+        span.set_status(Status::error("boom"));
+        span.end();
+
+        assert_eq!(*collect.0.lock().unwrap(), vec!["graphql", "failed"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_root_promotion() -> anyhow::Result<()> {
+        let sampler = RuleSampler::new(SamplingRule::parse_rules(
+            "graphql.operation.name=CreateOrder:1;*:0",
+        )?);
+        let collect = Collect::default();
+        let provider = TracerProvider::builder()
+            .with_span_processor(RootPromotionProcessor::new(collect.clone()))
+            .with_config(Config::default().with_sampler(sampler))
+            .build();
+        let tracer = provider.tracer("test");
+
+        for operation_name in ["ListOrders", "CreateOrder"] {
+            let root = tracer.start(format!("POST {operation_name}"));
+            let cx = Context::current_with_span(root);
+            let child = tracer
+                .span_builder("graphql")
+                .with_attributes([KeyValue::new("graphql.operation.name", operation_name)])
+                .start_with_context(&tracer, &cx);
+            let child_cx = cx.with_span(child);
+            tracer.start_with_context("db", &child_cx).end();
+            child_cx.span().end();
+            cx.span().end();
+        }
+
+        assert_eq!(
+            *collect.0.lock().unwrap(),
+            vec!["db", "graphql", "POST CreateOrder"]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,617 @@
+// 2つのSDLを比較して、クライアント/ゲートウェイへの影響度で分類する
+// https://the-guild.dev/graphql/inspector/docs/essentials/diff の分類を参考にしている
+// `extend type` は元の型にまとめてから比べる。directiveの定義とschemaのルート型も比べる
+use std::collections::BTreeMap;
+use std::fmt;
+
+use async_graphql::parser::types::{
+    BaseType, DirectiveDefinition, DirectiveLocation, FieldDefinition, InputValueDefinition,
+    SchemaDefinition, Type, TypeDefinition, TypeKind, TypeSystemDefinition,
+};
+use async_graphql::parser::Positioned;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Criticality {
+    Safe,
+    Dangerous,
+    Breaking,
+}
+
+impl fmt::Display for Criticality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Safe => write!(f, "safe"),
+            Self::Dangerous => write!(f, "dangerous"),
+            Self::Breaking => write!(f, "breaking"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Change {
+    pub criticality: Criticality,
+    pub message: String,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}", self.criticality, self.message)
+    }
+}
+
+struct Changes(Vec<Change>);
+
+impl Changes {
+    fn push(&mut self, criticality: Criticality, message: String) {
+        self.0.push(Change {
+            criticality,
+            message,
+        });
+    }
+}
+
+#[derive(Default)]
+struct ParsedSchema {
+    types: BTreeMap<String, TypeDefinition>,
+    directives: BTreeMap<String, DirectiveDefinition>,
+    // query, mutation, subscriptionのルート型
+    roots: [Option<String>; 3],
+}
+
+// 拡張の中身を元の型に足す
+fn merge_extension(base: &mut TypeKind, extension: TypeKind) {
+    match (base, extension) {
+        (TypeKind::Object(base), TypeKind::Object(extension)) => {
+            base.implements.extend(extension.implements);
+            base.fields.extend(extension.fields);
+        }
+        (TypeKind::Interface(base), TypeKind::Interface(extension)) => {
+            base.implements.extend(extension.implements);
+            base.fields.extend(extension.fields);
+        }
+        (TypeKind::Union(base), TypeKind::Union(extension)) => {
+            base.members.extend(extension.members)
+        }
+        (TypeKind::Enum(base), TypeKind::Enum(extension)) => base.values.extend(extension.values),
+        (TypeKind::InputObject(base), TypeKind::InputObject(extension)) => {
+            base.fields.extend(extension.fields)
+        }
+        _ => {}
+    }
+}
+
+fn merge_schema(roots: &mut [Option<String>; 3], schema: SchemaDefinition) {
+    for (root, name) in roots
+        .iter_mut()
+        .zip([schema.query, schema.mutation, schema.subscription])
+    {
+        if let Some(name) = name {
+            *root = Some(name.node.to_string());
+        }
+    }
+}
+
+fn parse(sdl: &str) -> anyhow::Result<ParsedSchema> {
+    let mut schema = ParsedSchema::default();
+    let mut extensions = vec![];
+    let mut has_schema = false;
+    for definition in async_graphql::parser::parse_schema(sdl)?.definitions {
+        match definition {
+            TypeSystemDefinition::Type(ty) if ty.node.extend => extensions.push(ty.node),
+            TypeSystemDefinition::Type(ty) => {
+                schema.types.insert(ty.node.name.node.to_string(), ty.node);
+            }
+            TypeSystemDefinition::Directive(directive) => {
+                schema
+                    .directives
+                    .insert(directive.node.name.node.to_string(), directive.node);
+            }
+            TypeSystemDefinition::Schema(definition) => {
+                has_schema |= !definition.node.extend;
+                merge_schema(&mut schema.roots, definition.node)
+            }
+        }
+    }
+    // 拡張が元の型より前に書かれていてもよい。元の型がないものはそのまま型として扱う
+    for extension in extensions {
+        match schema.types.get_mut(extension.name.node.as_str()) {
+            Some(base) => merge_extension(&mut base.kind, extension.kind),
+            None => {
+                schema
+                    .types
+                    .insert(extension.name.node.to_string(), extension);
+            }
+        }
+    }
+    // schema {} がなければQuery/Mutation/Subscriptionという名前の型がルートになる
+    if !has_schema {
+        for (root, name) in schema
+            .roots
+            .iter_mut()
+            .zip(["Query", "Mutation", "Subscription"])
+        {
+            if root.is_none() && schema.types.contains_key(name) {
+                *root = Some(name.to_string());
+            }
+        }
+    }
+    Ok(schema)
+}
+
+fn kind_name(kind: &TypeKind) -> &'static str {
+    match kind {
+        TypeKind::Scalar => "scalar",
+        TypeKind::Object(_) => "object",
+        TypeKind::Interface(_) => "interface",
+        TypeKind::Union(_) => "union",
+        TypeKind::Enum(_) => "enum",
+        TypeKind::InputObject(_) => "input object",
+    }
+}
+
+// 出力側はnullable -> non-nullの変更なら互換
+fn is_output_compatible(old: &Type, new: &Type) -> bool {
+    if !old.nullable && new.nullable {
+        return false;
+    }
+    match (&old.base, &new.base) {
+        (BaseType::Named(old), BaseType::Named(new)) => old == new,
+        (BaseType::List(old), BaseType::List(new)) => is_output_compatible(old, new),
+        _ => false,
+    }
+}
+
+// 入力側はnon-null -> nullableの変更なら互換
+fn is_input_compatible(old: &Type, new: &Type) -> bool {
+    is_output_compatible(new, old)
+}
+
+fn is_required(value: &InputValueDefinition) -> bool {
+    !value.ty.node.nullable && value.default_value.is_none()
+}
+
+fn names<T>(items: &[Positioned<T>], name: impl Fn(&T) -> String) -> BTreeMap<String, &T> {
+    items.iter().map(|x| (name(&x.node), &x.node)).collect()
+}
+
+fn diff_arguments(
+    changes: &mut Changes,
+    path: &str,
+    old: &[Positioned<InputValueDefinition>],
+    new: &[Positioned<InputValueDefinition>],
+) {
+    let old_args = names(old, |x| x.name.node.to_string());
+    let new_args = names(new, |x| x.name.node.to_string());
+
+    for (name, old_arg) in &old_args {
+        let Some(new_arg) = new_args.get(name) else {
+            changes.push(
+                Criticality::Breaking,
+                format!("argument {path}({name}:) was removed"),
+            );
+            continue;
+        };
+        if old_arg.ty.node != new_arg.ty.node {
+            let criticality = if is_input_compatible(&old_arg.ty.node, &new_arg.ty.node) {
+                Criticality::Safe
+            } else {
+                Criticality::Breaking
+            };
+            changes.push(
+                criticality,
+                format!(
+                    "argument {path}({name}:) changed type from {} to {}",
+                    old_arg.ty.node, new_arg.ty.node
+                ),
+            );
+        }
+        if old_arg.default_value.as_ref().map(|x| &x.node)
+            != new_arg.default_value.as_ref().map(|x| &x.node)
+        {
+            changes.push(
+                Criticality::Dangerous,
+                format!("argument {path}({name}:) changed default value"),
+            );
+        }
+    }
+    for (name, new_arg) in &new_args {
+        if !old_args.contains_key(name) {
+            let criticality = if is_required(new_arg) {
+                Criticality::Breaking
+            } else {
+                Criticality::Safe
+            };
+            changes.push(criticality, format!("argument {path}({name}:) was added"));
+        }
+    }
+}
+
+fn diff_fields(
+    changes: &mut Changes,
+    type_name: &str,
+    old: &[Positioned<FieldDefinition>],
+    new: &[Positioned<FieldDefinition>],
+) {
+    let old_fields = names(old, |x| x.name.node.to_string());
+    let new_fields = names(new, |x| x.name.node.to_string());
+
+    for (name, old_field) in &old_fields {
+        let path = format!("{type_name}.{name}");
+        let Some(new_field) = new_fields.get(name) else {
+            changes.push(Criticality::Breaking, format!("field {path} was removed"));
+            continue;
+        };
+        if old_field.ty.node != new_field.ty.node {
+            let criticality = if is_output_compatible(&old_field.ty.node, &new_field.ty.node) {
+                Criticality::Safe
+            } else {
+                Criticality::Breaking
+            };
+            changes.push(
+                criticality,
+                format!(
+                    "field {path} changed type from {} to {}",
+                    old_field.ty.node, new_field.ty.node
+                ),
+            );
+        }
+        diff_arguments(changes, &path, &old_field.arguments, &new_field.arguments);
+    }
+    for name in new_fields.keys() {
+        if !old_fields.contains_key(name) {
+            changes.push(
+                Criticality::Safe,
+                format!("field {type_name}.{name} was added"),
+            );
+        }
+    }
+}
+
+fn diff_input_fields(
+    changes: &mut Changes,
+    type_name: &str,
+    old: &[Positioned<InputValueDefinition>],
+    new: &[Positioned<InputValueDefinition>],
+) {
+    let old_fields = names(old, |x| x.name.node.to_string());
+    let new_fields = names(new, |x| x.name.node.to_string());
+
+    for (name, old_field) in &old_fields {
+        let Some(new_field) = new_fields.get(name) else {
+            changes.push(
+                Criticality::Breaking,
+                format!("input field {type_name}.{name} was removed"),
+            );
+            continue;
+        };
+        if old_field.ty.node != new_field.ty.node {
+            let criticality = if is_input_compatible(&old_field.ty.node, &new_field.ty.node) {
+                Criticality::Safe
+            } else {
+                Criticality::Breaking
+            };
+            changes.push(
+                criticality,
+                format!(
+                    "input field {type_name}.{name} changed type from {} to {}",
+                    old_field.ty.node, new_field.ty.node
+                ),
+            );
+        }
+    }
+    for (name, new_field) in &new_fields {
+        if !old_fields.contains_key(name) {
+            let criticality = if is_required(new_field) {
+                Criticality::Breaking
+            } else {
+                Criticality::Dangerous
+            };
+            changes.push(
+                criticality,
+                format!("input field {type_name}.{name} was added"),
+            );
+        }
+    }
+}
+
+fn diff_names(
+    changes: &mut Changes,
+    what: &str,
+    type_name: &str,
+    old: Vec<String>,
+    new: Vec<String>,
+    added: Criticality,
+) {
+    for name in &old {
+        if !new.contains(name) {
+            changes.push(
+                Criticality::Breaking,
+                format!("{what} {name} was removed from {type_name}"),
+            );
+        }
+    }
+    for name in &new {
+        if !old.contains(name) {
+            changes.push(added, format!("{what} {name} was added to {type_name}"));
+        }
+    }
+}
+
+fn diff_type(changes: &mut Changes, name: &str, old: &TypeKind, new: &TypeKind) {
+    match (old, new) {
+        (TypeKind::Object(old), TypeKind::Object(new)) => {
+            diff_names(
+                changes,
+                "interface",
+                name,
+                old.implements.iter().map(|x| x.node.to_string()).collect(),
+                new.implements.iter().map(|x| x.node.to_string()).collect(),
+                Criticality::Safe,
+            );
+            diff_fields(changes, name, &old.fields, &new.fields);
+        }
+        (TypeKind::Interface(old), TypeKind::Interface(new)) => {
+            diff_fields(changes, name, &old.fields, &new.fields);
+        }
+        (TypeKind::Union(old), TypeKind::Union(new)) => diff_names(
+            changes,
+            "member",
+            name,
+            old.members.iter().map(|x| x.node.to_string()).collect(),
+            new.members.iter().map(|x| x.node.to_string()).collect(),
+            Criticality::Dangerous,
+        ),
+        (TypeKind::Enum(old), TypeKind::Enum(new)) => diff_names(
+            changes,
+            "enum value",
+            name,
+            old.values
+                .iter()
+                .map(|x| x.node.value.node.to_string())
+                .collect(),
+            new.values
+                .iter()
+                .map(|x| x.node.value.node.to_string())
+                .collect(),
+            Criticality::Dangerous,
+        ),
+        (TypeKind::InputObject(old), TypeKind::InputObject(new)) => {
+            diff_input_fields(changes, name, &old.fields, &new.fields);
+        }
+        (TypeKind::Scalar, TypeKind::Scalar) => {}
+        _ => changes.push(
+            Criticality::Breaking,
+            format!(
+                "type {name} changed kind from {} to {}",
+                kind_name(old),
+                kind_name(new)
+            ),
+        ),
+    }
+}
+
+// FieldDefinition -> FIELD_DEFINITION
+fn location_name(location: &DirectiveLocation) -> String {
+    let mut name = String::new();
+    for (i, c) in format!("{location:?}").chars().enumerate() {
+        if i > 0 && c.is_ascii_uppercase() {
+            name.push('_');
+        }
+        name.push(c.to_ascii_uppercase());
+    }
+    name
+}
+
+fn diff_directive(
+    changes: &mut Changes,
+    name: &str,
+    old: &DirectiveDefinition,
+    new: &DirectiveDefinition,
+) {
+    let path = format!("@{name}");
+    diff_arguments(changes, &path, &old.arguments, &new.arguments);
+    diff_names(
+        changes,
+        "location",
+        &path,
+        old.locations
+            .iter()
+            .map(|x| location_name(&x.node))
+            .collect(),
+        new.locations
+            .iter()
+            .map(|x| location_name(&x.node))
+            .collect(),
+        Criticality::Safe,
+    );
+    // async-graphqlのパーサーはis_repeatableを常にtrueにするので、repeatableは比べない
+}
+
+pub fn diff(old_sdl: &str, new_sdl: &str) -> anyhow::Result<Vec<Change>> {
+    let old = parse(old_sdl)?;
+    let new = parse(new_sdl)?;
+    let mut changes = Changes(vec![]);
+
+    for (name, old_ty) in &old.types {
+        match new.types.get(name) {
+            Some(new_ty) => diff_type(&mut changes, name, &old_ty.kind, &new_ty.kind),
+            None => changes.push(Criticality::Breaking, format!("type {name} was removed")),
+        }
+    }
+    for name in new.types.keys() {
+        if !old.types.contains_key(name) {
+            changes.push(Criticality::Safe, format!("type {name} was added"));
+        }
+    }
+
+    for (name, old_directive) in &old.directives {
+        match new.directives.get(name) {
+            Some(new_directive) => diff_directive(&mut changes, name, old_directive, new_directive),
+            None => changes.push(
+                Criticality::Breaking,
+                format!("directive @{name} was removed"),
+            ),
+        }
+    }
+    for name in new.directives.keys() {
+        if !old.directives.contains_key(name) {
+            changes.push(Criticality::Safe, format!("directive @{name} was added"));
+        }
+    }
+
+    for (operation, (old_root, new_root)) in ["query", "mutation", "subscription"]
+        .iter()
+        .zip(old.roots.iter().zip(&new.roots))
+    {
+        match (old_root, new_root) {
+            (None, Some(new_root)) => changes.push(
+                Criticality::Safe,
+                format!("schema {operation} root {new_root} was added"),
+            ),
+            (Some(old_root), None) => changes.push(
+                Criticality::Breaking,
+                format!("schema {operation} root {old_root} was removed"),
+            ),
+            (Some(old_root), Some(new_root)) if old_root != new_root => changes.push(
+                Criticality::Breaking,
+                format!("schema {operation} root changed from {old_root} to {new_root}"),
+            ),
+            _ => {}
+        }
+    }
+
+    let mut changes = changes.0;
+    changes.sort_by_key(|x| std::cmp::Reverse(x.criticality));
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD: &str = r#"
+        type Query {
+            user(id: ID!): User
+            users(first: Int): [User!]!
+            legacy: String
+        }
+        type User {
+            id: ID!
+            name: String
+        }
+        enum Role { ADMIN USER }
+        input UserInput { name: String! }
+    "#;
+
+    const NEW: &str = r#"
+        type Query {
+            user(id: ID!, withDeleted: Boolean): User
+            users(first: Int, after: String!): [User!]!
+        }
+        type User {
+            id: ID!
+            name: String!
+            email: String
+        }
+        enum Role { ADMIN USER GUEST }
+        input UserInput { name: String, age: Int }
+        scalar Date
+    "#;
+
+    #[test]
+    fn test_diff() -> anyhow::Result<()> {
+        let changes: Vec<String> = diff(OLD, NEW)?.iter().map(|x| x.to_string()).collect();
+        assert_eq!(
+            changes,
+            vec![
+                "[breaking] field Query.legacy was removed",
+                "[breaking] argument Query.users(after:) was added",
+                "[dangerous] enum value GUEST was added to Role",
+                "[dangerous] input field UserInput.age was added",
+                "[safe] argument Query.user(withDeleted:) was added",
+                "[safe] field User.name changed type from String to String!",
+                "[safe] field User.email was added",
+                "[safe] input field UserInput.name changed type from String! to String",
+                "[safe] type Date was added",
+            ]
+        );
+        assert!(diff(NEW, NEW)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_extensions_and_directives() -> anyhow::Result<()> {
+        let old = r#"
+            schema { query: Query }
+            extend type Query { me: User }
+            type Query { user(id: ID!): User }
+            type User { id: ID! }
+            extend type User { name: String }
+            directive @auth(role: String) on FIELD_DEFINITION | OBJECT
+            directive @legacy on FIELD_DEFINITION
+        "#;
+        let new = r#"
+            schema { query: Query mutation: Mutation }
+            type Query { user(id: ID!): User }
+            extend type Query { me: User }
+            type User { id: ID! }
+            type Mutation { noop: Int }
+            directive @auth(role: String!) on FIELD_DEFINITION
+        "#;
+        let changes: Vec<String> = diff(old, new)?.iter().map(|x| x.to_string()).collect();
+        assert_eq!(
+            changes,
+            vec![
+                "[breaking] field User.name was removed",
+                "[breaking] argument @auth(role:) changed type from String to String!",
+                "[breaking] location OBJECT was removed from @auth",
+                "[breaking] directive @legacy was removed",
+                "[safe] type Mutation was added",
+                "[safe] schema mutation root Mutation was added",
+            ]
+        );
+        Ok(())
+    }
+
+    fn changes(old: &str, new: &str) -> anyhow::Result<Vec<String>> {
+        Ok(diff(old, new)?.iter().map(|x| x.to_string()).collect())
+    }
+
+    #[test]
+    fn test_roots() -> anyhow::Result<()> {
+        // schema {} がなければ型の名前でルートが決まる
+        let old = "type Query { a: Int }";
+        let new = "type Query { a: Int } type Mutation { b: Int }";
+        assert_eq!(
+            changes(old, new)?,
+            vec![
+                "[safe] type Mutation was added",
+                "[safe] schema mutation root Mutation was added",
+            ]
+        );
+        assert_eq!(
+            changes(new, old)?,
+            vec![
+                "[breaking] type Mutation was removed",
+                "[breaking] schema mutation root Mutation was removed",
+            ]
+        );
+        // 同じルートを明示しただけなら変更なし
+        assert!(changes(
+            new,
+            &format!("schema {{ query: Query mutation: Mutation }} {new}")
+        )?
+        .is_empty());
+
+        let renamed = "schema { query: Root } type Root { a: Int }";
+        assert_eq!(
+            changes(old, renamed)?,
+            vec![
+                "[breaking] type Query was removed",
+                "[breaking] schema query root changed from Query to Root",
+                "[safe] type Root was added",
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+// Sentryのbreadcrumbと、送る前に値を隠す処理
+//
+// - GraphQLの変数: 名前がdeny-listのキーを含むものは `[Filtered]` にする
+// - SQL: tools/sql.rsのredact_sqlでリテラルを隠したものを残す
+// - breadcrumbの大きさ: ClientOptionsのbefore_breadcrumbにlimitを指定して、messageやdataを切り詰める
+//   (数はmax_breadcrumbs)
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use sentry::protocol::{Breadcrumb, Level};
+use serde_json::Value;
+
+// 名前にこれらを含む変数の値はSentryに送らない。設定 (sensitive_keys) で追加できる
+pub const DEFAULT_SENSITIVE_KEYS: &[&str] = &[
+    "password",
+    "secret",
+    "token",
+    "authorization",
+    "cookie",
+    "apikey",
+    "api_key",
+    "credential",
+];
+
+pub const MAX_MESSAGE_LENGTH: usize = 1024;
+pub const MAX_VALUE_LENGTH: usize = 512;
+pub const MAX_DATA_ENTRIES: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct SensitiveKeys(Vec<String>);
+
+impl SensitiveKeys {
+    pub fn new<S: AsRef<str>>(extra: impl IntoIterator<Item = S>) -> Self {
+        Self(
+            DEFAULT_SENSITIVE_KEYS
+                .iter()
+                .map(|x| x.to_string())
+                .chain(extra.into_iter().map(|x| x.as_ref().to_ascii_lowercase()))
+                .collect(),
+        )
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        let key = key.to_ascii_lowercase();
+        self.0.iter().any(|x| key.contains(x.as_str()))
+    }
+
+    pub fn scrub(&self, value: Value) -> Value {
+        match value {
+            Value::Object(map) => Value::Object(
+                map.into_iter()
+                    .map(|(key, value)| {
+                        let value = if self.contains(&key) {
+                            Value::from("[Filtered]")
+                        } else {
+                            self.scrub(value)
+                        };
+                        (key, value)
+                    })
+                    .collect(),
+            ),
+            Value::Array(values) => {
+                Value::Array(values.into_iter().map(|x| self.scrub(x)).collect())
+            }
+            value => value,
+        }
+    }
+}
+
+impl Default for SensitiveKeys {
+    fn default() -> Self {
+        Self::new(std::iter::empty::<&str>())
+    }
+}
+
+fn truncate(value: &mut String, max: usize) {
+    if value.len() > max {
+        let mut end = max;
+        while !value.is_char_boundary(end) {
+            end -= 1;
+        }
+        value.truncate(end);
+        value.push('…');
+    }
+}
+
+// before_breadcrumbに指定する
+pub fn limit(mut breadcrumb: Breadcrumb) -> Option<Breadcrumb> {
+    if let Some(message) = breadcrumb.message.as_mut() {
+        truncate(message, MAX_MESSAGE_LENGTH);
+    }
+    breadcrumb.data = std::mem::take(&mut breadcrumb.data)
+        .into_iter()
+        .take(MAX_DATA_ENTRIES)
+        .map(|(key, value)| {
+            let value = match value {
+                Value::String(mut s) => {
+                    truncate(&mut s, MAX_VALUE_LENGTH);
+                    Value::String(s)
+                }
+                value @ (Value::Null | Value::Bool(_) | Value::Number(_)) => value,
+                value => {
+                    let mut s = value.to_string();
+                    if s.len() > MAX_VALUE_LENGTH {
+                        truncate(&mut s, MAX_VALUE_LENGTH);
+                        Value::String(s)
+                    } else {
+                        value
+                    }
+                }
+            };
+            (key, value)
+        })
+        .collect();
+    Some(breadcrumb)
+}
+
+// 今のHub (GraphQLのリクエストの中ならリクエストごとのHub) に追加する
+pub fn add(
+    category: &str,
+    ty: &str,
+    message: String,
+    duration: Duration,
+    data: impl IntoIterator<Item = (&'static str, Value)>,
+    failed: bool,
+) {
+    let mut data: BTreeMap<String, Value> = data
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect();
+    data.insert(
+        "duration_ms".to_string(),
+        Value::from(duration.as_secs_f64() * 1000.0),
+    );
+    sentry::add_breadcrumb(Breadcrumb {
+        ty: ty.to_string(),
+        category: Some(category.to_string()),
+        level: if failed { Level::Error } else { Level::Info },
+        message: Some(message),
+        data,
+        ..Default::default()
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scrub() {
+        let keys = SensitiveKeys::new(["ssn"]);
+        let value = keys.scrub(serde_json::json!({
+            "name": "a",
+            "userSSN": "123",
+            "input": {"userPassword": "x", "tokens": ["y"], "list": [{"apiKey": "z"}]},
+        }));
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "name": "a",
+                "userSSN": "[Filtered]",
+                "input": {"userPassword": "[Filtered]", "tokens": "[Filtered]", "list": [{"apiKey": "[Filtered]"}]},
+            })
+        );
+    }
+
+    #[test]
+    fn test_limit() {
+        let breadcrumb = limit(Breadcrumb {
+            message: Some("あ".repeat(MAX_MESSAGE_LENGTH)),
+            data: (0..100)
+                .map(|i| (format!("key{i:03}"), Value::from("x".repeat(1000))))
+                .collect(),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(breadcrumb.message.unwrap().len() <= MAX_MESSAGE_LENGTH + '…'.len_utf8());
+        assert_eq!(breadcrumb.data.len(), MAX_DATA_ENTRIES);
+        assert_eq!(
+            breadcrumb.data["key000"].as_str().unwrap().len(),
+            MAX_VALUE_LENGTH + '…'.len_utf8()
+        );
+    }
+}
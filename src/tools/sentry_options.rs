@@ -0,0 +1,189 @@
+// SentryConfigからClientOptionsを作る
+//
+// - release: 指定がなければ `<crate名>@<version>+<gitのコミット>` (build.rsで埋め込む)
+// - before_send: 登録した順に通す。Noneを返したらそのイベントは送らない
+//   最初にPIIを隠す処理 (scrub_pii) が入っている
+//
+// ```ignore
+// let guard = setup_tracing::setup(config)?;
+// guard.before_send().push(|event| (event.level >= sentry::Level::Warning).then_some(event));
+// ```
+use std::sync::{Arc, RwLock};
+
+use sentry::protocol::{Event, Value};
+use sentry::ClientOptions;
+
+use super::sentry_breadcrumb::{self, SensitiveKeys};
+use super::tracing_config::SentryConfig;
+
+pub const DEFAULT_RELEASE: &str = concat!(
+    env!("CARGO_PKG_NAME"),
+    "@",
+    env!("CARGO_PKG_VERSION"),
+    "+",
+    env!("GIT_SHA")
+);
+
+type Hook = dyn Fn(Event<'static>) -> Option<Event<'static>> + Send + Sync;
+
+// setupの後からでも追加できるように、ClientOptionsにはこれを呼ぶクロージャを渡す
+#[derive(Clone, Default)]
+pub struct BeforeSend(Arc<RwLock<Vec<Arc<Hook>>>>);
+
+impl BeforeSend {
+    pub fn push(
+        &self,
+        hook: impl Fn(Event<'static>) -> Option<Event<'static>> + Send + Sync + 'static,
+    ) {
+        self.0.write().unwrap().push(Arc::new(hook));
+    }
+
+    pub fn apply(&self, event: Event<'static>) -> Option<Event<'static>> {
+        let hooks = self.0.read().unwrap().clone();
+        hooks.iter().try_fold(event, |event, hook| hook(event))
+    }
+}
+
+fn filter_values<'a>(
+    keys: &SensitiveKeys,
+    values: impl Iterator<Item = (&'a String, &'a mut Value)>,
+) {
+    for (key, value) in values {
+        *value = if keys.contains(key) {
+            Value::from("[Filtered]")
+        } else {
+            keys.scrub(std::mem::take(value))
+        };
+    }
+}
+
+// ユーザーはidだけ残し、ヘッダーやextra、contextなどのキーがsensitive_keysに当たるものを隠す
+pub fn scrub_pii(keys: &SensitiveKeys, mut event: Event<'static>) -> Event<'static> {
+    if let Some(user) = event.user.as_mut() {
+        user.email = None;
+        user.ip_address = None;
+        user.username = None;
+        user.other.clear();
+    }
+    if let Some(request) = event.request.as_mut() {
+        request.cookies = None;
+        request.data = None;
+        for (key, value) in request.headers.iter_mut() {
+            if keys.contains(key) {
+                *value = "[Filtered]".to_string();
+            }
+        }
+    }
+    for (key, value) in event.tags.iter_mut() {
+        if keys.contains(key) {
+            *value = "[Filtered]".to_string();
+        }
+    }
+    filter_values(keys, event.extra.iter_mut());
+    for context in event.contexts.values_mut() {
+        if let sentry::protocol::Context::Other(map) = context {
+            filter_values(keys, map.iter_mut());
+        }
+    }
+    for breadcrumb in event.breadcrumbs.values.iter_mut() {
+        filter_values(keys, breadcrumb.data.iter_mut());
+    }
+    event
+}
+
+pub fn client_options(
+    config: &SentryConfig,
+    before_send: BeforeSend,
+) -> anyhow::Result<ClientOptions> {
+    let keys = SensitiveKeys::new(&config.sensitive_keys);
+    let hooks = BeforeSend::default();
+    hooks.push(move |event| Some(scrub_pii(&keys, event)));
+    hooks.push(move |event| before_send.apply(event));
+
+    Ok(ClientOptions {
+        dsn: Some(config.dsn.parse()?),
+        environment: config.environment.clone().map(Into::into),
+        release: Some(
+            config
+                .release
+                .clone()
+                .unwrap_or_else(|| DEFAULT_RELEASE.to_string())
+                .into(),
+        ),
+        server_name: config.server_name.clone().map(Into::into),
+        sample_rate: config.sample_rate,
+        traces_sample_rate: config.traces_sample_rate,
+        attach_stacktrace: config.attach_stacktrace,
+        max_breadcrumbs: config.max_breadcrumbs,
+        before_breadcrumb: Some(Arc::new(sentry_breadcrumb::limit)),
+        before_send: Some(Arc::new(move |event| hooks.apply(event))),
+        ..Default::default()
+    })
+}
+
+// テスト用に、送る代わりにメモリに溜めるtransportでHubを作る
+#[cfg(test)]
+pub fn test_hub(options: ClientOptions) -> (Arc<sentry::test::TestTransport>, Arc<sentry::Hub>) {
+    let transport = sentry::test::TestTransport::new();
+    let client = sentry::Client::from(ClientOptions {
+        transport: Some(Arc::new(transport.clone())),
+        ..options
+    });
+    (
+        transport,
+        Arc::new(sentry::Hub::new(Some(Arc::new(client)), Default::default())),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> SentryConfig {
+        toml::from_str(
+            r#"
+            dsn = "https://public@sentry.invalid/1"
+            environment = "test"
+            server_name = "api-1"
+            sensitive_keys = ["ssn"]
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_client_options() -> anyhow::Result<()> {
+        let before_send = BeforeSend::default();
+        let (transport, hub) = test_hub(client_options(&config(), before_send.clone())?);
+        before_send.push(|event| (event.message.as_deref() != Some("drop")).then_some(event));
+
+        sentry::Hub::run(hub, || {
+            sentry::configure_scope(|scope| {
+                scope.set_user(Some(sentry::User {
+                    id: Some("1".to_string()),
+                    email: Some("a@example.com".to_string()),
+                    ..Default::default()
+                }));
+                scope.set_extra("user_ssn", "123".into());
+                scope.set_tag("session_token", "abc");
+            });
+            sentry::capture_message("hello", sentry::Level::Error);
+            sentry::capture_message("drop", sentry::Level::Error);
+        });
+
+        let events = transport.fetch_and_clear_events();
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert_eq!(event.message.as_deref(), Some("hello"));
+        assert_eq!(event.environment.as_deref(), Some("test"));
+        assert_eq!(event.server_name.as_deref(), Some("api-1"));
+        assert_eq!(event.release.as_deref(), Some(DEFAULT_RELEASE));
+        assert!(DEFAULT_RELEASE.starts_with(concat!(env!("CARGO_PKG_NAME"), "@")));
+        let user = event.user.as_ref().unwrap();
+        assert_eq!(user.id.as_deref(), Some("1"));
+        assert_eq!(user.email, None);
+        assert_eq!(event.extra["user_ssn"], "[Filtered]");
+        assert_eq!(event.tags["session_token"], "[Filtered]");
+        Ok(())
+    }
+}
@@ -8,10 +8,14 @@ pub async fn run(router: Router, port: Option<u16>) -> anyhow::Result<()> {
     info!("server listening {:?}", addr);
 
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, router)
-        .with_graceful_shutdown(shutdown_signal())
-        .await
-        .unwrap();
+    // client.address (tools/http_trace.rs) のために接続元を渡す
+    axum::serve(
+        listener,
+        router.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal())
+    .await
+    .unwrap();
     info!("server shutdown");
 
     #[cfg(feature = "with-sentry")]
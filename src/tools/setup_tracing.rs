@@ -1,135 +1,217 @@
-use opentelemetry::trace::TracerProvider;
-use opentelemetry_otlp::WithExportConfig;
+// tracingのsubscriberを設定する
+// 標準出力のログは常に出し、OpenTelemetry (with-opentelemetry) とSentry (with-sentry) はfeatureがあれば足す
+use std::io::IsTerminal;
+
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, EnvFilter, Layer, Registry};
+
+use super::json_log_format::{JsonFields, JsonFormat};
+use super::log_filter::LogFilter;
+use super::tracing_config::{LogFormat, TracingConfig};
+
+#[cfg(feature = "with-opentelemetry")]
+use super::metrics::PrometheusExporter;
+#[cfg(feature = "with-opentelemetry")]
+use super::otel_setup::OtelProviders;
+
+#[cfg(feature = "with-sentry")]
+use super::sentry_options::{self, BeforeSend};
 
 #[cfg(all(feature = "with-graphql", feature = "with-sentry"))]
 use super::async_graphql_sentry_extension;
 
+#[cfg(all(feature = "with-graphql", feature = "with-opentelemetry"))]
+use super::async_graphql_metrics_extension;
+
 #[cfg(feature = "with-graphql")]
 use async_graphql::SchemaBuilder;
 
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
 pub struct SetupGuard {
+    #[cfg(feature = "with-sentry")]
     sentry_guard: Option<sentry::ClientInitGuard>,
-    provider: Option<opentelemetry_sdk::trace::TracerProvider>,
+    #[cfg(feature = "with-sentry")]
+    before_send: BeforeSend,
+    // GraphQLのextensionの設定 (遅いresolverの閾値、変数で隠すキー) に使う
+    #[cfg(all(feature = "with-graphql", feature = "with-sentry"))]
+    sentry_config: Option<super::tracing_config::SentryConfig>,
+    #[cfg(feature = "with-opentelemetry")]
+    otel: OtelProviders,
+    #[cfg(all(feature = "with-graphql", feature = "with-opentelemetry"))]
+    graphql_operations: Vec<String>,
+    log_filter: LogFilter,
 }
 
 impl SetupGuard {
+    // 実行中にRUST_LOG相当のフィルタを変える
+    pub fn log_filter(&self) -> LogFilter {
+        self.log_filter.clone()
+    }
+
+    // Sentryに送る前にイベントを書き換える・捨てる処理を追加する
+    #[cfg(feature = "with-sentry")]
+    pub fn before_send(&self) -> BeforeSend {
+        self.before_send.clone()
+    }
+
+    // `/metrics` で公開するexporter (ADMIN_TOKENが必要)。prometheusが無効ならNone
+    #[cfg(feature = "with-opentelemetry")]
+    pub fn prometheus_exporter(&self) -> Option<PrometheusExporter> {
+        self.otel.prometheus_exporter.clone()
+    }
+
     #[cfg(feature = "with-graphql")]
     pub fn add_extension<Q, M, S>(
         &self,
         schema_builder: SchemaBuilder<Q, M, S>,
     ) -> SchemaBuilder<Q, M, S> {
-        let schema_builder = if self.sentry_guard.is_some() {
-            schema_builder.extension(async_graphql_sentry_extension::Sentry)
-        } else {
-            schema_builder
+        #[cfg(feature = "with-sentry")]
+        let schema_builder = match self.sentry_config.as_ref() {
+            Some(config) if self.sentry_guard.is_some() => schema_builder.extension(
+                async_graphql_sentry_extension::Sentry::new(std::time::Duration::from_millis(
+                    config.slow_resolver_ms,
+                ))
+                .with_sensitive_keys(&config.sensitive_keys),
+            ),
+            _ => schema_builder,
         };
-        if let Some(provider) = self.provider.as_ref() {
+
+        #[cfg(feature = "with-opentelemetry")]
+        let schema_builder = if let Some(provider) = self.otel.tracer_provider.as_ref() {
+            use opentelemetry::trace::TracerProvider;
+
             schema_builder.extension(async_graphql::extensions::OpenTelemetry::new(
                 provider.tracer("graphql"),
             ))
         } else {
             schema_builder
-        }
+        };
+
+        #[cfg(feature = "with-opentelemetry")]
+        let schema_builder = if self.otel.meter_provider.is_some() {
+            schema_builder.extension(
+                async_graphql_metrics_extension::Metrics::new()
+                    .with_operation_names(&self.graphql_operations),
+            )
+        } else {
+            schema_builder
+        };
+
+        schema_builder
     }
 }
 
+#[cfg(feature = "with-sentry")]
 impl Drop for SetupGuard {
     fn drop(&mut self) {
         self.sentry_guard.take();
-        self.provider.take();
 
         if let Some(client) = sentry::Hub::current().client() {
             client.close(Some(std::time::Duration::from_secs(2)));
         }
+    }
+}
 
-        opentelemetry::global::shutdown_tracer_provider();
+// 標準出力のログ。パイプやファイルに出すときは色をつけない
+fn fmt_layer(format: LogFormat) -> BoxedLayer {
+    let layer = tracing_subscriber::fmt::Layer::new()
+        .with_ansi(std::io::stdout().is_terminal())
+        .with_file(true)
+        .with_line_number(true)
+        .with_level(true);
+    match format {
+        LogFormat::Full => layer.boxed(),
+        LogFormat::Compact => layer.compact().boxed(),
+        LogFormat::Json => layer
+            .with_ansi(false)
+            .fmt_fields(JsonFields)
+            .event_format(JsonFormat)
+            .boxed(),
     }
 }
 
-pub fn setup() -> anyhow::Result<SetupGuard> {
-    let provider = if let Ok(otel_exporter) = std::env::var("OTEL_EXPORTER") {
-        let service_name = if let Ok(service_name) = std::env::var("OTEL_SERVICE_NAME") {
-            service_name
-        } else {
-            std::env::var("HOSTNAME").unwrap_or("not-set".to_string())
-        };
+// SIGHUPで設定 (TRACING_CONFIGのファイル) を読み直してlog_filterを反映する
+// RUST_LOGよりファイルのlog_filterが優先 (TracingConfig::reload_log_filter)
+#[cfg(unix)]
+async fn reload_on_sighup(log_filter: LogFilter) {
+    use tokio::signal::unix::{signal, SignalKind};
 
-        opentelemetry::global::set_text_map_propagator(
-            opentelemetry_sdk::propagation::TraceContextPropagator::new(),
-        );
-
-        let pipeline = opentelemetry_otlp::new_pipeline()
-            .tracing()
-            .with_exporter(
-                opentelemetry_otlp::new_exporter()
-                    .tonic()
-                    .with_endpoint(otel_exporter)
-                    .with_timeout(std::time::Duration::from_secs(5)),
-            )
-            .with_trace_config(
-                opentelemetry_sdk::trace::Config::default()
-                    .with_sampler(opentelemetry_sdk::trace::Sampler::AlwaysOn)
-                    .with_id_generator(opentelemetry_sdk::trace::RandomIdGenerator::default())
-                    .with_max_events_per_span(32)
-                    .with_max_attributes_per_event(16)
-                    .with_resource(opentelemetry_sdk::Resource::new(vec![
-                        opentelemetry::KeyValue::new("service.name", service_name.to_string()),
-                    ])),
-            )
-            .with_batch_config(
-                opentelemetry_sdk::trace::BatchConfigBuilder::default()
-                    .with_scheduled_delay(std::time::Duration::from_secs(10))
-                    .build(),
-            );
+    let Ok(mut hangup) = signal(SignalKind::hangup()) else {
+        tracing::error!("failed to install SIGHUP handler");
+        return;
+    };
+    while hangup.recv().await.is_some() {
+        match TracingConfig::reload_log_filter()
+            .and_then(|directives| log_filter.set_default(&directives))
+        {
+            Ok(()) => tracing::info!(
+                directives = log_filter.status().directives,
+                "log filter reloaded"
+            ),
+            Err(e) => tracing::error!("failed to reload log filter: {e}"),
+        }
+    }
+}
 
-        // install_simpleだと動作しない・・・？
-        // #[cfg(debug_assertions)]
-        // let provider = pipeline.install_simple()?;
+pub fn setup(config: TracingConfig) -> anyhow::Result<SetupGuard> {
+    config.validate()?;
 
-        // #[cfg(not(debug_assertions))]
-        let provider = pipeline.install_batch(opentelemetry_sdk::runtime::Tokio)?;
+    #[cfg(feature = "with-opentelemetry")]
+    let otel = OtelProviders::install(&config)?;
 
-        opentelemetry::global::set_tracer_provider(provider.clone());
+    // 標準出力・Sentry・トレースはRUST_LOG (log_filter) で、OTLPのログはlogs.filterで絞る
+    #[allow(unused_mut)]
+    let mut layers: Vec<BoxedLayer> = vec![fmt_layer(config.log_format)];
 
-        Some(provider)
-    } else {
-        None
-    };
+    #[cfg(feature = "with-opentelemetry")]
+    if let Some(layer) = otel.trace_layer() {
+        layers.push(layer.boxed());
+    }
 
-    {
-        let builder = tracing_subscriber::registry()
-            .with(
-                tracing_subscriber::fmt::Layer::new()
-                    .with_ansi(true)
-                    .with_file(true)
-                    .with_line_number(true)
-                    .with_level(true),
-            )
-            .with(tracing_subscriber::EnvFilter::from_default_env());
+    #[cfg(feature = "with-sentry")]
+    layers.push(sentry_tracing::layer().boxed());
 
-        #[cfg(feature = "with-sentry")]
-        let builder = builder.with(sentry_tracing::layer());
-
-        // TODO: もっときれいにかけないものか
-        if let Some(provider) = provider.as_ref() {
-            builder
-                .with(
-                    tracing_opentelemetry::layer()
-                        .with_tracer(provider.tracer("tracing-otel-subscriber")),
-                )
-                .try_init()?;
-        } else {
-            builder.try_init()?;
-        }
+    let (filter, handle) = reload::Layer::new(EnvFilter::try_new(&config.log_filter)?);
+    let subscriber = tracing_subscriber::registry().with(layers.with_filter(filter));
+
+    #[cfg(feature = "with-opentelemetry")]
+    let subscriber = subscriber.with(otel.log_layer(&config)?);
+
+    subscriber.try_init()?;
+
+    let log_filter = LogFilter::new(handle, config.log_filter.clone());
+    #[cfg(unix)]
+    if tokio::runtime::Handle::try_current().is_ok() {
+        tokio::spawn(reload_on_sighup(log_filter.clone()));
     }
+
+    #[cfg(all(feature = "with-graphql", feature = "with-sentry"))]
+    let sentry_config = config.sentry.clone();
+
+    #[cfg(feature = "with-sentry")]
+    let before_send = BeforeSend::default();
+    #[cfg(feature = "with-sentry")]
+    let sentry_guard = match config.sentry.as_ref() {
+        Some(sentry) => Some(sentry::init(sentry_options::client_options(
+            sentry,
+            before_send.clone(),
+        )?)),
+        None => None,
+    };
+
     Ok(SetupGuard {
-        sentry_guard: if let Ok(sentry_dsn) = std::env::var("SENTRY_DSN") {
-            Some(sentry::init(sentry_dsn))
-        } else {
-            None
-        },
-        provider,
+        #[cfg(feature = "with-sentry")]
+        sentry_guard,
+        #[cfg(feature = "with-sentry")]
+        before_send,
+        #[cfg(all(feature = "with-graphql", feature = "with-sentry"))]
+        sentry_config,
+        #[cfg(feature = "with-opentelemetry")]
+        otel,
+        #[cfg(all(feature = "with-graphql", feature = "with-opentelemetry"))]
+        graphql_operations: config.metrics.graphql_operations.clone(),
+        log_filter,
     })
 }
@@ -0,0 +1,110 @@
+// トレースやログに残すためのSQLの処理
+// sea-ormが作るSQL (`SELECT "users"."id" FROM "users" WHERE ...`) を想定していて、パーサーではない
+
+// 'abc' や 42 を ? にする。識別子の中の数字 (t1など) はそのまま
+pub fn redact_sql(sql: &str) -> String {
+    let mut out = String::with_capacity(sql.len());
+    let mut chars = sql.chars().peekable();
+    let mut prev: Option<char> = None;
+    while let Some(c) = chars.next() {
+        if c == '\'' {
+            // '' はエスケープされたクォート
+            while let Some(c) = chars.next() {
+                if c == '\'' {
+                    if chars.peek() == Some(&'\'') {
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+            }
+            out.push('?');
+            prev = Some('?');
+        } else if c.is_ascii_digit()
+            && !prev.is_some_and(|x| x.is_alphanumeric() || x == '_' || x == '$')
+        {
+            while chars
+                .peek()
+                .is_some_and(|x| x.is_ascii_digit() || *x == '.')
+            {
+                chars.next();
+            }
+            out.push('?');
+            prev = Some('?');
+        } else {
+            out.push(c);
+            prev = Some(c);
+        }
+    }
+    out
+}
+
+// SELECT, INSERT, UPDATE, DELETE など最初のキーワード
+pub fn operation(sql: &str) -> Option<String> {
+    let keyword: String = sql
+        .trim_start()
+        .chars()
+        .take_while(|c| c.is_ascii_alphabetic())
+        .collect();
+    (!keyword.is_empty()).then(|| keyword.to_ascii_uppercase())
+}
+
+// 対象のテーブル。サブクエリなどでわからなければNone
+pub fn table(sql: &str) -> Option<String> {
+    let mut words = sql.split_whitespace();
+    let keyword = match operation(sql)?.as_str() {
+        "SELECT" | "DELETE" => "FROM",
+        "INSERT" => "INTO",
+        "UPDATE" => "UPDATE",
+        _ => return None,
+    };
+    words.find(|x| x.eq_ignore_ascii_case(keyword))?;
+    let table = words.next()?.split('(').next()?;
+    let table: String = table.chars().filter(|c| !matches!(c, '"' | '`')).collect();
+    (!table.is_empty()
+        && table
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '.'))
+    .then_some(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_sql() {
+        assert_eq!(
+            redact_sql("SELECT t1.id FROM t1 WHERE name = 'it''s' AND age > 20 AND id = $1"),
+            "SELECT t1.id FROM t1 WHERE name = ? AND age > ? AND id = $1"
+        );
+    }
+
+    #[test]
+    fn test_operation_and_table() {
+        for (sql, operation_, table_) in [
+            (
+                r#"SELECT "users"."id" FROM "users" WHERE "users"."id" = $1"#,
+                Some("SELECT"),
+                Some("users"),
+            ),
+            (
+                r#"INSERT INTO "public"."users" ("name") VALUES ($1)"#,
+                Some("INSERT"),
+                Some("public.users"),
+            ),
+            (
+                r#"update "users" SET "name" = $1"#,
+                Some("UPDATE"),
+                Some("users"),
+            ),
+            (r#"DELETE FROM "users""#, Some("DELETE"), Some("users")),
+            ("SELECT COUNT(*) FROM (SELECT 1) AS t", Some("SELECT"), None),
+            ("BEGIN", Some("BEGIN"), None),
+            ("", None, None),
+        ] {
+            assert_eq!(operation(sql).as_deref(), operation_, "{sql}");
+            assert_eq!(table(sql).as_deref(), table_, "{sql}");
+        }
+    }
+}
@@ -0,0 +1,155 @@
+// IANAのタイムゾーン名 (Asia/Tokyo) のscalar
+// ローカルの日付や時刻はここから計算する (夏時間があるのでオフセットは時刻ごとに変わる)
+//
+// ```ignore
+// let tz: TimeZone = ...; // ユーザーの設定
+// let today = tz.today();
+// let start = tz.start_of_day(today); // DateTimeOffset
+// let at = tz.localize(&today.and_hms_opt(9, 0, 0).unwrap());
+// ```
+use async_graphql::{InputValueError, InputValueResult, Scalar, ScalarType, Value};
+use chrono::{
+    DateTime, FixedOffset, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, Offset as _,
+    TimeDelta, TimeZone as _, Utc,
+};
+use serde::{Deserialize, Serialize};
+
+use super::date_time_offset::DateTimeOffset;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub struct TimeZone(#[serde(with = "tz_serializer")] pub chrono_tz::Tz);
+
+impl TimeZone {
+    pub fn new(tz: chrono_tz::Tz) -> Self {
+        TimeZone(tz)
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.0.name()
+    }
+
+    pub fn offset_at(&self, t: &DateTime<Utc>) -> FixedOffset {
+        self.0.offset_from_utc_datetime(&t.naive_utc()).fix()
+    }
+
+    pub fn local_date(&self, t: &DateTime<Utc>) -> NaiveDate {
+        t.with_timezone(&self.0).date_naive()
+    }
+
+    pub fn today(&self) -> NaiveDate {
+        self.local_date(&Utc::now())
+    }
+
+    // 夏時間の終わりで2回ある時刻は早い方
+    // 始まりで存在しない時刻は、ずれる前のオフセットで読んで後ろにずらす (02:30 -> 03:30)
+    pub fn localize(&self, t: &NaiveDateTime) -> DateTimeOffset {
+        let dt = match self.0.from_local_datetime(t) {
+            LocalResult::Single(x) => x,
+            LocalResult::Ambiguous(earliest, _) => earliest,
+            LocalResult::None => {
+                let before = self
+                    .0
+                    .offset_from_utc_datetime(&(*t - TimeDelta::days(1)))
+                    .fix();
+                self.0.from_utc_datetime(&(*t - before))
+            }
+        };
+        DateTimeOffset(dt.fixed_offset())
+    }
+
+    pub fn start_of_day(&self, date: NaiveDate) -> DateTimeOffset {
+        self.localize(&date.and_time(NaiveTime::MIN))
+    }
+}
+
+#[Scalar]
+impl ScalarType for TimeZone {
+    fn parse(value: Value) -> InputValueResult<Self> {
+        if let Value::String(value) = &value {
+            value
+                .parse()
+                .map(TimeZone)
+                .map_err(|_| InputValueError::custom(format!("unknown time zone {value}")))
+        } else {
+            Err(InputValueError::expected_type(value))
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        Value::String(self.name().to_string())
+    }
+}
+
+mod tz_serializer {
+    use serde::{de::Error, Deserialize, Deserializer, Serialize as _, Serializer};
+
+    pub fn serialize<S: Serializer>(tz: &chrono_tz::Tz, serializer: S) -> Result<S::Ok, S::Error> {
+        tz.name().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<chrono_tz::Tz, D::Error> {
+        let name: String = Deserialize::deserialize(deserializer)?;
+        name.parse()
+            .map_err(|_| D::Error::custom(format!("unknown time zone {name}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M").unwrap()
+    }
+
+    #[test]
+    fn test_serialize() -> anyhow::Result<()> {
+        let tz = TimeZone::parse(Value::String("Asia/Tokyo".to_string())).unwrap();
+        assert_eq!(tz, TimeZone(chrono_tz::Asia::Tokyo));
+        assert_eq!(tz.to_value(), Value::String("Asia/Tokyo".to_string()));
+        assert_eq!(serde_json::to_string(&tz)?, r#""Asia/Tokyo""#);
+        assert_eq!(serde_json::from_str::<TimeZone>(r#""Asia/Tokyo""#)?, tz);
+
+        assert!(TimeZone::parse(Value::String("Mars/Olympus".to_string())).is_err());
+        assert!(TimeZone::parse(Value::Number(9.into())).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_local() {
+        let tokyo = TimeZone(chrono_tz::Asia::Tokyo);
+        let t = "2024-10-15T20:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        assert_eq!(
+            tokyo.local_date(&t),
+            NaiveDate::from_ymd_opt(2024, 10, 16).unwrap()
+        );
+        assert_eq!(
+            tokyo.offset_at(&t),
+            FixedOffset::east_opt(9 * 3600).unwrap()
+        );
+        assert_eq!(
+            tokyo
+                .start_of_day(NaiveDate::from_ymd_opt(2024, 10, 16).unwrap())
+                .to_value(),
+            Value::String("2024-10-16T00:00:00+09:00".to_string())
+        );
+
+        let ny = TimeZone(chrono_tz::America::New_York);
+        // 夏時間の始まり (02:00 -> 03:00)
+        assert_eq!(
+            ny.localize(&local("2024-03-10T02:30")).to_value(),
+            Value::String("2024-03-10T03:30:00-04:00".to_string())
+        );
+        // 夏時間の終わり (02:00 -> 01:00)
+        assert_eq!(
+            ny.localize(&local("2024-11-03T01:30")).to_value(),
+            Value::String("2024-11-03T01:30:00-04:00".to_string())
+        );
+        assert_eq!(
+            ny.localize(&local("2024-11-03T09:00")).to_value(),
+            Value::String("2024-11-03T09:00:00-05:00".to_string())
+        );
+    }
+}
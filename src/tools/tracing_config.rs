@@ -0,0 +1,1179 @@
+// setup_tracing::setup に渡す設定
+//
+// 環境変数から読む場合(TracingConfig::from_env)
+// - OTEL_EXPORTER_OTLP_ENDPOINT (OTEL_EXPORTER): 未設定ならOpenTelemetryは無効
+// - OTEL_EXPORTER_OTLP_PROTOCOL (grpc, http/protobuf, http/json), OTEL_EXPORTER_OTLP_HEADERS,
+//   OTEL_EXPORTER_OTLP_TIMEOUT, OTEL_EXPORTER_OTLP_COMPRESSION (gzip)
+// - OTEL_EXPORTER_OTLP_CERTIFICATE, OTEL_EXPORTER_OTLP_CLIENT_CERTIFICATE,
+//   OTEL_EXPORTER_OTLP_CLIENT_KEY, OTEL_EXPORTER_OTLP_INSECURE
+// - OTEL_SERVICE_NAME (HOSTNAME), OTEL_RESOURCE_ATTRIBUTES
+// - OTEL_TRACES_SAMPLER, OTEL_TRACES_SAMPLER_ARG: 標準の値に加えて rule_based (tools/sampler.rs)
+//   OTEL_TRACES_SAMPLER_RECORD_ERRORS: rule_basedのerrorのルールを有効にする
+// - OTEL_PROPAGATORS (tracecontext, baggage, b3, b3multi, jaeger, none): デフォルトは tracecontext,baggage
+// - OTEL_BSP_SCHEDULE_DELAY, OTEL_SPAN_EVENT_COUNT_LIMIT, OTEL_SPAN_ATTRIBUTE_COUNT_LIMIT,
+//   OTEL_SPAN_LINK_COUNT_LIMIT, OTEL_EVENT_ATTRIBUTE_COUNT_LIMIT
+// - OTEL_METRICS_EXPORTER (otlp, prometheus, none), OTEL_METRIC_EXPORT_INTERVAL
+// - OTEL_METRICS_GRAPHQL_OPERATIONS: メトリクスにそのまま出すoperation名 (カンマ区切り)。ほかは "other"
+// - OTEL_LOGS_EXPORTER (otlp, none), OTEL_LOGS_FILTER: OTLPに送るログのフィルタ (RUST_LOGと同じ書き方)
+// - LOG_FORMAT (full, compact, json), RUST_LOG: 標準出力のログ
+//   log_filter は実行中にSIGHUPでTRACING_CONFIGのファイルから読み直せる (tools/log_filter.rs)
+// - SENTRY_DSN (未設定ならSentryは無効), SENTRY_ENVIRONMENT, SENTRY_RELEASE (未設定ならversionとgitのコミット),
+//   SENTRY_SAMPLE_RATE, SENTRY_SERVER_NAME, SENTRY_ATTACH_STACKTRACE
+// - SENTRY_TRACES_SAMPLE_RATE: GraphQLのoperationごとのtransactionを送る割合 (0のときは送らない)
+// - SENTRY_SLOW_RESOLVER_MS: これより遅いresolverだけtransactionのspanとbreadcrumbにする
+// - SENTRY_MAX_BREADCRUMBS, SENTRY_SENSITIVE_KEYS: GraphQLの変数で値を隠すキー (カンマ区切りで追加)
+//
+// TOMLの場合はフィールド名そのまま。TRACING_CONFIG のファイルを読むときも、設定されている環境変数で上書きする
+// ```toml
+// service_name = "api"
+// log_format = "compact"
+// propagators = ["tracecontext", "baggage", "b3multi"]
+// log_filter = "info,sqlx=warn"
+//
+// [logs]
+// filter = "warn"
+//
+// [exporter]
+// endpoint = "https://otel-collector:4318"
+// protocol = "http/protobuf"
+// compression = "gzip"
+// headers = { authorization = "Bearer xxx" }
+// tls = { ca_certificate = "/etc/ssl/collector-ca.pem" }
+//
+// [sampler]
+// type = "parent_based_trace_id_ratio"
+// ratio = 0.1
+//
+// # ルールベース
+// # [sampler]
+// # type = "rule_based"
+// # rules = [
+// #     { attribute = "graphql.operation.name", value = "CreateOrder", ratio = 1.0 },
+// #     { error = true, ratio = 1.0 },
+// #     { ratio = 0.01 },
+// # ]
+// # record_errors = true
+// ```
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+#[cfg(feature = "with-opentelemetry")]
+use super::sampler::SamplingRule;
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Protocol {
+    #[serde(rename = "grpc")]
+    Grpc,
+    #[serde(rename = "http/protobuf")]
+    HttpProtobuf,
+    #[serde(rename = "http/json")]
+    HttpJson,
+}
+
+impl Protocol {
+    fn parse(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "grpc" => Ok(Self::Grpc),
+            "http/protobuf" => Ok(Self::HttpProtobuf),
+            "http/json" => Ok(Self::HttpJson),
+            _ => Err(anyhow::anyhow!("unsupported OTLP protocol {value}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    Gzip,
+}
+
+impl Compression {
+    fn parse(value: &str) -> anyhow::Result<Option<Self>> {
+        match value {
+            "gzip" => Ok(Some(Self::Gzip)),
+            "none" => Ok(None),
+            _ => Err(anyhow::anyhow!("unsupported OTLP compression {value}")),
+        }
+    }
+}
+
+// PEMファイルのパス。insecureはhttp/*のみ (証明書の検証をしない)
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    pub ca_certificate: Option<PathBuf>,
+    pub client_certificate: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+    pub insecure: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ExporterConfig {
+    pub endpoint: String,
+    #[serde(default = "ExporterConfig::default_protocol")]
+    pub protocol: Protocol,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default = "ExporterConfig::default_timeout_ms")]
+    pub timeout_ms: u64,
+    #[serde(default = "ExporterConfig::default_scheduled_delay_ms")]
+    pub scheduled_delay_ms: u64,
+    #[serde(default)]
+    pub compression: Option<Compression>,
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+}
+
+impl ExporterConfig {
+    fn default_protocol() -> Protocol {
+        Protocol::Grpc
+    }
+
+    fn default_timeout_ms() -> u64 {
+        5_000
+    }
+
+    fn default_scheduled_delay_ms() -> u64 {
+        10_000
+    }
+
+    pub fn new(endpoint: impl Into<String>) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            protocol: Self::default_protocol(),
+            headers: BTreeMap::new(),
+            timeout_ms: Self::default_timeout_ms(),
+            scheduled_delay_ms: Self::default_scheduled_delay_ms(),
+            compression: None,
+            tls: None,
+        }
+    }
+}
+
+#[cfg(feature = "with-opentelemetry")]
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum SamplerConfig {
+    AlwaysOn,
+    AlwaysOff,
+    TraceIdRatio {
+        ratio: f64,
+    },
+    ParentBasedAlwaysOn,
+    ParentBasedAlwaysOff,
+    ParentBasedTraceIdRatio {
+        ratio: f64,
+    },
+    // errorのルールを使うにはrecord_errorsが必要 (コストはtools/sampler.rs)
+    RuleBased {
+        rules: Vec<SamplingRule>,
+        #[serde(default)]
+        record_errors: bool,
+    },
+}
+
+#[cfg(feature = "with-opentelemetry")]
+impl SamplerConfig {
+    // OTEL_TRACES_SAMPLER, OTEL_TRACES_SAMPLER_ARG
+    fn parse(sampler: &str, arg: Option<&str>) -> anyhow::Result<Self> {
+        let ratio = || -> anyhow::Result<f64> {
+            arg.map(|x| {
+                x.parse()
+                    .map_err(|e| anyhow::anyhow!("invalid OTEL_TRACES_SAMPLER_ARG={x}: {e}"))
+            })
+            .unwrap_or(Ok(1.0))
+        };
+        Ok(match sampler {
+            "always_on" => Self::AlwaysOn,
+            "always_off" => Self::AlwaysOff,
+            "traceidratio" => Self::TraceIdRatio { ratio: ratio()? },
+            "parentbased_always_on" => Self::ParentBasedAlwaysOn,
+            "parentbased_always_off" => Self::ParentBasedAlwaysOff,
+            "parentbased_traceidratio" => Self::ParentBasedTraceIdRatio { ratio: ratio()? },
+            "rule_based" => Self::RuleBased {
+                rules: SamplingRule::parse_rules(arg.ok_or_else(|| {
+                    anyhow::anyhow!("OTEL_TRACES_SAMPLER_ARG is required for rule_based")
+                })?)?,
+                record_errors: false,
+            },
+            _ => anyhow::bail!("unsupported OTEL_TRACES_SAMPLER {sampler}"),
+        })
+    }
+}
+
+#[cfg(feature = "with-opentelemetry")]
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+pub enum Propagator {
+    #[serde(rename = "tracecontext")]
+    TraceContext,
+    #[serde(rename = "baggage")]
+    Baggage,
+    #[serde(rename = "b3")]
+    B3,
+    #[serde(rename = "b3multi")]
+    B3Multi,
+    #[serde(rename = "jaeger")]
+    Jaeger,
+}
+
+#[cfg(feature = "with-opentelemetry")]
+impl Propagator {
+    fn default_list() -> Vec<Self> {
+        vec![Self::TraceContext, Self::Baggage]
+    }
+
+    // OTEL_PROPAGATORS: カンマ区切り、`none` で何も伝播しない
+    fn parse_list(value: &str) -> anyhow::Result<Vec<Self>> {
+        let mut propagators = vec![];
+        for propagator in value.split(',').map(str::trim) {
+            let propagator = match propagator {
+                "tracecontext" => Self::TraceContext,
+                "baggage" => Self::Baggage,
+                "b3" => Self::B3,
+                "b3multi" => Self::B3Multi,
+                "jaeger" => Self::Jaeger,
+                "none" => continue,
+                _ => anyhow::bail!("unsupported OTEL_PROPAGATORS {propagator}"),
+            };
+            if !propagators.contains(&propagator) {
+                propagators.push(propagator);
+            }
+        }
+        Ok(propagators)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpanLimitsConfig {
+    pub max_events_per_span: u32,
+    pub max_attributes_per_span: u32,
+    pub max_links_per_span: u32,
+    pub max_attributes_per_event: u32,
+}
+
+impl Default for SpanLimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_events_per_span: 32,
+            max_attributes_per_span: 128,
+            max_links_per_span: 128,
+            max_attributes_per_event: 16,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    #[default]
+    Full,
+    Compact,
+    Json,
+}
+
+impl LogFormat {
+    fn parse(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "full" => Ok(Self::Full),
+            "compact" => Ok(Self::Compact),
+            "json" => Ok(Self::Json),
+            _ => Err(anyhow::anyhow!("unsupported LOG_FORMAT {value}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SentryConfig {
+    pub dsn: String,
+    #[serde(default)]
+    pub environment: Option<String>,
+    #[serde(default)]
+    pub release: Option<String>,
+    #[serde(default)]
+    pub server_name: Option<String>,
+    #[serde(default = "default_sample_rate")]
+    pub sample_rate: f32,
+    #[serde(default)]
+    pub traces_sample_rate: f32,
+    #[serde(default)]
+    pub attach_stacktrace: bool,
+    #[serde(default = "default_slow_resolver_ms")]
+    pub slow_resolver_ms: u64,
+    #[serde(default = "default_max_breadcrumbs")]
+    pub max_breadcrumbs: usize,
+    #[serde(default)]
+    pub sensitive_keys: Vec<String>,
+}
+
+fn default_sample_rate() -> f32 {
+    1.0
+}
+
+fn default_slow_resolver_ms() -> u64 {
+    100
+}
+
+fn default_max_breadcrumbs() -> usize {
+    100
+}
+
+#[cfg(feature = "with-sentry")]
+impl SentryConfig {
+    pub fn new(dsn: impl Into<String>) -> Self {
+        Self {
+            dsn: dsn.into(),
+            environment: None,
+            release: None,
+            server_name: None,
+            sample_rate: default_sample_rate(),
+            traces_sample_rate: 0.0,
+            attach_stacktrace: false,
+            slow_resolver_ms: default_slow_resolver_ms(),
+            max_breadcrumbs: default_max_breadcrumbs(),
+            sensitive_keys: vec![],
+        }
+    }
+}
+
+// otlpはexporterが設定されている場合のみ有効
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    pub otlp: bool,
+    pub prometheus: bool,
+    pub export_interval_ms: u64,
+    // graphql.operation.nameのラベルに使うoperation名。それ以外は "other" にまとめる
+    pub graphql_operations: Vec<String>,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            otlp: true,
+            prometheus: false,
+            export_interval_ms: 60_000,
+            graphql_operations: vec![],
+        }
+    }
+}
+
+impl MetricsConfig {
+    // OTEL_METRICS_EXPORTER: `otlp,prometheus` のようにカンマ区切り、`none` で無効
+    fn parse_exporters(&mut self, value: &str) -> anyhow::Result<()> {
+        self.otlp = false;
+        self.prometheus = false;
+        for exporter in value.split(',').map(str::trim) {
+            match exporter {
+                "otlp" => self.otlp = true,
+                "prometheus" => self.prometheus = true,
+                "none" => {}
+                _ => anyhow::bail!("unsupported OTEL_METRICS_EXPORTER {exporter}"),
+            }
+        }
+        Ok(())
+    }
+}
+
+// otlpはexporterが設定されている場合のみ有効
+// filterは標準出力 (RUST_LOG) とは別に指定する
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogsConfig {
+    pub otlp: bool,
+    pub filter: String,
+}
+
+impl Default for LogsConfig {
+    fn default() -> Self {
+        Self {
+            otlp: true,
+            filter: "info".to_string(),
+        }
+    }
+}
+
+impl LogsConfig {
+    // OTEL_LOGS_EXPORTER
+    fn parse_exporter(&mut self, value: &str) -> anyhow::Result<()> {
+        self.otlp = match value {
+            "otlp" => true,
+            "none" => false,
+            _ => anyhow::bail!("unsupported OTEL_LOGS_EXPORTER {value}"),
+        };
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TracingConfig {
+    pub service_name: String,
+    #[cfg(feature = "with-opentelemetry")]
+    pub resource_attributes: BTreeMap<String, String>,
+    #[cfg(feature = "with-opentelemetry")]
+    pub exporter: Option<ExporterConfig>,
+    #[cfg(feature = "with-opentelemetry")]
+    pub sampler: SamplerConfig,
+    #[cfg(feature = "with-opentelemetry")]
+    pub propagators: Vec<Propagator>,
+    #[cfg(feature = "with-opentelemetry")]
+    pub span_limits: SpanLimitsConfig,
+    #[cfg(feature = "with-opentelemetry")]
+    pub metrics: MetricsConfig,
+    #[cfg(feature = "with-opentelemetry")]
+    pub logs: LogsConfig,
+    pub log_format: LogFormat,
+    pub log_filter: String,
+    #[cfg(feature = "with-sentry")]
+    pub sentry: Option<SentryConfig>,
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        Self {
+            service_name: "not-set".to_string(),
+            #[cfg(feature = "with-opentelemetry")]
+            resource_attributes: BTreeMap::new(),
+            #[cfg(feature = "with-opentelemetry")]
+            exporter: None,
+            #[cfg(feature = "with-opentelemetry")]
+            sampler: SamplerConfig::AlwaysOn,
+            #[cfg(feature = "with-opentelemetry")]
+            propagators: Propagator::default_list(),
+            #[cfg(feature = "with-opentelemetry")]
+            span_limits: SpanLimitsConfig::default(),
+            #[cfg(feature = "with-opentelemetry")]
+            metrics: MetricsConfig::default(),
+            #[cfg(feature = "with-opentelemetry")]
+            logs: LogsConfig::default(),
+            log_format: LogFormat::default(),
+            log_filter: DEFAULT_LOG_FILTER.to_string(),
+            #[cfg(feature = "with-sentry")]
+            sentry: None,
+        }
+    }
+}
+
+// EnvFilterと同じくerrorのみ。RUST_LOGはapply_envで上書きする
+const DEFAULT_LOG_FILTER: &str = "error";
+
+fn env(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|x| !x.is_empty())
+}
+
+// 環境変数の読み先。テストではmapから読む
+struct Env<'a>(&'a dyn Fn(&str) -> Option<String>);
+
+impl Env<'_> {
+    fn get(&self, name: &str) -> Option<String> {
+        (self.0)(name).filter(|x| !x.is_empty())
+    }
+
+    fn parse<T: std::str::FromStr>(&self, name: &str) -> anyhow::Result<Option<T>>
+    where
+        T::Err: std::fmt::Display,
+    {
+        self.get(name)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|e| anyhow::anyhow!("invalid {name}={value}: {e}"))
+            })
+            .transpose()
+    }
+}
+
+// `key1=value1,key2=value2` 形式 (OTEL_RESOURCE_ATTRIBUTES, OTEL_EXPORTER_OTLP_HEADERS)
+// 値はパーセントエンコードされている (`authorization=Bearer%20xxx`)
+#[cfg(feature = "with-opentelemetry")]
+fn parse_key_values(name: &str, value: &str) -> anyhow::Result<BTreeMap<String, String>> {
+    value
+        .split(',')
+        .filter(|x| !x.trim().is_empty())
+        .map(|pair| {
+            let (k, v) = pair
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("invalid {name}: expected key=value, got {pair}"))?;
+            let v = percent_encoding::percent_decode_str(v.trim())
+                .decode_utf8()
+                .map_err(|e| anyhow::anyhow!("invalid {name}: {e}"))?;
+            Ok((k.trim().to_string(), v.into_owned()))
+        })
+        .collect()
+}
+
+impl TracingConfig {
+    pub fn from_env() -> anyhow::Result<Self> {
+        Self::from_env_with(&Env(&env))
+    }
+
+    fn from_env_with(env: &Env) -> anyhow::Result<Self> {
+        let mut config = Self::default();
+        if let Some(hostname) = env.get("HOSTNAME") {
+            config.service_name = hostname;
+        }
+        config.apply_env(env)?;
+        Ok(config)
+    }
+
+    // 設定されている環境変数だけで上書きする
+    fn apply_env(&mut self, env: &Env) -> anyhow::Result<()> {
+        if let Some(name) = env.get("OTEL_SERVICE_NAME") {
+            self.service_name = name;
+        }
+        if let Some(value) = env.get("LOG_FORMAT") {
+            self.log_format = LogFormat::parse(&value)?;
+        }
+        if let Some(filter) = env.get("RUST_LOG") {
+            self.log_filter = filter;
+        }
+
+        #[cfg(feature = "with-opentelemetry")]
+        self.apply_otel_env(env)?;
+
+        #[cfg(feature = "with-sentry")]
+        self.apply_sentry_env(env)?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "with-sentry")]
+    fn apply_sentry_env(&mut self, env: &Env) -> anyhow::Result<()> {
+        if let Some(dsn) = env.get("SENTRY_DSN") {
+            match self.sentry.as_mut() {
+                Some(sentry) => sentry.dsn = dsn,
+                None => self.sentry = Some(SentryConfig::new(dsn)),
+            }
+        }
+        let Some(sentry) = self.sentry.as_mut() else {
+            return Ok(());
+        };
+        if let Some(environment) = env.get("SENTRY_ENVIRONMENT") {
+            sentry.environment = Some(environment);
+        }
+        if let Some(release) = env.get("SENTRY_RELEASE") {
+            sentry.release = Some(release);
+        }
+        if let Some(server_name) = env.get("SENTRY_SERVER_NAME") {
+            sentry.server_name = Some(server_name);
+        }
+        if let Some(rate) = env.parse("SENTRY_SAMPLE_RATE")? {
+            sentry.sample_rate = rate;
+        }
+        if let Some(attach) = env.parse("SENTRY_ATTACH_STACKTRACE")? {
+            sentry.attach_stacktrace = attach;
+        }
+        if let Some(rate) = env.parse("SENTRY_TRACES_SAMPLE_RATE")? {
+            sentry.traces_sample_rate = rate;
+        }
+        if let Some(ms) = env.parse("SENTRY_SLOW_RESOLVER_MS")? {
+            sentry.slow_resolver_ms = ms;
+        }
+        if let Some(max) = env.parse("SENTRY_MAX_BREADCRUMBS")? {
+            sentry.max_breadcrumbs = max;
+        }
+        if let Some(keys) = env.get("SENTRY_SENSITIVE_KEYS") {
+            sentry.sensitive_keys = keys
+                .split(',')
+                .map(str::trim)
+                .filter(|x| !x.is_empty())
+                .map(String::from)
+                .collect();
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "with-opentelemetry")]
+    fn apply_otel_env(&mut self, env: &Env) -> anyhow::Result<()> {
+        // ファイルのexporterにも環境変数を上書きする
+        if let Some(endpoint) = env
+            .get("OTEL_EXPORTER_OTLP_ENDPOINT")
+            .or_else(|| env.get("OTEL_EXPORTER"))
+        {
+            match self.exporter.as_mut() {
+                Some(exporter) => exporter.endpoint = endpoint,
+                None => self.exporter = Some(ExporterConfig::new(endpoint)),
+            }
+        }
+        if let Some(exporter) = self.exporter.as_mut() {
+            if let Some(protocol) = env.get("OTEL_EXPORTER_OTLP_PROTOCOL") {
+                exporter.protocol = Protocol::parse(&protocol)?;
+            }
+            if let Some(headers) = env.get("OTEL_EXPORTER_OTLP_HEADERS") {
+                exporter.headers = parse_key_values("OTEL_EXPORTER_OTLP_HEADERS", &headers)?;
+            }
+            if let Some(timeout) = env.parse("OTEL_EXPORTER_OTLP_TIMEOUT")? {
+                exporter.timeout_ms = timeout;
+            }
+            if let Some(delay) = env.parse("OTEL_BSP_SCHEDULE_DELAY")? {
+                exporter.scheduled_delay_ms = delay;
+            }
+            if let Some(compression) = env.get("OTEL_EXPORTER_OTLP_COMPRESSION") {
+                exporter.compression = Compression::parse(&compression)?;
+            }
+            let mut tls = exporter.tls.clone().unwrap_or_default();
+            if let Some(path) = env.get("OTEL_EXPORTER_OTLP_CERTIFICATE") {
+                tls.ca_certificate = Some(path.into());
+            }
+            if let Some(path) = env.get("OTEL_EXPORTER_OTLP_CLIENT_CERTIFICATE") {
+                tls.client_certificate = Some(path.into());
+            }
+            if let Some(path) = env.get("OTEL_EXPORTER_OTLP_CLIENT_KEY") {
+                tls.client_key = Some(path.into());
+            }
+            if let Some(insecure) = env.parse("OTEL_EXPORTER_OTLP_INSECURE")? {
+                tls.insecure = insecure;
+            }
+            if tls != TlsConfig::default() {
+                exporter.tls = Some(tls);
+            }
+        }
+
+        if let Some(value) = env.get("OTEL_RESOURCE_ATTRIBUTES") {
+            self.resource_attributes = parse_key_values("OTEL_RESOURCE_ATTRIBUTES", &value)?;
+        }
+        if let Some(sampler) = env.get("OTEL_TRACES_SAMPLER") {
+            self.sampler =
+                SamplerConfig::parse(&sampler, env.get("OTEL_TRACES_SAMPLER_ARG").as_deref())?;
+        }
+        if let Some(enabled) = env.parse("OTEL_TRACES_SAMPLER_RECORD_ERRORS")? {
+            if let SamplerConfig::RuleBased { record_errors, .. } = &mut self.sampler {
+                *record_errors = enabled;
+            }
+        }
+        if let Some(propagators) = env.get("OTEL_PROPAGATORS") {
+            self.propagators = Propagator::parse_list(&propagators)?;
+        }
+
+        let span_limits = &mut self.span_limits;
+        if let Some(limit) = env.parse("OTEL_SPAN_EVENT_COUNT_LIMIT")? {
+            span_limits.max_events_per_span = limit;
+        }
+        if let Some(limit) = env.parse("OTEL_SPAN_ATTRIBUTE_COUNT_LIMIT")? {
+            span_limits.max_attributes_per_span = limit;
+        }
+        if let Some(limit) = env.parse("OTEL_SPAN_LINK_COUNT_LIMIT")? {
+            span_limits.max_links_per_span = limit;
+        }
+        if let Some(limit) = env.parse("OTEL_EVENT_ATTRIBUTE_COUNT_LIMIT")? {
+            span_limits.max_attributes_per_event = limit;
+        }
+
+        if let Some(exporters) = env.get("OTEL_METRICS_EXPORTER") {
+            self.metrics.parse_exporters(&exporters)?;
+        }
+        if let Some(interval) = env.parse("OTEL_METRIC_EXPORT_INTERVAL")? {
+            self.metrics.export_interval_ms = interval;
+        }
+        if let Some(operations) = env.get("OTEL_METRICS_GRAPHQL_OPERATIONS") {
+            self.metrics.graphql_operations = operations
+                .split(',')
+                .map(str::trim)
+                .filter(|x| !x.is_empty())
+                .map(String::from)
+                .collect();
+        }
+
+        if let Some(exporter) = env.get("OTEL_LOGS_EXPORTER") {
+            self.logs.parse_exporter(&exporter)?;
+        }
+        if let Some(filter) = env.get("OTEL_LOGS_FILTER") {
+            self.logs.filter = filter;
+        }
+        Ok(())
+    }
+
+    pub fn from_toml(value: &str) -> anyhow::Result<Self> {
+        Ok(toml::from_str(value)?)
+    }
+
+    // TRACING_CONFIG にTOMLファイルのパスが指定されていればそれに、なければデフォルトに環境変数を上書きする
+    // 検証はsetup_tracing::setupでする
+    pub fn load() -> anyhow::Result<Self> {
+        Self::load_with(&Env(&env))
+    }
+
+    fn load_with(env: &Env) -> anyhow::Result<Self> {
+        match env.get("TRACING_CONFIG") {
+            Some(path) => {
+                let mut config = Self::from_toml(
+                    &std::fs::read_to_string(&path)
+                        .map_err(|e| anyhow::anyhow!("failed to read {path}: {e}"))?,
+                )?;
+                config.apply_env(env)?;
+                Ok(config)
+            }
+            None => Self::from_env_with(env),
+        }
+    }
+
+    // SIGHUPで読み直すlog_filter。起動中のプロセスの環境変数は変わらないので、ここではRUST_LOGで上書きせず
+    // ファイルのlog_filterを優先する (上書きするとファイルを直しても反映されない)
+    // ファイルにlog_filterがなければ起動時と同じ (RUST_LOG、なければデフォルト)
+    pub fn reload_log_filter() -> anyhow::Result<String> {
+        Self::reload_log_filter_with(&Env(&env))
+    }
+
+    fn reload_log_filter_with(env: &Env) -> anyhow::Result<String> {
+        let path = env
+            .get("TRACING_CONFIG")
+            .ok_or_else(|| anyhow::anyhow!("TRACING_CONFIG is not set"))?;
+        let value = std::fs::read_to_string(&path)
+            .map_err(|e| anyhow::anyhow!("failed to read {path}: {e}"))?;
+        if toml::from_str::<toml::Table>(&value)?.contains_key("log_filter") {
+            Ok(Self::from_toml(&value)?.log_filter)
+        } else {
+            Ok(Self::load_with(env)?.log_filter)
+        }
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut errors = vec![];
+
+        if self.service_name.is_empty() {
+            errors.push("service_name must not be empty".to_string());
+        }
+        #[cfg(feature = "with-opentelemetry")]
+        self.validate_otel(&mut errors);
+        if let Err(e) = tracing_subscriber::EnvFilter::try_new(&self.log_filter) {
+            errors.push(format!("log_filter is invalid: {e}"));
+        }
+        #[cfg(feature = "with-sentry")]
+        if let Some(sentry) = self.sentry.as_ref() {
+            if let Err(e) = sentry.dsn.parse::<sentry::types::Dsn>() {
+                errors.push(format!("sentry.dsn is invalid: {e}"));
+            }
+            for (name, rate) in [
+                ("sample_rate", sentry.sample_rate),
+                ("traces_sample_rate", sentry.traces_sample_rate),
+            ] {
+                if !(0.0..=1.0).contains(&rate) {
+                    errors.push(format!("sentry.{name} must be between 0 and 1, got {rate}"));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!(
+                "invalid tracing config: {}",
+                errors.join(", ")
+            ))
+        }
+    }
+
+    #[cfg(feature = "with-opentelemetry")]
+    fn validate_otel(&self, errors: &mut Vec<String>) {
+        if let Some(exporter) = self.exporter.as_ref() {
+            if !(exporter.endpoint.starts_with("http://")
+                || exporter.endpoint.starts_with("https://"))
+            {
+                errors.push(format!(
+                    "exporter.endpoint must start with http:// or https://, got {}",
+                    exporter.endpoint
+                ));
+            }
+            if exporter.timeout_ms == 0 {
+                errors.push("exporter.timeout_ms must be greater than 0".to_string());
+            }
+            for key in exporter.headers.keys() {
+                if key.parse::<http::HeaderName>().is_err() {
+                    errors.push(format!("exporter.headers has invalid header name {key}"));
+                }
+            }
+            if let Some(tls) = exporter.tls.as_ref() {
+                if tls.client_certificate.is_some() != tls.client_key.is_some() {
+                    errors.push(
+                        "exporter.tls.client_certificate and client_key must be set together"
+                            .to_string(),
+                    );
+                }
+                for path in [
+                    &tls.ca_certificate,
+                    &tls.client_certificate,
+                    &tls.client_key,
+                ]
+                .into_iter()
+                .flatten()
+                {
+                    if !path.is_file() {
+                        errors.push(format!("exporter.tls file not found: {}", path.display()));
+                    }
+                }
+                if tls.insecure && exporter.protocol == Protocol::Grpc {
+                    errors.push(
+                        "exporter.tls.insecure is only supported for http protocols".to_string(),
+                    );
+                }
+            }
+        }
+        match &self.sampler {
+            SamplerConfig::TraceIdRatio { ratio }
+            | SamplerConfig::ParentBasedTraceIdRatio { ratio }
+                if !(0.0..=1.0).contains(ratio) =>
+            {
+                errors.push(format!(
+                    "sampler.ratio must be between 0 and 1, got {ratio}"
+                ));
+            }
+            SamplerConfig::RuleBased {
+                rules,
+                record_errors,
+            } => {
+                for (i, rule) in rules.iter().enumerate() {
+                    if !(0.0..=1.0).contains(&rule.ratio) {
+                        errors.push(format!(
+                            "sampler.rules[{i}].ratio must be between 0 and 1, got {}",
+                            rule.ratio
+                        ));
+                    }
+                    if rule.attribute.is_some() != rule.value.is_some() {
+                        errors.push(format!(
+                            "sampler.rules[{i}] must set both attribute and value"
+                        ));
+                    }
+                    if rule.error && (rule.span_name.is_some() || rule.attribute.is_some()) {
+                        errors.push(format!(
+                            "sampler.rules[{i}] cannot combine error with other matchers"
+                        ));
+                    }
+                    if rule.error && !record_errors {
+                        errors.push(format!(
+                            "sampler.rules[{i}] is an error rule and requires sampler.record_errors"
+                        ));
+                    }
+                }
+            }
+            _ => {}
+        }
+        if self.span_limits.max_events_per_span == 0
+            || self.span_limits.max_attributes_per_span == 0
+        {
+            errors.push("span_limits must be greater than 0".to_string());
+        }
+        if self.metrics.export_interval_ms == 0 {
+            errors.push("metrics.export_interval_ms must be greater than 0".to_string());
+        }
+        if let Err(e) = super::otel_log_layer::filter(&self.logs.filter) {
+            errors.push(format!("logs.filter is invalid: {e}"));
+        }
+    }
+}
+
+#[cfg(all(test, feature = "with-opentelemetry", feature = "with-sentry"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_toml() -> anyhow::Result<()> {
+        let config = TracingConfig::from_toml(
+            r#"
+            service_name = "api"
+            log_format = "compact"
+            propagators = ["tracecontext", "b3multi"]
+            resource_attributes = { "deployment.environment" = "staging" }
+
+            [exporter]
+            endpoint = "http://localhost:4317"
+            headers = { authorization = "Bearer xxx" }
+
+            [sampler]
+            type = "trace_id_ratio"
+            ratio = 0.25
+
+            [span_limits]
+            max_events_per_span = 64
+
+            [sentry]
+            dsn = "https://public@sentry.example.com/1"
+            traces_sample_rate = 0.2
+            sensitive_keys = ["ssn"]
+            "#,
+        )?;
+        config.validate()?;
+        assert_eq!(config.service_name, "api");
+        assert_eq!(config.log_format, LogFormat::Compact);
+        assert_eq!(config.sampler, SamplerConfig::TraceIdRatio { ratio: 0.25 });
+        assert_eq!(
+            config.propagators,
+            [Propagator::TraceContext, Propagator::B3Multi]
+        );
+        assert_eq!(config.span_limits.max_events_per_span, 64);
+        assert_eq!(config.span_limits.max_attributes_per_event, 16);
+        let sentry = config.sentry.unwrap();
+        assert_eq!(sentry.traces_sample_rate, 0.2);
+        assert_eq!(sentry.sample_rate, 1.0);
+        assert_eq!(sentry.slow_resolver_ms, 100);
+        assert_eq!(sentry.max_breadcrumbs, 100);
+        assert_eq!(sentry.sensitive_keys, ["ssn"]);
+        let exporter = config.exporter.unwrap();
+        assert_eq!(exporter.protocol, Protocol::Grpc);
+        assert_eq!(exporter.timeout_ms, 5_000);
+        assert_eq!(exporter.headers["authorization"], "Bearer xxx");
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate() -> anyhow::Result<()> {
+        let config = TracingConfig {
+            exporter: Some(ExporterConfig::new("localhost:4317")),
+            sampler: SamplerConfig::TraceIdRatio { ratio: 2.0 },
+            ..Default::default()
+        };
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "invalid tracing config: exporter.endpoint must start with http:// or https://, got localhost:4317, sampler.ratio must be between 0 and 1, got 2"
+        );
+        assert!(TracingConfig::from_toml("unknown = 1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_exporter() -> anyhow::Result<()> {
+        let config = TracingConfig::from_toml(
+            r#"
+            [exporter]
+            endpoint = "https://collector.example.com"
+            protocol = "http/json"
+            compression = "gzip"
+            tls = { insecure = true }
+            "#,
+        )?;
+        config.validate()?;
+        let exporter = config.exporter.unwrap();
+        assert_eq!(exporter.protocol, Protocol::HttpJson);
+        assert_eq!(exporter.compression, Some(Compression::Gzip));
+
+        let config = TracingConfig {
+            exporter: Some(ExporterConfig {
+                tls: Some(TlsConfig {
+                    client_key: Some("/nonexistent/key.pem".into()),
+                    insecure: true,
+                    ..Default::default()
+                }),
+                ..ExporterConfig::new("https://collector.example.com")
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "invalid tracing config: exporter.tls.client_certificate and client_key must be set together, exporter.tls file not found: /nonexistent/key.pem, exporter.tls.insecure is only supported for http protocols"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_metrics_exporters() -> anyhow::Result<()> {
+        let mut metrics = MetricsConfig::default();
+        metrics.parse_exporters("prometheus, otlp")?;
+        assert!(metrics.otlp && metrics.prometheus);
+        metrics.parse_exporters("none")?;
+        assert!(!metrics.otlp && !metrics.prometheus);
+        assert!(metrics.parse_exporters("statsd").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_propagators() -> anyhow::Result<()> {
+        assert_eq!(
+            Propagator::parse_list("tracecontext, baggage,b3,tracecontext")?,
+            [
+                Propagator::TraceContext,
+                Propagator::Baggage,
+                Propagator::B3
+            ]
+        );
+        assert!(Propagator::parse_list("none")?.is_empty());
+        assert!(Propagator::parse_list("xray").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_logs() -> anyhow::Result<()> {
+        let config = TracingConfig::from_toml(
+            r#"
+            [logs]
+            filter = "warn,app=debug"
+            "#,
+        )?;
+        config.validate()?;
+        assert!(config.logs.otlp);
+        assert_eq!(config.logs.filter, "warn,app=debug");
+
+        let config = TracingConfig {
+            logs: LogsConfig {
+                filter: "app=loud".to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(config
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .starts_with("invalid tracing config: logs.filter is invalid"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_sampler() -> anyhow::Result<()> {
+        assert_eq!(
+            SamplerConfig::parse("parentbased_traceidratio", Some("0.1"))?,
+            SamplerConfig::ParentBasedTraceIdRatio { ratio: 0.1 }
+        );
+        assert_eq!(
+            SamplerConfig::parse("traceidratio", None)?,
+            SamplerConfig::TraceIdRatio { ratio: 1.0 }
+        );
+        assert!(SamplerConfig::parse("rule_based", None).is_err());
+        assert!(SamplerConfig::parse("unknown", None).is_err());
+
+        let config = TracingConfig::from_toml(
+            r#"
+            [sampler]
+            type = "rule_based"
+            rules = [
+                { attribute = "graphql.operation.name", value = "CreateOrder", ratio = 1.0 },
+                { error = true, span_name = "x", ratio = 1.0 },
+                { ratio = 1.5 },
+            ]
+            "#,
+        )?;
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "invalid tracing config: sampler.rules[1] cannot combine error with other matchers, sampler.rules[1] is an error rule and requires sampler.record_errors, sampler.rules[2].ratio must be between 0 and 1, got 1.5"
+        );
+
+        let config = TracingConfig::from_toml(
+            r#"
+            [sampler]
+            type = "rule_based"
+            rules = [{ error = true, ratio = 1.0 }, { ratio = 0.1 }]
+            record_errors = true
+            "#,
+        )?;
+        config.validate()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_key_values() -> anyhow::Result<()> {
+        let values = parse_key_values("X", "a=1, b = 2,authorization=Bearer%20xxx%3D")?;
+        assert_eq!(values["a"], "1");
+        assert_eq!(values["b"], "2");
+        assert_eq!(values["authorization"], "Bearer xxx=");
+        assert!(parse_key_values("X", "a").is_err());
+        assert!(parse_key_values("X", "a=%FF").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_env_over_file() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("tracing-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"
+            service_name = "api"
+            log_filter = "info"
+
+            [exporter]
+            endpoint = "https://collector.example.com"
+            protocol = "http/json"
+
+            [sentry]
+            dsn = "https://public@sentry.example.com/1"
+            environment = "staging"
+            "#,
+        )?;
+        let vars = BTreeMap::from([
+            ("TRACING_CONFIG", path.display().to_string()),
+            ("HOSTNAME", "pod-1".to_string()),
+            ("RUST_LOG", "debug".to_string()),
+            ("OTEL_EXPORTER_OTLP_HEADERS", "api-key=a%2Cb".to_string()),
+            ("SENTRY_ENVIRONMENT", "production".to_string()),
+        ]);
+        let lookup = |name: &str| vars.get(name).cloned();
+        let config = TracingConfig::load_with(&Env(&lookup));
+        std::fs::remove_file(&path)?;
+        let config = config?;
+
+        // HOSTNAMEはファイルのservice_nameを上書きしない
+        assert_eq!(config.service_name, "api");
+        assert_eq!(config.log_filter, "debug");
+        let exporter = config.exporter.unwrap();
+        assert_eq!(exporter.endpoint, "https://collector.example.com");
+        assert_eq!(exporter.protocol, Protocol::HttpJson);
+        assert_eq!(exporter.headers["api-key"], "a,b");
+        assert_eq!(
+            config.sentry.unwrap().environment.as_deref(),
+            Some("production")
+        );
+
+        let config = TracingConfig::from_env_with(&Env(&lookup))?;
+        assert_eq!(config.service_name, "pod-1");
+        assert_eq!(config.exporter, None);
+
+        // RUST_LOGがなければプロセスの環境変数にかかわらずerror
+        let lookup = |name: &str| {
+            (name != "RUST_LOG")
+                .then(|| vars.get(name).cloned())
+                .flatten()
+        };
+        assert_eq!(
+            TracingConfig::from_env_with(&Env(&lookup))?.log_filter,
+            "error"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_reload_file_over_env() -> anyhow::Result<()> {
+        use crate::tools::log_filter::LogFilter;
+        use tracing_subscriber::{layer::SubscriberExt, reload, EnvFilter};
+
+        let (layer, handle) = reload::Layer::new(EnvFilter::new("debug"));
+        let _guard = tracing::subscriber::set_default(tracing_subscriber::registry().with(layer));
+        let filter = LogFilter::new(handle, "debug".to_string());
+
+        let path = std::env::temp_dir().join(format!("tracing-reload-{}.toml", std::process::id()));
+        let vars = BTreeMap::from([
+            ("TRACING_CONFIG", path.display().to_string()),
+            ("RUST_LOG", "debug".to_string()),
+        ]);
+        let lookup = |name: &str| vars.get(name).cloned();
+        let reload = |content: &str| -> anyhow::Result<String> {
+            std::fs::write(&path, content)?;
+            let directives = TracingConfig::reload_log_filter_with(&Env(&lookup))?;
+            filter.set_default(&directives)?;
+            Ok(filter.status().directives)
+        };
+
+        let result = (|| -> anyhow::Result<()> {
+            // 起動時はRUST_LOGが優先
+            std::fs::write(&path, r#"log_filter = "info""#)?;
+            assert_eq!(TracingConfig::load_with(&Env(&lookup))?.log_filter, "debug");
+
+            // SIGHUPではファイルの変更が反映される
+            assert_eq!(
+                reload(r#"log_filter = "warn,app=trace""#)?,
+                "warn,app=trace"
+            );
+            assert!(!tracing::enabled!(tracing::Level::INFO));
+            assert_eq!(reload(r#"log_filter = "info""#)?, "info");
+            assert!(tracing::enabled!(tracing::Level::INFO));
+
+            // ファイルで指定しなくなったら起動時と同じ
+            assert_eq!(reload(r#"service_name = "api""#)?, "debug");
+            Ok(())
+        })();
+        std::fs::remove_file(&path)?;
+        result
+    }
+}